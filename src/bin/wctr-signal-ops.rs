@@ -0,0 +1,232 @@
+//! Command line tool to inspect and run a patch, saved as JSON
+//! `Patch` by `Simulator::save_patch`.
+
+use wctr_signal_ops::*;
+use std::io::Write;
+
+const USAGE : &str = "\
+Usage: wctr-signal-ops <patch.json> [options]
+
+Loads the patch and prints the ops with their inputs and outputs.
+
+Options:
+    --ticks <n>             Number of exec ticks to run (default 100)
+    --regs <r1,r2,...>      Print the registers, like 'sin1.out', of each
+                            tick as CSV to stdout. The op table is printed
+                            to stderr then.
+    --wav <file>            Render the ticks to a stereo WAV file
+    --group <name>          Render group written to the WAV file
+                            (default: the first group)
+    --format <16|24|f32>    WAV sample format (default f32)
+    --midi <file.mid>       Play the MIDI file into the render group
+    --control-rate <hz>     exec ticks per second (default 689.0625)
+    --sample-rate <hz>      Audio sample rate (default 44100)
+";
+
+struct Args {
+    patch:          String,
+    ticks:          usize,
+    regs:           Option<Vec<String>>,
+    wav:            Option<String>,
+    group:          Option<String>,
+    format:         WavFormat,
+    midi:           Option<String>,
+    control_rate:   f32,
+    sample_rate:    f32,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        patch:          String::new(),
+        ticks:          100,
+        regs:           None,
+        wav:            None,
+        group:          None,
+        format:         WavFormat::Float32,
+        midi:           None,
+        control_rate:   signals::DEFAULT_CONTROL_RATE,
+        sample_rate:    signals::DEFAULT_SAMPLE_RATE,
+    };
+
+    let mut it = std::env::args().skip(1);
+    while let Some(arg) = it.next() {
+        let mut value = || it.next().ok_or(format!("Missing value for {}", arg));
+        match &arg[..] {
+            "--ticks" =>
+                args.ticks = value()?.parse().map_err(|e| format!("--ticks: {}", e))?,
+            "--regs" =>
+                args.regs = Some(value()?.split(',').map(|r| r.trim().to_string()).collect()),
+            "--wav"   => args.wav   = Some(value()?),
+            "--group" => args.group = Some(value()?),
+            "--midi"  => args.midi  = Some(value()?),
+            "--format" =>
+                args.format = match &value()?[..] {
+                    "16"  => WavFormat::Int16,
+                    "24"  => WavFormat::Int24,
+                    "f32" => WavFormat::Float32,
+                    f     => return Err(format!("Unknown WAV format '{}'", f)),
+                },
+            "--control-rate" =>
+                args.control_rate =
+                    value()?.parse().map_err(|e| format!("--control-rate: {}", e))?,
+            "--sample-rate" =>
+                args.sample_rate =
+                    value()?.parse().map_err(|e| format!("--sample-rate: {}", e))?,
+            "-h" | "--help" => return Err(String::new()),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ if args.patch.is_empty() => args.patch = arg,
+            _ => return Err(format!("Unexpected argument '{}'", arg)),
+        }
+    }
+
+    if args.patch.is_empty() {
+        return Err("No patch file given".to_string());
+    }
+    Ok(args)
+}
+
+fn format_op_in(sim: &Simulator, op_in: &OpIn) -> String {
+    match sim.name_op_in(op_in) {
+        Ok(NamedOpIn { expr: Some(src), regs, .. }) =>
+            format!("Expr({}) {}", src, regs.join(",")),
+        Ok(named) if !named.regs.is_empty() =>
+            format!("{:?} {}", named.op_in, named.regs.join(",")),
+        _ => format!("{:?}", op_in),
+    }
+}
+
+fn print_specs<W: Write>(out: &mut W, sim: &Simulator) -> std::io::Result<()> {
+    for (spec, info, mods) in sim.get_specs().iter() {
+        writeln!(out, "[{}] {} ({}) group={}{}",
+            spec.index, info.name, sim.ops[spec.index].type_name(),
+            info.group.name,
+            if info.does_render { " render" } else { "" })?;
+
+        for (i, port) in spec.inputs.iter().enumerate() {
+            writeln!(out, "    in  {:<12} {:>9.3} .. {:<9.3} {:<4} = {}",
+                port.name, port.min, port.max, port.unit,
+                format_op_in(sim, &spec.input_values[i]))?;
+        }
+        for m in mods.iter() {
+            writeln!(out, "    mod {:<12} += {} * {}",
+                spec.inputs[m.input].name,
+                sim.reg_name(m.source).unwrap_or_else(|_| format!("r{}", m.source)),
+                m.depth)?;
+        }
+        for (i, port) in spec.outputs.iter().enumerate() {
+            writeln!(out, "    out {:<12} {:>9.3} .. {:<9.3} {:<4} reg {}",
+                port.name, port.min, port.max, port.unit, spec.output_regs[i])?;
+        }
+    }
+    Ok(())
+}
+
+fn run(args: &Args) -> Result<(), String> {
+    let text =
+        std::fs::read_to_string(&args.patch)
+            .map_err(|e| format!("Can't read {}: {}", args.patch, e))?;
+    let patch : Patch =
+        serde_json::from_str(&text)
+            .map_err(|e| format!("Can't parse {}: {}", args.patch, e))?;
+
+    let mut sim = Simulator::new();
+    if let Err(errors) = sim.load_patch(&patch, &OpRegistry::new()) {
+        for e in errors.iter() {
+            eprintln!("warning: {}", e);
+        }
+    }
+    sim.set_rates(args.control_rate, args.sample_rate);
+    if let Err(cycle) = sim.update_exec_order() {
+        let names : Vec<&str> = cycle.iter().map(|i| &sim.op_infos[*i].name[..]).collect();
+        eprintln!("warning: feedback cycle between {}", names.join(", "));
+    }
+
+    if args.regs.is_some() {
+        print_specs(&mut std::io::stderr(), &sim).map_err(|e| e.to_string())?;
+    } else {
+        print_specs(&mut std::io::stdout(), &sim).map_err(|e| e.to_string())?;
+    }
+
+    if let Some(regs) = &args.regs {
+        let mut reg_idxs = Vec::with_capacity(regs.len());
+        for r in regs.iter() {
+            reg_idxs.push(sim.resolve_reg(r).map_err(|e| e.to_string())?);
+        }
+
+        let stdout  = std::io::stdout();
+        let mut out = std::io::BufWriter::new(stdout.lock());
+        let io_err  = |e: std::io::Error| e.to_string();
+        writeln!(out, "tick,{}", regs.join(",")).map_err(io_err)?;
+        for tick in 0..args.ticks {
+            sim.exec();
+            let vals : Vec<String> =
+                reg_idxs.iter().map(|r| sim.get_reg(*r).to_string()).collect();
+            writeln!(out, "{},{}", tick, vals.join(",")).map_err(io_err)?;
+        }
+        // The WAV file is rendered from the start again.
+        if args.wav.is_some() {
+            sim.load_patch(&patch, &OpRegistry::new()).ok();
+            sim.set_rates(args.control_rate, args.sample_rate);
+        }
+    }
+
+    if let Some(wav) = &args.wav {
+        let group =
+            match &args.group {
+                Some(name) =>
+                    sim.op_groups.iter().position(|g| &g.name == name)
+                        .ok_or_else(|| format!("Unknown group '{}'", name))?,
+                None => 0,
+            };
+        if group >= sim.op_groups.len() {
+            return Err("The patch has no render groups".to_string());
+        }
+
+        let mut seq =
+            match &args.midi {
+                Some(path) => {
+                    let data =
+                        std::fs::read(path).map_err(|e| format!("Can't read {}: {}", path, e))?;
+                    Some(MidiSequence::from_smf(&data)
+                            .map_err(|e| format!("Can't parse {}: {}", path, e))?)
+                },
+                None => None,
+            };
+
+        let seconds = args.ticks as f64 / f64::from(args.control_rate);
+        let render  = OfflineRender::new(args.sample_rate).with_format(args.format);
+        let sample_rate = args.sample_rate;
+        let output =
+            render.render_with(&mut sim, seconds, |sim, num_samples| {
+                if let Some(seq) = seq.as_mut() {
+                    if let Err(e) = seq.schedule_block(sim, group, num_samples, sample_rate) {
+                        eprintln!("warning: {}", e);
+                    }
+                }
+            });
+
+        let file = std::fs::File::create(wav).map_err(|e| format!("Can't create {}: {}", wav, e))?;
+        let mut file = std::io::BufWriter::new(file);
+        write_wav(&mut file, &output[group], 2, args.sample_rate as u32, args.format)
+            .map_err(|e| format!("Can't write {}: {}", wav, e))?;
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let args =
+        match parse_args() {
+            Ok(args) => args,
+            Err(e) => {
+                if !e.is_empty() { eprintln!("error: {}", e); }
+                eprint!("{}", USAGE);
+                std::process::exit(if e.is_empty() { 0 } else { 2 });
+            },
+        };
+
+    if let Err(e) = run(&args) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
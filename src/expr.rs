@@ -0,0 +1,562 @@
+//! Expressions for `OpIn::Expr`.
+//!
+//! An expression is parsed once and compiled to a small stack program,
+//! an `Expr`. The `Simulator` keeps the expressions of its inputs in an
+//! arena and `OpIn::Expr` refers to them by `ExprId`, so `OpIn` stays
+//! `Copy` and evaluating an expression neither allocates nor locks.
+//!
+//! Syntax, from the lowest to the highest precedence:
+//!
+//! - `cond ? a : b`, where `cond` is true if it is not 0.0
+//! - `<`, `>`, `<=`, `>=`, `==`, `!=`, which result in 1.0 or 0.0
+//! - `+`, `-`
+//! - `*`, `/`
+//! - unary `-`
+//! - numbers, `pi`, registers `r<index>` like `r12`, parentheses and the
+//!   functions `min(a, b)`, `max(a, b)`, `clamp(x, lo, hi)`, `abs(x)`,
+//!   `sin(x)`, `cos(x)` and `pow(x, y)`.
+
+use serde::{Serialize, Deserialize};
+
+/// Maximum stack depth of an expression, deeper nested
+/// expressions are rejected by the parser.
+pub const EXPR_MAX_STACK : usize = 16;
+/// Maximum nesting of parentheses, function calls, `? :` and unary `-`.
+/// Limits the recursion of the parser.
+pub const EXPR_MAX_NESTING : usize = 32;
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub enum ExprError {
+    /// Unexpected character or token at the byte offset.
+    Syntax(usize, String),
+    /// A name that is neither a function, nor could be resolved
+    /// to a register.
+    UnknownName(String),
+    /// The function was called with the wrong number of arguments.
+    BadArgCount(String),
+    /// The expression needs more than `EXPR_MAX_STACK` stack slots
+    /// or is nested deeper than `EXPR_MAX_NESTING`.
+    TooDeep,
+}
+
+impl std::fmt::Display for ExprError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ExprError::Syntax(pos, msg) =>
+                write!(f, "Syntax error at {}: {}", pos, msg),
+            ExprError::UnknownName(name) =>
+                write!(f, "Unknown name '{}' in expression", name),
+            ExprError::BadArgCount(func) =>
+                write!(f, "Wrong number of arguments for '{}'", func),
+            ExprError::TooDeep =>
+                write!(f, "Expression nested too deeply"),
+        }
+    }
+}
+
+impl std::error::Error for ExprError { }
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Instr {
+    Const(f32),
+    Reg(usize),
+    Neg,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Eq,
+    Ne,
+    Min,
+    Max,
+    Pow,
+    Abs,
+    Sin,
+    Cos,
+    Clamp,
+    /// Pops `cond`, `a`, `b` and pushes `a` if `cond` is not 0.0, else `b`.
+    Select,
+}
+
+impl Instr {
+    /// Number of values the instruction pops from the stack.
+    fn arity(self) -> usize {
+        match self {
+            Instr::Const(_) | Instr::Reg(_)             => 0,
+            Instr::Neg | Instr::Abs | Instr::Sin | Instr::Cos => 1,
+            Instr::Clamp | Instr::Select                => 3,
+            _                                           => 2,
+        }
+    }
+}
+
+/// A compiled expression. Two `Expr` are equal if their programs are.
+#[derive(Debug, Clone)]
+pub struct Expr {
+    code: Vec<Instr>,
+    /// Canonical source, which parses to the same `code`.
+    text: String,
+}
+
+impl Expr {
+    /// Parses an expression with registers given as `r<index>`.
+    pub fn parse(src: &str) -> Result<Self, ExprError> {
+        Self::parse_with(src, reg_index)
+    }
+
+    /// Parses an expression and resolves register names with `resolve`.
+    /// `r<index>` names are passed to `resolve` too.
+    pub fn parse_with<F>(src: &str, resolve: F) -> Result<Self, ExprError>
+        where F: FnMut(&str) -> Option<usize> {
+
+        let mut p = Parser {
+            src: src.as_bytes(), pos: 0, code: Vec::new(), resolve, depth: 0
+        };
+        p.ternary()?;
+        if p.peek().is_some() {
+            return Err(p.error("unexpected input after the expression"));
+        }
+        Expr::compile(p.code)
+    }
+
+    fn compile(code: Vec<Instr>) -> Result<Self, ExprError> {
+        let mut depth     = 0;
+        let mut max_depth = 0;
+        for i in code.iter() {
+            depth = depth - i.arity() + 1;
+            max_depth = max_depth.max(depth);
+        }
+        if max_depth > EXPR_MAX_STACK {
+            return Err(ExprError::TooDeep);
+        }
+
+        let text = decompile(&code);
+        Ok(Expr { code, text })
+    }
+
+    pub fn eval(&self, regs: &[f32]) -> f32 {
+        let mut stack = [0.0_f32; EXPR_MAX_STACK];
+        let mut sp    = 0;
+
+        for i in self.code.iter() {
+            let n = i.arity();
+            let a = if n > 0 { stack[sp - n] } else { 0.0 };
+            let b = if n > 1 { stack[sp - n + 1] } else { 0.0 };
+            let c = if n > 2 { stack[sp - n + 2] } else { 0.0 };
+            sp -= n;
+
+            stack[sp] = match i {
+                Instr::Const(v) => *v,
+                Instr::Reg(r)   => regs[*r],
+                Instr::Neg      => -a,
+                Instr::Add      => a + b,
+                Instr::Sub      => a - b,
+                Instr::Mul      => a * b,
+                Instr::Div      => a / b,
+                Instr::Lt       => if a <  b { 1.0 } else { 0.0 },
+                Instr::Gt       => if a >  b { 1.0 } else { 0.0 },
+                Instr::Le       => if a <= b { 1.0 } else { 0.0 },
+                Instr::Ge       => if a >= b { 1.0 } else { 0.0 },
+                Instr::Eq       => if a == b { 1.0 } else { 0.0 },
+                Instr::Ne       => if a != b { 1.0 } else { 0.0 },
+                Instr::Min      => a.min(b),
+                Instr::Max      => a.max(b),
+                Instr::Pow      => a.powf(b),
+                Instr::Abs      => a.abs(),
+                Instr::Sin      => a.sin(),
+                Instr::Cos      => a.cos(),
+                Instr::Clamp    => a.max(b).min(c),
+                Instr::Select   => if a != 0.0 { b } else { c },
+            };
+            sp += 1;
+        }
+
+        stack[0]
+    }
+
+    /// The canonical form of the expression, with registers as `r<index>`.
+    pub fn as_str(&self) -> &str { &self.text }
+
+    pub fn for_each_reg<F>(&self, mut f: F) where F: FnMut(usize) {
+        for i in self.code.iter() {
+            if let Instr::Reg(r) = i { f(*r); }
+        }
+    }
+
+    /// Returns the expression with all register indices mapped through
+    /// `f`, or `None` if `f` could not map one of them. `f` is called
+    /// once for every occurrence of a register. Allocates.
+    pub fn map_regs<F>(&self, mut f: F) -> Option<Self>
+        where F: FnMut(usize) -> Option<usize> {
+
+        let mut code = self.code.clone();
+        for i in code.iter_mut() {
+            if let Instr::Reg(r) = i { *r = f(*r)?; }
+        }
+        Expr::compile(code).ok()
+    }
+}
+
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool { self.code == other.code }
+}
+
+/// Turns the stack program back into an expression string.
+fn decompile(code: &[Instr]) -> String {
+    let mut stack : Vec<String> = Vec::new();
+    for i in code.iter() {
+        let args = stack.split_off(stack.len() - i.arity());
+        let binop = |op: &str| format!("({} {} {})", args[0], op, args[1]);
+        let call  = |f: &str| format!("{}({})", f, args.join(", "));
+
+        stack.push(match i {
+            Instr::Const(v) => format!("{}", v),
+            Instr::Reg(r)   => format!("r{}", r),
+            Instr::Neg      => format!("(-{})", args[0]),
+            Instr::Add      => binop("+"),
+            Instr::Sub      => binop("-"),
+            Instr::Mul      => binop("*"),
+            Instr::Div      => binop("/"),
+            Instr::Lt       => binop("<"),
+            Instr::Gt       => binop(">"),
+            Instr::Le       => binop("<="),
+            Instr::Ge       => binop(">="),
+            Instr::Eq       => binop("=="),
+            Instr::Ne       => binop("!="),
+            Instr::Min      => call("min"),
+            Instr::Max      => call("max"),
+            Instr::Pow      => call("pow"),
+            Instr::Abs      => call("abs"),
+            Instr::Sin      => call("sin"),
+            Instr::Cos      => call("cos"),
+            Instr::Clamp    => call("clamp"),
+            Instr::Select   => format!("({} ? {} : {})", args[0], args[1], args[2]),
+        });
+    }
+    stack.pop().unwrap_or_default()
+}
+
+struct Parser<'a, F: FnMut(&str) -> Option<usize>> {
+    src:     &'a [u8],
+    pos:     usize,
+    code:    Vec<Instr>,
+    resolve: F,
+    depth:   usize,
+}
+
+impl<'a, F: FnMut(&str) -> Option<usize>> Parser<'a, F> {
+    fn skip_ws(&mut self) {
+        while self.pos < self.src.len() && self.src[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_ws();
+        self.src.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, tok: &str) -> bool {
+        self.skip_ws();
+        if self.src[self.pos..].starts_with(tok.as_bytes()) {
+            self.pos += tok.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, tok: &str) -> Result<(), ExprError> {
+        if self.eat(tok) { Ok(()) }
+        else { Err(self.error(&format!("expected '{}'", tok))) }
+    }
+
+    fn error(&self, msg: &str) -> ExprError {
+        ExprError::Syntax(self.pos, msg.to_string())
+    }
+
+    /// Counts a level of recursion, which is undone by `leave`.
+    /// An error ends the parse, so it does not need to be undone then.
+    fn enter(&mut self) -> Result<(), ExprError> {
+        self.depth += 1;
+        if self.depth > EXPR_MAX_NESTING {
+            return Err(ExprError::TooDeep);
+        }
+        Ok(())
+    }
+
+    fn leave(&mut self) { self.depth -= 1; }
+
+    fn ternary(&mut self) -> Result<(), ExprError> {
+        self.enter()?;
+        self.compare()?;
+        if self.eat("?") {
+            self.ternary()?;
+            self.expect(":")?;
+            self.ternary()?;
+            self.code.push(Instr::Select);
+        }
+        self.leave();
+        Ok(())
+    }
+
+    fn compare(&mut self) -> Result<(), ExprError> {
+        self.sum()?;
+        let op =
+            if      self.eat("<=") { Instr::Le }
+            else if self.eat(">=") { Instr::Ge }
+            else if self.eat("==") { Instr::Eq }
+            else if self.eat("!=") { Instr::Ne }
+            else if self.eat("<")  { Instr::Lt }
+            else if self.eat(">")  { Instr::Gt }
+            else { return Ok(()); };
+        self.sum()?;
+        self.code.push(op);
+        Ok(())
+    }
+
+    fn sum(&mut self) -> Result<(), ExprError> {
+        self.product()?;
+        loop {
+            let op =
+                if      self.eat("+") { Instr::Add }
+                else if self.eat("-") { Instr::Sub }
+                else { return Ok(()); };
+            self.product()?;
+            self.code.push(op);
+        }
+    }
+
+    fn product(&mut self) -> Result<(), ExprError> {
+        self.unary()?;
+        loop {
+            let op =
+                if      self.eat("*") { Instr::Mul }
+                else if self.eat("/") { Instr::Div }
+                else { return Ok(()); };
+            self.unary()?;
+            self.code.push(op);
+        }
+    }
+
+    fn unary(&mut self) -> Result<(), ExprError> {
+        if self.eat("-") {
+            self.enter()?;
+            self.unary()?;
+            self.leave();
+            self.code.push(Instr::Neg);
+            Ok(())
+        } else {
+            self.atom()
+        }
+    }
+
+    fn atom(&mut self) -> Result<(), ExprError> {
+        let c = self.peek().ok_or_else(|| self.error("unexpected end"))?;
+
+        if c == b'(' {
+            self.pos += 1;
+            self.ternary()?;
+            return self.expect(")");
+        }
+
+        let start = self.pos;
+        if c.is_ascii_digit() || c == b'.' {
+            while self.pos < self.src.len()
+                  && (self.src[self.pos].is_ascii_digit() || self.src[self.pos] == b'.') {
+                self.pos += 1;
+            }
+            let num = std::str::from_utf8(&self.src[start..self.pos]).unwrap();
+            let v   = num.parse::<f32>().map_err(|_| ExprError::Syntax(start, num.to_string()))?;
+            // The canonical text could not represent an infinite constant.
+            if !v.is_finite() {
+                return Err(ExprError::Syntax(start, "number out of range".to_string()));
+            }
+            self.code.push(Instr::Const(v));
+            return Ok(());
+        }
+
+        if !(c.is_ascii_alphabetic() || c == b'_') {
+            return Err(self.error(&format!("unexpected '{}'", c as char)));
+        }
+        while self.pos < self.src.len()
+              && (self.src[self.pos].is_ascii_alphanumeric()
+                  || self.src[self.pos] == b'_'
+                  || self.src[self.pos] == b'.') {
+            self.pos += 1;
+        }
+        let name = std::str::from_utf8(&self.src[start..self.pos]).unwrap();
+
+        if self.peek() == Some(b'(') {
+            self.pos += 1;
+            let mut argc = 0;
+            if !self.eat(")") {
+                loop {
+                    self.ternary()?;
+                    argc += 1;
+                    if self.eat(")") { break; }
+                    self.expect(",")?;
+                }
+            }
+
+            let instr = match name {
+                "min"   => Instr::Min,
+                "max"   => Instr::Max,
+                "pow"   => Instr::Pow,
+                "abs"   => Instr::Abs,
+                "sin"   => Instr::Sin,
+                "cos"   => Instr::Cos,
+                "clamp" => Instr::Clamp,
+                _       => return Err(ExprError::UnknownName(name.to_string())),
+            };
+            if instr.arity() != argc {
+                return Err(ExprError::BadArgCount(name.to_string()));
+            }
+            self.code.push(instr);
+            return Ok(());
+        }
+
+        if name == "pi" {
+            self.code.push(Instr::Const(std::f32::consts::PI));
+            return Ok(());
+        }
+
+        match (self.resolve)(name) {
+            Some(r) => { self.code.push(Instr::Reg(r)); Ok(()) },
+            None    => Err(ExprError::UnknownName(name.to_string())),
+        }
+    }
+}
+
+/// Resolves register names of the form `r<index>`.
+fn reg_index(name: &str) -> Option<usize> {
+    let num = name.strip_prefix('r')?;
+    if num.is_empty() || !num.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    num.parse().ok()
+}
+
+/// Refers to an `Expr` in the arena of a `Simulator`, see
+/// `Simulator::add_expr`. Only valid for the `Simulator` it came from.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
+pub struct ExprId {
+    index: u32,
+    /// Tells apart the expressions that used the same slot,
+    /// so that an outdated `ExprId` does not find a new expression.
+    gen:   u32,
+}
+
+#[derive(Debug)]
+struct ExprSlot {
+    /// A free slot keeps its expression until the slot is reused.
+    expr: Option<Expr>,
+    gen:  u32,
+    /// Number of op inputs that refer to the expression.
+    refs: u32,
+    live: bool,
+}
+
+/// The expressions of a `Simulator`, counted by the op inputs that
+/// refer to them.
+///
+/// An expression is freed when the last input lets go of it. Its slot
+/// keeps the `Expr` until `insert` reuses the slot and hands it back,
+/// so releasing an expression on the audio thread never frees memory.
+/// Inserting only allocates if more expressions than the reserved
+/// capacity are alive.
+#[derive(Debug, Default)]
+pub(crate) struct ExprArena {
+    slots: Vec<ExprSlot>,
+    /// Indices of the free slots, with room for all slots.
+    free:  Vec<u32>,
+}
+
+impl ExprArena {
+    pub fn with_capacity(capacity: usize) -> Self {
+        ExprArena {
+            slots: Vec::with_capacity(capacity),
+            free:  Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Adds `expr`, which no input refers to yet. Also returns the
+    /// expression that was left in the reused slot, for the caller
+    /// to free.
+    pub fn insert(&mut self, expr: Expr) -> (ExprId, Option<Expr>) {
+        if let Some(index) = self.free.pop() {
+            let slot  = &mut self.slots[index as usize];
+            slot.live = true;
+            slot.refs = 0;
+            let old   = slot.expr.replace(expr);
+            return (ExprId { index, gen: slot.gen }, old);
+        }
+
+        let index = self.slots.len() as u32;
+        self.slots.push(ExprSlot { expr: Some(expr), gen: 0, refs: 0, live: true });
+        self.free.reserve(self.slots.len() - self.free.len());
+        (ExprId { index, gen: 0 }, None)
+    }
+
+    fn slot_mut(&mut self, id: ExprId) -> Option<&mut ExprSlot> {
+        self.slots.get_mut(id.index as usize).filter(|s| s.live && s.gen == id.gen)
+    }
+
+    pub fn get(&self, id: ExprId) -> Option<&Expr> {
+        self.slots.get(id.index as usize)
+            .filter(|s| s.live && s.gen == id.gen)
+            .and_then(|s| s.expr.as_ref())
+    }
+
+    /// Evaluates the expression `id`, 0.0 if there is none.
+    #[inline]
+    pub fn eval(&self, id: ExprId, regs: &[f32]) -> f32 {
+        self.get(id).map_or(0.0, |e| e.eval(regs))
+    }
+
+    /// Counts another input that refers to `id`.
+    pub fn retain(&mut self, id: ExprId) {
+        if let Some(slot) = self.slot_mut(id) {
+            slot.refs += 1;
+        }
+    }
+
+    /// Counts an input less that refers to `id` and frees the
+    /// expression if it was the last one.
+    pub fn release(&mut self, id: ExprId) {
+        if let Some(slot) = self.slot_mut(id) {
+            slot.refs = slot.refs.saturating_sub(1);
+        }
+        self.discard_unused(id);
+    }
+
+    /// Frees the expression `id` if no input refers to it.
+    pub fn discard_unused(&mut self, id: ExprId) {
+        let slot =
+            match self.slot_mut(id) {
+                Some(slot) if slot.refs == 0 => slot,
+                _ => return,
+            };
+        slot.live = false;
+        slot.gen  = slot.gen.wrapping_add(1);
+        self.free.push(id.index);
+    }
+
+    /// Frees all expressions. Existing `ExprId` stay invalid.
+    pub fn clear(&mut self) {
+        self.free.clear();
+        for (i, slot) in self.slots.iter_mut().enumerate() {
+            if slot.live {
+                slot.gen = slot.gen.wrapping_add(1);
+            }
+            slot.live = false;
+            slot.refs = 0;
+            slot.expr = None;
+            self.free.push(i as u32);
+        }
+    }
+}
@@ -1,18 +1,43 @@
 pub mod sample_row;
 pub mod signals;
 pub mod ops;
+pub mod ringbuf;
+pub mod smoothing;
+pub mod patch;
+pub mod registry;
+pub mod midi;
+pub mod wav;
+pub mod offline;
+pub mod expr;
 
 pub use signals::{
     OpIn,
+    NamedOpIn,
+    InputValue,
+    SerializedInputs,
     Op,
     OpPort,
+    PortScale,
     OpIOSpec,
+    Modulation,
     OpInfo,
+    ExecContext,
+    Event,
+    EventKind,
     Simulator,
+    SimulatorError,
     SimulatorUIEvent,
     SimulatorUIInput,
+    InputName,
     SimulatorCommunicator,
     SimulatorCommunicatorEndpoint};
+pub use patch::{Patch, PatchOp};
+pub use registry::{OpRegistry, OpTypeInfo};
+pub use smoothing::{Smoothing, ControlInterp};
+pub use midi::{MidiParser, MidiSequence, MidiError, TimedEvent};
+pub use wav::{write_wav, WavFormat};
+pub use offline::OfflineRender;
+pub use expr::{Expr, ExprId, ExprError};
 
 //#[cfg(test)]
 //mod tests {
@@ -0,0 +1,398 @@
+//! Parsing of raw MIDI 1.0 byte streams and Standard MIDI Files
+//! (format 0 and 1) into `Event`s.
+//!
+//! Note on with velocity 0 becomes `EventKind::NoteOff`. Program change,
+//! system exclusive, system common and meta messages, except for the
+//! tempo, have no `EventKind` and are skipped.
+
+use crate::signals::{Event, EventKind, Simulator, SimulatorError};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum MidiError {
+    /// The data ended in the middle of a chunk or message.
+    UnexpectedEnd,
+    /// The data does not start with an `MThd` chunk.
+    NotAMidiFile,
+    /// Only the formats 0 and 1 are supported.
+    UnsupportedFormat(u16),
+    /// A data byte without a preceding status byte at that byte offset.
+    MissingStatus(usize),
+}
+
+impl std::fmt::Display for MidiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MidiError::UnexpectedEnd =>
+                write!(f, "Unexpected end of MIDI data"),
+            MidiError::NotAMidiFile =>
+                write!(f, "Not a standard MIDI file"),
+            MidiError::UnsupportedFormat(fmt) =>
+                write!(f, "Unsupported MIDI file format {}", fmt),
+            MidiError::MissingStatus(offs) =>
+                write!(f, "MIDI data byte without status at offset {}", offs),
+        }
+    }
+}
+
+impl std::error::Error for MidiError { }
+
+/// Number of data bytes following the `status` byte,
+/// `None` for system exclusive and unknown status bytes.
+fn data_len(status: u8) -> Option<usize> {
+    match status & 0xF0 {
+        0x80 | 0x90 | 0xA0 | 0xB0 | 0xE0 => Some(2),
+        0xC0 | 0xD0                      => Some(1),
+        _ => match status {
+            0xF1 | 0xF3               => Some(1),
+            0xF2                      => Some(2),
+            0xF6 | 0xF8..=0xFF        => Some(0),
+            _                         => None,
+        },
+    }
+}
+
+/// Converts a complete message into an `EventKind`.
+fn event_kind(status: u8, d1: u8, d2: u8) -> Option<EventKind> {
+    let channel = status & 0x0F;
+    let norm    = |v: u8| f32::from(v) / 127.0;
+
+    match status & 0xF0 {
+        0x80 => Some(EventKind::NoteOff { channel, note: d1, velocity: norm(d2) }),
+        0x90 if d2 == 0 =>
+            Some(EventKind::NoteOff { channel, note: d1, velocity: 0.0 }),
+        0x90 => Some(EventKind::NoteOn { channel, note: d1, velocity: norm(d2) }),
+        0xA0 => Some(EventKind::PolyAftertouch { channel, note: d1, value: norm(d2) }),
+        0xB0 => Some(EventKind::ControlChange { channel, controller: d1, value: norm(d2) }),
+        0xD0 => Some(EventKind::Aftertouch { channel, value: norm(d1) }),
+        0xE0 => {
+            let bend = ((i32::from(d2) << 7) | i32::from(d1)) - 8192;
+            Some(EventKind::PitchBend { channel, value: bend as f32 / 8192.0 })
+        },
+        _ => match status {
+            0xF8 => Some(EventKind::Clock),
+            0xFA => Some(EventKind::Start),
+            0xFB => Some(EventKind::Continue),
+            0xFC => Some(EventKind::Stop),
+            _    => None,
+        },
+    }
+}
+
+/// Parser for a raw MIDI 1.0 byte stream, as received from a MIDI port.
+/// Handles running status, real time messages between the bytes of other
+/// messages and skips system exclusive messages. The state is kept
+/// between calls, so messages may be split across buffers.
+#[derive(Debug, Clone, Default)]
+pub struct MidiParser {
+    status:     Option<u8>,
+    data:       [u8; 2],
+    data_pos:   usize,
+    in_sysex:   bool,
+}
+
+impl MidiParser {
+    pub fn new() -> Self { Self::default() }
+
+    /// Feeds one byte, returns an `Event` if it completed a message
+    /// that has an `EventKind`.
+    pub fn feed(&mut self, byte: u8) -> Option<Event> {
+        if byte >= 0xF8 {
+            // Real time messages may appear anywhere.
+            return event_kind(byte, 0, 0).map(Event::new);
+        }
+
+        if byte & 0x80 != 0 {
+            self.in_sysex = byte == 0xF0;
+            self.data_pos = 0;
+            self.status   = data_len(byte).map(|_| byte);
+        } else {
+            if self.in_sysex || self.status.is_none() { return None; }
+            self.data[self.data_pos.min(1)] = byte;
+            self.data_pos += 1;
+        }
+
+        let status = self.status?;
+        let len    = data_len(status)?;
+        if self.data_pos < len { return None; }
+
+        self.data_pos = 0;
+        // System common messages have no running status.
+        if status >= 0xF0 {
+            self.status = None;
+        }
+        event_kind(status, self.data[0], self.data[1]).map(Event::new)
+    }
+
+    /// Feeds all `bytes` and calls `f` for each parsed `Event`.
+    pub fn parse<F: FnMut(Event)>(&mut self, bytes: &[u8], mut f: F) {
+        for b in bytes.iter() {
+            if let Some(ev) = self.feed(*b) {
+                f(ev);
+            }
+        }
+    }
+}
+
+/// An `Event` at an absolute time, as read from a MIDI file.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct TimedEvent {
+    /// Time in seconds from the start of the file.
+    pub time:   f64,
+    /// Index of the track the event was read from.
+    pub track:  usize,
+    pub event:  Event,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos:  usize,
+}
+
+impl<'a> Reader<'a> {
+    fn u8(&mut self) -> Result<u8, MidiError> {
+        let b = *self.data.get(self.pos).ok_or(MidiError::UnexpectedEnd)?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn u16(&mut self) -> Result<u16, MidiError> {
+        Ok((u16::from(self.u8()?) << 8) | u16::from(self.u8()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, MidiError> {
+        Ok((u32::from(self.u16()?) << 16) | u32::from(self.u16()?))
+    }
+
+    fn var_len(&mut self) -> Result<u32, MidiError> {
+        let mut v = 0;
+        for _ in 0..4 {
+            let b = self.u8()?;
+            v = (v << 7) | u32::from(b & 0x7F);
+            if b & 0x80 == 0 { return Ok(v); }
+        }
+        Ok(v)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], MidiError> {
+        if self.data.len() - self.pos < len {
+            return Err(MidiError::UnexpectedEnd);
+        }
+        let b = &self.data[self.pos..(self.pos + len)];
+        self.pos += len;
+        Ok(b)
+    }
+}
+
+/// A track entry before the tick to time conversion.
+enum TrackEntry {
+    Event(EventKind),
+    /// Microseconds per quarter note.
+    Tempo(u32),
+}
+
+/// Reads the events of one `MTrk` chunk as `(tick, entry)` pairs.
+fn read_track(r: &mut Reader, end: usize, out: &mut Vec<(u64, TrackEntry)>)
+    -> Result<(), MidiError> {
+
+    let mut tick    = 0u64;
+    let mut running = None;
+
+    while r.pos < end {
+        tick += u64::from(r.var_len()?);
+
+        let offs   = r.pos;
+        let b      = r.u8()?;
+        match b {
+            // Meta and system exclusive events cancel the running status.
+            0xFF => {
+                running = None;
+                let typ = r.u8()?;
+                let len = r.var_len()? as usize;
+                let data = r.bytes(len)?;
+                if typ == 0x51 && len == 3 {
+                    let tempo =
+                        (u32::from(data[0]) << 16)
+                        | (u32::from(data[1]) << 8)
+                        | u32::from(data[2]);
+                    out.push((tick, TrackEntry::Tempo(tempo)));
+                } else if typ == 0x2F {
+                    break;
+                }
+            },
+            0xF0 | 0xF7 => {
+                running = None;
+                let len = r.var_len()? as usize;
+                r.bytes(len)?;
+            },
+            _ => {
+                let status =
+                    if b & 0x80 != 0 {
+                        // System common messages cancel the running status.
+                        if b < 0xF0 {
+                            running = Some(b);
+                        } else if b < 0xF8 {
+                            running = None;
+                        }
+                        b
+                    } else {
+                        r.pos -= 1;
+                        running.ok_or(MidiError::MissingStatus(offs))?
+                    };
+
+                let len = data_len(status).ok_or(MidiError::MissingStatus(offs))?;
+                let d1  = if len > 0 { r.u8()? } else { 0 };
+                let d2  = if len > 1 { r.u8()? } else { 0 };
+
+                if let Some(kind) = event_kind(status, d1, d2) {
+                    out.push((tick, TrackEntry::Event(kind)));
+                }
+            },
+        }
+    }
+
+    r.pos = end;
+    Ok(())
+}
+
+/// Parses a Standard MIDI File of format 0 or 1. Returns the events of
+/// all tracks, sorted by time, with the tempo changes applied.
+/// Events at the same time keep the track order.
+pub fn parse_smf(data: &[u8]) -> Result<Vec<TimedEvent>, MidiError> {
+    let mut r = Reader { data, pos: 0 };
+
+    if r.bytes(4).map_err(|_| MidiError::NotAMidiFile)? != b"MThd" {
+        return Err(MidiError::NotAMidiFile);
+    }
+    let hdr_len    = r.u32()? as usize;
+    if hdr_len < 6 {
+        return Err(MidiError::NotAMidiFile);
+    }
+    let hdr_end    = r.pos + hdr_len;
+    let format     = r.u16()?;
+    let num_tracks = r.u16()? as usize;
+    let division   = r.u16()?;
+    if format > 1 {
+        return Err(MidiError::UnsupportedFormat(format));
+    }
+    r.pos = hdr_end;
+
+    let mut entries : Vec<(u64, usize, TrackEntry)> = Vec::new();
+    let mut track = 0;
+    while track < num_tracks && r.pos < data.len() {
+        let id  = r.bytes(4)?;
+        let len = r.u32()? as usize;
+        let end = r.pos + len;
+        if end > data.len() {
+            return Err(MidiError::UnexpectedEnd);
+        }
+
+        if id == b"MTrk" {
+            let mut track_entries = Vec::new();
+            read_track(&mut r, end, &mut track_entries)?;
+            entries.extend(
+                track_entries.into_iter().map(|(t, e)| (t, track, e)));
+            track += 1;
+        } else {
+            // Unknown chunks are to be skipped.
+            r.pos = end;
+        }
+    }
+
+    // Stable, so events at the same tick keep their track order.
+    entries.sort_by_key(|(tick, track, _)| (*tick, *track));
+
+    // With SMPTE timing the division is frames per second and ticks
+    // per frame, otherwise ticks per quarter note.
+    let smpte_ticks_per_sec =
+        if division & 0x8000 != 0 {
+            let fps = -((division >> 8) as i8) as f64;
+            let fps = if fps == 29.0 { 29.97 } else { fps };
+            Some(fps * f64::from(division & 0xFF))
+        } else {
+            None
+        };
+    let ticks_per_qn = f64::from(division.max(1));
+
+    let mut events    = Vec::with_capacity(entries.len());
+    let mut tempo     = 500_000.0;
+    let mut last_tick = 0;
+    let mut time      = 0.0;
+    for (tick, track, entry) in entries.into_iter() {
+        let secs_per_tick = match smpte_ticks_per_sec {
+            Some(tps) => 1.0 / tps,
+            None      => tempo / 1_000_000.0 / ticks_per_qn,
+        };
+        time += (tick - last_tick) as f64 * secs_per_tick;
+        last_tick = tick;
+
+        match entry {
+            TrackEntry::Tempo(t)    => { tempo = f64::from(t); },
+            TrackEntry::Event(kind) => {
+                events.push(TimedEvent { time, track, event: Event::new(kind) });
+            },
+        }
+    }
+
+    Ok(events)
+}
+
+/// Plays back `TimedEvent`s, like those from `parse_smf`, by scheduling
+/// them block by block with `Simulator::schedule_event`.
+#[derive(Debug, Clone)]
+pub struct MidiSequence {
+    events:     Vec<TimedEvent>,
+    next:       usize,
+    sample_pos: u64,
+}
+
+impl MidiSequence {
+    /// The `events` must be sorted by time.
+    pub fn new(events: Vec<TimedEvent>) -> Self {
+        MidiSequence { events, next: 0, sample_pos: 0 }
+    }
+
+    pub fn from_smf(data: &[u8]) -> Result<Self, MidiError> {
+        Ok(Self::new(parse_smf(data)?))
+    }
+
+    pub fn events(&self) -> &[TimedEvent] { &self.events }
+
+    /// Time of the last event in seconds.
+    pub fn duration(&self) -> f64 {
+        self.events.last().map(|e| e.time).unwrap_or(0.0)
+    }
+
+    pub fn is_finished(&self) -> bool { self.next >= self.events.len() }
+
+    pub fn rewind(&mut self) {
+        self.next       = 0;
+        self.sample_pos = 0;
+    }
+
+    /// Schedules the events of the next `num_samples` samples at the
+    /// `sample_rate`, for a following `Simulator::process` of that length.
+    /// Channel events go to the ops of `group_idx`, transport and clock
+    /// events to all ops.
+    pub fn schedule_block(&mut self, sim: &mut Simulator, group_idx: usize,
+                          num_samples: usize, sample_rate: f32)
+        -> Result<(), SimulatorError> {
+
+        let block_end = self.sample_pos + num_samples as u64;
+        while let Some(te) = self.events.get(self.next) {
+            let pos = (te.time * f64::from(sample_rate)).round() as u64;
+            if pos >= block_end { break; }
+
+            let offs  = pos.saturating_sub(self.sample_pos) as usize;
+            let event = Event::at(offs, te.event.kind);
+            if event.channel().is_some() {
+                sim.schedule_event(group_idx, event)?;
+            } else {
+                sim.schedule_broadcast_event(event)?;
+            }
+            self.next += 1;
+        }
+
+        self.sample_pos = block_end;
+        Ok(())
+    }
+}
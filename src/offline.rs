@@ -0,0 +1,99 @@
+//! Rendering of a `Simulator` without an audio device, for example
+//! for regression tests or to bounce the render groups into stems.
+
+use crate::signals::Simulator;
+use crate::wav::{write_wav, WavFormat};
+use std::path::{Path, PathBuf};
+
+/// Default for `OfflineRender::block_size`.
+pub const DEFAULT_BLOCK_SIZE : usize = 128;
+
+/// Runs `Simulator::process` block by block for a given duration.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct OfflineRender {
+    pub sample_rate:    f32,
+    /// Number of samples rendered by each `Simulator::process` call.
+    pub block_size:     usize,
+    /// Sample format of the files written by `write_group_wavs`.
+    pub format:         WavFormat,
+}
+
+impl OfflineRender {
+    pub fn new(sample_rate: f32) -> Self {
+        OfflineRender {
+            sample_rate,
+            block_size: DEFAULT_BLOCK_SIZE,
+            format:     WavFormat::Float32,
+        }
+    }
+
+    pub fn with_block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size.max(1);
+        self
+    }
+
+    pub fn with_format(mut self, format: WavFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Renders `seconds` of audio and returns the interleaved stereo
+    /// samples of each render group. Sets the sample rate of `sim`,
+    /// its control rate is kept.
+    pub fn render(&self, sim: &mut Simulator, seconds: f64) -> Vec<Vec<f32>> {
+        self.render_with(sim, seconds, |_, _| ())
+    }
+
+    /// Like `render`, but calls `before_block` with the number of samples
+    /// before each block is rendered, for example to schedule the events
+    /// of a `MidiSequence`.
+    pub fn render_with<F>(&self, sim: &mut Simulator, seconds: f64, mut before_block: F)
+        -> Vec<Vec<f32>>
+        where F: FnMut(&mut Simulator, usize) {
+
+        sim.set_rates(sim.control_rate, self.sample_rate);
+
+        let total = (seconds * f64::from(self.sample_rate)).round().max(0.0) as usize;
+        let mut block  = sim.new_group_sample_buffers(self.block_size);
+        let mut output : Vec<Vec<f32>> =
+            block.iter().map(|_| Vec::with_capacity(total * 2)).collect();
+
+        let mut pos = 0;
+        while pos < total {
+            let len = self.block_size.min(total - pos);
+            before_block(sim, len);
+            sim.process(len, 0, &mut block);
+
+            for (out, buf) in output.iter_mut().zip(block.iter()) {
+                out.extend_from_slice(&buf[0..(len * 2)]);
+            }
+            pos += len;
+        }
+
+        output
+    }
+
+    /// Renders `seconds` of audio and writes a stereo WAV file for each
+    /// render group into `dir`, named after the group. Returns the paths
+    /// of the written files.
+    pub fn write_group_wavs(&self, sim: &mut Simulator, seconds: f64, dir: &Path)
+        -> std::io::Result<Vec<PathBuf>> {
+
+        let output = self.render(sim, seconds);
+
+        let mut paths = Vec::with_capacity(output.len());
+        for (grp, samples) in sim.op_groups.iter().zip(output.iter()) {
+            let name : String =
+                grp.name.chars()
+                   .map(|c| if c.is_alphanumeric() || c == '-' { c } else { '_' })
+                   .collect();
+            let path = dir.join(format!("{}.wav", name));
+
+            let mut file = std::io::BufWriter::new(std::fs::File::create(&path)?);
+            write_wav(&mut file, samples, 2, self.sample_rate as u32, self.format)?;
+            paths.push(path);
+        }
+
+        Ok(paths)
+    }
+}
@@ -1,12 +1,13 @@
-use crate::signals::{OpIn, Op, OpPort, OpIOSpec};
+use crate::signals::{OpIn, Op, OpPort, OpIOSpec, ExecContext};
+use crate::smoothing::{Smoothing, ControlInterp};
 
 pub struct AudioSend {
         volume_l: OpIn,
         volume_r: OpIn,
         volume_l_d: OpIn,
         volume_r_d: OpIn,
-        cur_vol_l: f32,
-        cur_vol_r: f32,
+        cur_vol_l: ControlInterp,
+        cur_vol_r: ControlInterp,
     pub out:    usize,
 }
 
@@ -17,19 +18,39 @@ impl AudioSend {
             volume_r:    OpIn::Constant(1.0),
             volume_l_d:  OpIn::Constant(0.5),
             volume_r_d:  OpIn::Constant(0.5),
-            cur_vol_l: 1.0,
-            cur_vol_r: 1.0,
+            cur_vol_l: ControlInterp::new(1.0),
+            cur_vol_r: ControlInterp::new(1.0),
             out:       0,
         }
     }
+
+    /// Creates a send that mixes into the group `out`.
+    pub fn to_group(out: usize) -> Self {
+        let mut send = Self::new();
+        send.out = out;
+        send
+    }
+}
+
+impl Default for AudioSend {
+    fn default() -> Self { Self::new() }
 }
 
 impl Op for AudioSend {
+    fn type_name(&self) -> &'static str { "audio_send" }
+    fn type_args(&self) -> Vec<usize> { vec![self.out] }
+
     fn io_spec(&self, index: usize) -> OpIOSpec {
         OpIOSpec {
             inputs: vec![
-                OpPort::new("vol_l", 0.0, 1.0),
-                OpPort::new("vol_r", 0.0, 1.0),
+                // Not `PortScale::Db`, the gain is the square of the
+                // value, which would be displayed at half its decibels.
+                OpPort::new("vol_l", 0.0, 1.0)
+                    .with_smoothing(Smoothing::Linear(0.01))
+                    .with_description("Volume of the left channel, the gain is its square"),
+                OpPort::new("vol_r", 0.0, 1.0)
+                    .with_smoothing(Smoothing::Linear(0.01))
+                    .with_description("Volume of the right channel, the gain is its square"),
             ],
             input_values:     vec![self.volume_l, self.volume_r],
             input_defaults:   vec![self.volume_l_d, self.volume_r_d],
@@ -41,17 +62,34 @@ impl Op for AudioSend {
     }
 
     fn init_regs(&mut self, _start_reg: usize, _regs: &mut [f32]) { }
-    fn get_output_reg(&mut self, _name: &str) -> Option<usize> { None }
+    fn output_reg(&self, _idx: usize) -> Option<usize> { None }
+    fn output_index(&self, _name: &str) -> Option<usize> { None }
 
-    fn set_input(&mut self, name: &str, to: OpIn, as_default: bool) -> bool {
-    println!("SETIN: {} = {:?}", name, to);
+    fn input_index(&self, name: &str) -> Option<usize> {
         match name {
-            "vol_l" => {
+            "vol_l" => Some(0),
+            "vol_r" => Some(1),
+            _       => None,
+        }
+    }
+
+    fn input_value(&self, idx: usize) -> Option<OpIn> {
+        match idx {
+            0 => Some(self.volume_l),
+            1 => Some(self.volume_r),
+            _ => None,
+        }
+    }
+
+    fn set_input_by_index(&mut self, idx: usize, to: OpIn, as_default: bool) -> bool {
+        //d// println!("SETIN: {} = {:?}", idx, to);
+        match idx {
+            0 => {
                 if as_default { self.volume_l_d = to; }
                 else { self.volume_l = to; }
                 true
             },
-            "vol_r" => {
+            1 => {
                 if as_default { self.volume_r_d = to; }
                 else { self.volume_r = to; }
                 true
@@ -60,17 +98,24 @@ impl Op for AudioSend {
         }
     }
 
-    fn exec(&mut self, _t: f32, regs: &mut [f32]) {
-        self.cur_vol_l = self.volume_l.calc(regs);
-        self.cur_vol_r = self.volume_r.calc(regs);
+    fn exec(&mut self, ctx: &ExecContext, regs: &mut [f32]) {
+        let len = ctx.samples_per_tick();
+        self.cur_vol_l.set(self.volume_l.calc(regs), len);
+        self.cur_vol_r.set(self.volume_r.calc(regs), len);
     }
 
-    fn render(&mut self, num_samples: usize, offs: usize, input_idx: usize, bufs: &mut Vec<Vec<f32>>) {
-        let vl = (self.cur_vol_l as f64) * (self.cur_vol_l as f64);
-        let vr = (self.cur_vol_r as f64) * (self.cur_vol_r as f64);
-        for i in 0..num_samples {
-            bufs[self.out][offs + (i * 2)]     += (vl * (bufs[input_idx][i * 2] as f64)) as f32;
-            bufs[self.out][offs + (i * 2) + 1] += (vr * (bufs[input_idx][i * 2 + 1] as f64)) as f32;
+    fn render(&mut self, num_samples: usize, offs: usize, input_idx: usize, bufs: &mut [Vec<f32>]) {
+        // A patch may name a group that does not exist.
+        if self.out >= bufs.len() { return; }
+
+        let vols =
+            self.cur_vol_l.ramp(num_samples)
+                .zip(self.cur_vol_r.ramp(num_samples));
+        for (i, (vl, vr)) in vols.enumerate() {
+            let vl = (vl as f64) * (vl as f64);
+            let vr = (vr as f64) * (vr as f64);
+            bufs[self.out][offs + (i * 2)]     += (vl * (bufs[input_idx][offs + i * 2] as f64)) as f32;
+            bufs[self.out][offs + (i * 2) + 1] += (vr * (bufs[input_idx][offs + i * 2 + 1] as f64)) as f32;
         }
     }
 }
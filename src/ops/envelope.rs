@@ -0,0 +1,390 @@
+use crate::signals::{OpIn, Op, OpPort, OpIOSpec, ExecContext, Event, EventKind};
+
+/// Maps the position `x` (0.0 to 1.0) within an envelope segment
+/// to the progress towards the segment's target level.
+/// A `curve` of 0.0 is linear, positive values start slow and end
+/// fast, negative values start fast and end slow.
+pub fn segment_shape(x: f32, curve: f32) -> f32 {
+    let x = x.clamp(0.0, 1.0);
+    if curve.abs() < 0.001 {
+        return x;
+    }
+
+    let k = curve.clamp(-1.0, 1.0) * 6.0;
+    ((k * x).exp() - 1.0) / (k.exp() - 1.0)
+}
+
+/// Advances the position within a segment of length `time` seconds
+/// by `dt` seconds. Segments of zero length are finished immediately.
+fn advance(pos: f32, time: f32, dt: f32) -> f32 {
+    if time <= 0.0 { 1.0 } else { (pos + dt / time).min(1.0) }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum AdsrStage {
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+/// ADSR envelope, gated by `EventKind::NoteOn` and `EventKind::NoteOff`.
+/// The times are in seconds, the curves are applied as
+/// described in `segment_shape`.
+pub struct Adsr {
+    values:   [OpIn; 7],
+    defaults: [OpIn; 7],
+    out:      usize,
+    stage:    AdsrStage,
+    pos:      f32,
+    start:    f32,
+    level:    f32,
+    note:     u8,
+}
+
+impl Adsr {
+    pub fn new() -> Self {
+        let defs = [
+            OpIn::Constant(0.01),
+            OpIn::Constant(0.1),
+            OpIn::Constant(0.5),
+            OpIn::Constant(0.3),
+            OpIn::Constant(0.0),
+            OpIn::Constant(0.0),
+            OpIn::Constant(0.0),
+        ];
+        Adsr {
+            values:   defs,
+            defaults: defs,
+            out:      0,
+            stage:    AdsrStage::Idle,
+            pos:      0.0,
+            start:    0.0,
+            level:    0.0,
+            note:     0,
+        }
+    }
+
+    fn enter(&mut self, stage: AdsrStage) {
+        self.stage = stage;
+        self.start = self.level;
+        self.pos   = 0.0;
+    }
+}
+
+impl Default for Adsr {
+    fn default() -> Self { Self::new() }
+}
+
+impl Op for Adsr {
+    fn type_name(&self) -> &'static str { "adsr" }
+
+    fn io_spec(&self, index: usize) -> OpIOSpec {
+        OpIOSpec {
+            inputs: vec![
+                OpPort::new("attack",   0.0, 60.0).with_unit("s"),
+                OpPort::new("decay",    0.0, 60.0).with_unit("s"),
+                OpPort::new("sustain",  0.0,  1.0),
+                OpPort::new("release",  0.0, 60.0).with_unit("s"),
+                OpPort::new("a_curve", -1.0,  1.0)
+                    .with_description("Shape of the attack, see segment_shape"),
+                OpPort::new("d_curve", -1.0,  1.0)
+                    .with_description("Shape of the decay, see segment_shape"),
+                OpPort::new("r_curve", -1.0,  1.0)
+                    .with_description("Shape of the release, see segment_shape"),
+            ],
+            input_values: self.values.to_vec(),
+            input_defaults: self.defaults.to_vec(),
+            outputs: vec![
+                OpPort::new("out", 0.0, 1.0),
+            ],
+            output_regs: vec![self.out],
+            audio_out_groups: vec![],
+            index,
+        }
+    }
+
+    fn init_regs(&mut self, start_reg: usize, regs: &mut [f32]) {
+        regs[start_reg] = 0.0;
+        self.out = start_reg;
+    }
+
+    fn output_index(&self, name: &str) -> Option<usize> {
+        match name {
+            "out"   => Some(0),
+            _       => None,
+        }
+    }
+
+    fn output_reg(&self, idx: usize) -> Option<usize> {
+        match idx {
+            0       => Some(self.out),
+            _       => None,
+        }
+    }
+
+    fn input_index(&self, name: &str) -> Option<usize> {
+        match name {
+            "attack"  => Some(0),
+            "decay"   => Some(1),
+            "sustain" => Some(2),
+            "release" => Some(3),
+            "a_curve" => Some(4),
+            "d_curve" => Some(5),
+            "r_curve" => Some(6),
+            _         => None,
+        }
+    }
+
+    fn input_value(&self, idx: usize) -> Option<OpIn> {
+        self.values.get(idx).copied()
+    }
+
+    fn set_input_by_index(&mut self, idx: usize, to: OpIn, as_default: bool) -> bool {
+        let s = if as_default { &mut self.defaults } else { &mut self.values };
+        if idx >= s.len() { return false; }
+        s[idx] = to;
+        true
+    }
+
+    fn event(&mut self, ev: &Event) {
+        match ev.kind {
+            EventKind::NoteOn { note, .. } => {
+                self.note = note;
+                self.enter(AdsrStage::Attack);
+            },
+            EventKind::NoteOff { note, .. }
+                if note == self.note
+                   && self.stage != AdsrStage::Idle
+                   && self.stage != AdsrStage::Release => {
+                self.enter(AdsrStage::Release);
+            },
+            _ => (),
+        }
+    }
+
+    fn exec(&mut self, ctx: &ExecContext, regs: &mut [f32]) {
+        let sustain = self.values[2].calc(regs);
+
+        match self.stage {
+            AdsrStage::Idle => { self.level = 0.0; },
+            AdsrStage::Attack => {
+                let time  = self.values[0].calc(regs);
+                let curve = self.values[4].calc(regs);
+                self.pos   = advance(self.pos, time, ctx.dt);
+                self.level = self.start + (1.0 - self.start) * segment_shape(self.pos, curve);
+                if self.pos >= 1.0 { self.enter(AdsrStage::Decay); }
+            },
+            AdsrStage::Decay => {
+                let time  = self.values[1].calc(regs);
+                let curve = self.values[5].calc(regs);
+                self.pos   = advance(self.pos, time, ctx.dt);
+                self.level = self.start + (sustain - self.start) * segment_shape(self.pos, curve);
+                if self.pos >= 1.0 { self.enter(AdsrStage::Sustain); }
+            },
+            AdsrStage::Sustain => { self.level = sustain; },
+            AdsrStage::Release => {
+                let time  = self.values[3].calc(regs);
+                let curve = self.values[6].calc(regs);
+                self.pos   = advance(self.pos, time, ctx.dt);
+                self.level = self.start - self.start * segment_shape(self.pos, curve);
+                if self.pos >= 1.0 { self.enter(AdsrStage::Idle); }
+            },
+        }
+
+        regs[self.out] = self.level;
+    }
+}
+
+/// Maximum number of segments of an `Env` created by the `OpRegistry`.
+pub const ENV_MAX_SEGMENTS : usize = 64;
+
+/// Envelope with a configurable number of segments, gated by
+/// `EventKind::NoteOn` and `EventKind::NoteOff`.
+///
+/// Each segment `i` ramps from the current level to `level<i>` in
+/// `time<i>` seconds, shaped by `curve<i>` (see `segment_shape`).
+/// While the note is held, the end level of the segment with the
+/// index given by the `sustain` input is held. On note off the envelope
+/// continues with the segment after it. A negative `sustain`
+/// makes the envelope run through regardless of note off.
+pub struct Env {
+    /// `sustain`, followed by `level<i>`, `time<i>`, `curve<i>` per segment.
+    values:   Vec<OpIn>,
+    defaults: Vec<OpIn>,
+    out:      usize,
+    segment:  Option<usize>,
+    gate:     bool,
+    pos:      f32,
+    start:    f32,
+    level:    f32,
+    note:     u8,
+}
+
+impl Env {
+    pub fn new(num_segments: usize) -> Self {
+        let num_segments = num_segments.max(1);
+        let sustain =
+            if num_segments >= 2 { (num_segments - 2) as f32 } else { -1.0 };
+
+        let mut defs = vec![OpIn::Constant(sustain)];
+        for i in 0..num_segments {
+            let level =
+                if i == 0                   { 1.0 }
+                else if i + 1 == num_segments { 0.0 }
+                else                        { 0.5 };
+            defs.push(OpIn::Constant(level));
+            defs.push(OpIn::Constant(0.1));
+            defs.push(OpIn::Constant(0.0));
+        }
+
+        Env {
+            values:   defs.clone(),
+            defaults: defs,
+            out:      0,
+            segment:  None,
+            gate:     false,
+            pos:      0.0,
+            start:    0.0,
+            level:    0.0,
+            note:     0,
+        }
+    }
+
+    fn num_segments(&self) -> usize { (self.values.len() - 1) / 3 }
+
+    fn sustain_segment(&self, regs: &[f32]) -> Option<usize> {
+        let s = self.values[0].calc(regs);
+        if s < 0.0 { None } else { Some(s.round() as usize) }
+    }
+
+    fn enter(&mut self, segment: Option<usize>) {
+        self.segment = segment.filter(|s| *s < self.num_segments());
+        self.start   = self.level;
+        self.pos     = 0.0;
+    }
+}
+
+impl Op for Env {
+    fn type_name(&self) -> &'static str { "env" }
+    fn type_args(&self) -> Vec<usize> { vec![self.num_segments()] }
+
+    fn io_spec(&self, index: usize) -> OpIOSpec {
+        let mut inputs = vec![
+            OpPort::new("sustain", -1.0, (self.num_segments() - 1) as f32)
+                .with_step(1.0)
+                .with_description(
+                    "Segment whose level is held until note off, -1 for none"),
+        ];
+        for i in 0..self.num_segments() {
+            inputs.push(OpPort::new(&format!("level{}", i),  0.0,  1.0));
+            inputs.push(OpPort::new(&format!("time{}", i),   0.0, 60.0).with_unit("s"));
+            inputs.push(
+                OpPort::new(&format!("curve{}", i), -1.0,  1.0)
+                    .with_description("Shape of the segment, see segment_shape"));
+        }
+
+        OpIOSpec {
+            inputs,
+            input_values: self.values.clone(),
+            input_defaults: self.defaults.clone(),
+            outputs: vec![
+                OpPort::new("out", 0.0, 1.0),
+            ],
+            output_regs: vec![self.out],
+            audio_out_groups: vec![],
+            index,
+        }
+    }
+
+    fn init_regs(&mut self, start_reg: usize, regs: &mut [f32]) {
+        regs[start_reg] = 0.0;
+        self.out = start_reg;
+    }
+
+    fn output_index(&self, name: &str) -> Option<usize> {
+        match name {
+            "out"   => Some(0),
+            _       => None,
+        }
+    }
+
+    fn output_reg(&self, idx: usize) -> Option<usize> {
+        match idx {
+            0       => Some(self.out),
+            _       => None,
+        }
+    }
+
+    fn input_index(&self, name: &str) -> Option<usize> {
+        let (seg, offs) =
+            if name == "sustain" {
+                return Some(0);
+            } else if let Some(i) = super::parse_port_index(name, "level") {
+                (i, 1)
+            } else if let Some(i) = super::parse_port_index(name, "time") {
+                (i, 2)
+            } else if let Some(i) = super::parse_port_index(name, "curve") {
+                (i, 3)
+            } else {
+                return None;
+            };
+
+        if seg < self.num_segments() { Some(seg * 3 + offs) } else { None }
+    }
+
+    fn input_value(&self, idx: usize) -> Option<OpIn> {
+        self.values.get(idx).copied()
+    }
+
+    fn set_input_by_index(&mut self, idx: usize, to: OpIn, as_default: bool) -> bool {
+        let s = if as_default { &mut self.defaults } else { &mut self.values };
+        if idx >= s.len() { return false; }
+        s[idx] = to;
+        true
+    }
+
+    fn event(&mut self, ev: &Event) {
+        match ev.kind {
+            EventKind::NoteOn { note, .. } => {
+                self.note = note;
+                self.gate = true;
+                self.enter(Some(0));
+            },
+            EventKind::NoteOff { note, .. } => {
+                if note != self.note || !self.gate { return; }
+                self.gate = false;
+                // The sustain input is only known in exec, so the jump
+                // to the release segments happens there.
+            },
+            _ => (),
+        }
+    }
+
+    fn exec(&mut self, ctx: &ExecContext, regs: &mut [f32]) {
+        let sustain = self.sustain_segment(regs);
+
+        if let (Some(seg), Some(sus), false) = (self.segment, sustain, self.gate) {
+            if seg <= sus {
+                self.enter(Some(sus + 1));
+            }
+        }
+
+        if let Some(seg) = self.segment {
+            let level = self.values[seg * 3 + 1].calc(regs);
+            let time  = self.values[seg * 3 + 2].calc(regs);
+            let curve = self.values[seg * 3 + 3].calc(regs);
+
+            self.pos   = advance(self.pos, time, ctx.dt);
+            self.level = self.start + (level - self.start) * segment_shape(self.pos, curve);
+
+            let hold = self.gate && sustain == Some(seg);
+            if self.pos >= 1.0 && !hold {
+                self.enter(Some(seg + 1));
+            }
+        }
+
+        regs[self.out] = self.level;
+    }
+}
@@ -0,0 +1,197 @@
+use crate::signals::{OpIn, Op, OpPort, OpIOSpec, ExecContext};
+use crate::smoothing::Smoothing;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LfoWave {
+    Triangle,
+    SawUp,
+    SawDown,
+    /// Has an additional `pw` input for the pulse width from 0.0 to 1.0.
+    Square,
+    /// Holds a new random value for each cycle.
+    Random,
+}
+
+/// Low frequency oscillator with the same `amp`, `phase`, `vert` and
+/// `freq` inputs as `Sin`, so they can be exchanged in a patch.
+/// At phase 0.0 `Triangle` and `SawUp` start at 0.0 rising like `Sin`,
+/// `SawDown` starts at 0.0 falling, `Square` starts at 1.0 and
+/// `Random` starts at its first random value.
+pub struct Lfo {
+    wave:     LfoWave,
+    values:   [OpIn; 5],
+    defaults: [OpIn; 5],
+    out:      usize,
+    /// Current phase in cycles, in the range 0.0 to 1.0.
+    phase:    f64,
+    /// Frequency of the previous tick, like in `Sin`.
+    freq:     f64,
+    seed:     u64,
+    rng:      u64,
+    hold:     f32,
+}
+
+impl Lfo {
+    pub fn new(wave: LfoWave) -> Self {
+        Self::with_seed(wave, 0x1234_5678_9abc_def1)
+    }
+
+    /// Creates an LFO with the given seed for the `LfoWave::Random` values.
+    pub fn with_seed(wave: LfoWave, seed: u64) -> Self {
+        let defs = [
+            OpIn::Constant(1.0),
+            OpIn::Constant(0.0),
+            OpIn::Constant(0.0),
+            OpIn::Constant(1.0),
+            OpIn::Constant(0.5),
+        ];
+        let mut lfo = Lfo {
+            wave,
+            values:   defs,
+            defaults: defs,
+            out:      0,
+            phase:    0.0,
+            freq:     0.0,
+            seed,
+            rng:      seed | 1,
+            hold:     0.0,
+        };
+        lfo.hold = lfo.next_random();
+        lfo
+    }
+
+    fn num_inputs(&self) -> usize {
+        if self.wave == LfoWave::Square { 5 } else { 4 }
+    }
+
+    /// Returns a random value in the range -1.0 to 1.0 (xorshift64*).
+    fn next_random(&mut self) -> f32 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        let r = self.rng.wrapping_mul(0x2545_f491_4f6c_dd1d);
+        ((r >> 40) as f32 / (1u64 << 23) as f32) - 1.0
+    }
+
+    /// Waveform in the range -1.0 to 1.0 at phase `ph` (in cycles).
+    fn wave_at(&self, ph: f32, pw: f32) -> f32 {
+        match self.wave {
+            LfoWave::Triangle => 1.0 - 4.0 * ((ph + 0.25).fract() - 0.5).abs(),
+            LfoWave::SawUp    => 2.0 * (ph + 0.5).fract() - 1.0,
+            LfoWave::SawDown  => 1.0 - 2.0 * (ph + 0.5).fract(),
+            LfoWave::Square   => if ph < pw { 1.0 } else { -1.0 },
+            LfoWave::Random   => self.hold,
+        }
+    }
+}
+
+impl Op for Lfo {
+    fn type_name(&self) -> &'static str {
+        match self.wave {
+            LfoWave::Triangle => "tri",
+            LfoWave::SawUp    => "saw_up",
+            LfoWave::SawDown  => "saw_down",
+            LfoWave::Square   => "square",
+            LfoWave::Random   => "random",
+        }
+    }
+
+    fn type_args(&self) -> Vec<usize> {
+        if self.wave == LfoWave::Random { vec![self.seed as usize] }
+        else { Vec::new() }
+    }
+
+    fn io_spec(&self, index: usize) -> OpIOSpec {
+        let mut inputs = vec![
+            OpPort::new("amp",    0.0, 9999.0)
+                .with_smoothing(Smoothing::Linear(0.01))
+                .with_description("Scales the output, including vert"),
+            OpPort::new("phase", -2.0 * std::f32::consts::PI,
+                                     2.0 * std::f32::consts::PI)
+                .with_unit("rad")
+                .with_description("Phase offset"),
+            OpPort::new("vert",  -9999.0,  9999.0)
+                .with_smoothing(Smoothing::Linear(0.01))
+                .with_description("Vertical offset, added before amp"),
+            OpPort::new("freq",      0.0, 11025.0)
+                .with_unit("Hz"),
+        ];
+        if self.wave == LfoWave::Square {
+            inputs.push(
+                OpPort::new("pw", 0.0, 1.0)
+                    .with_description("Pulse width, the high part of the period"));
+        }
+
+        OpIOSpec {
+            inputs,
+            input_values: self.values[0..self.num_inputs()].to_vec(),
+            input_defaults: self.defaults[0..self.num_inputs()].to_vec(),
+            outputs: vec![
+                OpPort::new("out", -9999.0, 9999.0),
+            ],
+            output_regs: vec![self.out],
+            audio_out_groups: vec![],
+            index,
+        }
+    }
+
+    fn init_regs(&mut self, start_reg: usize, regs: &mut [f32]) {
+        regs[start_reg] = 0.0;
+        self.out = start_reg;
+    }
+
+    fn output_index(&self, name: &str) -> Option<usize> {
+        match name {
+            "out"   => Some(0),
+            _       => None,
+        }
+    }
+
+    fn output_reg(&self, idx: usize) -> Option<usize> {
+        match idx {
+            0       => Some(self.out),
+            _       => None,
+        }
+    }
+
+    fn input_index(&self, name: &str) -> Option<usize> {
+        match name {
+            "amp"   => Some(0),
+            "phase" => Some(1),
+            "vert"  => Some(2),
+            "freq"  => Some(3),
+            "pw" if self.wave == LfoWave::Square => Some(4),
+            _       => None,
+        }
+    }
+
+    fn input_value(&self, idx: usize) -> Option<OpIn> {
+        if idx >= self.num_inputs() { return None; }
+        Some(self.values[idx])
+    }
+
+    fn set_input_by_index(&mut self, idx: usize, to: OpIn, as_default: bool) -> bool {
+        if idx >= self.num_inputs() { return false; }
+        let s = if as_default { &mut self.defaults } else { &mut self.values };
+        s[idx] = to;
+        true
+    }
+
+    fn exec(&mut self, ctx: &ExecContext, regs: &mut [f32]) {
+        let next_phase = self.phase + self.freq * (ctx.dt as f64);
+        if self.wave == LfoWave::Random && next_phase.floor() != self.phase.floor() {
+            self.hold = self.next_random();
+        }
+        self.phase = next_phase.rem_euclid(1.0);
+
+        let a  = self.values[0].calc(regs);
+        let p  = self.values[1].calc(regs);
+        let v  = self.values[2].calc(regs);
+        let f  = self.values[3].calc(regs);
+        let pw = self.values[4].calc(regs);
+
+        let ph = (self.phase as f32 + p / std::f32::consts::TAU).rem_euclid(1.0);
+        regs[self.out] = a * (self.wave_at(ph, pw) + v);
+        self.freq = f as f64;
+    }
+}
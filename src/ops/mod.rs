@@ -1,7 +1,84 @@
 pub mod sin;
 pub mod proxy;
 pub mod audio_send;
+pub mod lfo;
+pub mod envelope;
 
 pub use sin::Sin;
-pub use proxy::OutProxy;
+pub use proxy::{OutProxy, OUT_PROXY_MAX_OUTPUTS};
 pub use audio_send::AudioSend;
+pub use lfo::{Lfo, LfoWave};
+pub use envelope::{Adsr, Env, ENV_MAX_SEGMENTS};
+
+use crate::registry::OpRegistry;
+
+/// Parses numbered port names like `"out12"` with the `prefix` `"out"`.
+/// Only accepts the exact form without sign or leading zeros.
+pub(crate) fn parse_port_index(name: &str, prefix: &str) -> Option<usize> {
+    let num = name.strip_prefix(prefix)?;
+    if !num.bytes().all(|b| b.is_ascii_digit())
+       || (num.len() > 1 && num.starts_with('0')) {
+        return None;
+    }
+    num.parse::<usize>().ok()
+}
+
+pub fn register_ops(registry: &mut OpRegistry) {
+    registry.register(
+        "sin",
+        "Sine oscillator: amp * (sin(2 * PI * freq * t + phase) + vert), \
+         with freq in Hz",
+        |_| Box::new(Sin::new()));
+    registry.register(
+        "tri",
+        "Triangle LFO, with the same inputs as sin",
+        |_| Box::new(Lfo::new(LfoWave::Triangle)));
+    registry.register(
+        "saw_up",
+        "Rising saw LFO, with the same inputs as sin",
+        |_| Box::new(Lfo::new(LfoWave::SawUp)));
+    registry.register(
+        "saw_down",
+        "Falling saw LFO, with the same inputs as sin",
+        |_| Box::new(Lfo::new(LfoWave::SawDown)));
+    registry.register(
+        "square",
+        "Square LFO, with the same inputs as sin and the pulse width pw",
+        |_| Box::new(Lfo::new(LfoWave::Square)));
+    registry.register(
+        "random",
+        "Stepped random LFO, holds a new random value each cycle. \
+         The type argument is the random seed",
+        |args| Box::new(match args.first() {
+            Some(seed) => Lfo::with_seed(LfoWave::Random, *seed as u64),
+            None       => Lfo::new(LfoWave::Random),
+        }));
+    registry.register(
+        "adsr",
+        "ADSR envelope, started by note on and released by note off. \
+         The times are in seconds, the curves bend the segments \
+         from -1.0 (fast start) over 0.0 (linear) to 1.0 (slow start)",
+        |_| Box::new(Adsr::new()));
+    registry.register(
+        "env",
+        &format!(
+            "Multi segment envelope, started by note on. The level of the \
+             sustain segment is held until note off. \
+             The type argument is the number of segments, at most {}",
+            ENV_MAX_SEGMENTS),
+        |args| Box::new(Env::new(args.first().copied().unwrap_or(4).min(ENV_MAX_SEGMENTS))));
+    registry.register(
+        "audio_send",
+        "Mixes the audio of its group into another group with a \
+         left and right volume. The type argument is the index \
+         of the target group",
+        |args| Box::new(AudioSend::to_group(args.first().copied().unwrap_or(0))));
+    registry.register(
+        "out_proxy",
+        &format!(
+            "Provides values set by the host application as output registers. \
+             The type argument is the number of outputs, at most {}",
+            OUT_PROXY_MAX_OUTPUTS),
+        |args| Box::new(OutProxy::new(
+            args.first().copied().unwrap_or(1).min(OUT_PROXY_MAX_OUTPUTS))));
+}
@@ -1,4 +1,7 @@
-use crate::signals::{OpIn, Op, OpPort, OpIOSpec};
+use crate::signals::{OpIn, Op, OpPort, OpIOSpec, ExecContext};
+
+/// Maximum number of outputs of an `OutProxy` created by the `OpRegistry`.
+pub const OUT_PROXY_MAX_OUTPUTS : usize = 1024;
 
 pub struct OutProxy {
     pub values:   std::rc::Rc<std::cell::RefCell<Vec<f32>>>,
@@ -15,6 +18,9 @@ impl OutProxy {
 }
 
 impl Op for OutProxy {
+    fn type_name(&self) -> &'static str { "out_proxy" }
+    fn type_args(&self) -> Vec<usize> { vec![self.out_regs.len()] }
+
     fn io_spec(&self, index: usize) -> OpIOSpec {
         OpIOSpec {
             inputs:         vec![],
@@ -37,25 +43,27 @@ impl Op for OutProxy {
         }
     }
 
-    fn get_output_reg(&mut self, name: &str) -> Option<usize> {
-        for i in 0..self.out_regs.len() {
-            if name == format!("out{}", i) {
-                return Some(self.out_regs[i])
-            }
-        }
+    fn output_index(&self, name: &str) -> Option<usize> {
+        super::parse_port_index(name, "out").filter(|i| *i < self.out_regs.len())
+    }
 
-        None
+    fn output_reg(&self, idx: usize) -> Option<usize> {
+        self.out_regs.get(idx).copied()
     }
 
-    fn set_input(&mut self, _name: &str, _to: OpIn, _as_default: bool) -> bool {
+    fn input_index(&self, _name: &str) -> Option<usize> { None }
+
+    fn set_input_by_index(&mut self, _idx: usize, _to: OpIn, _as_default: bool) -> bool {
         false
     }
 
-    fn exec(&mut self, _t: f32, regs: &mut [f32]) {
+    fn exec(&mut self, _ctx: &ExecContext, regs: &mut [f32]) {
         let v = self.values.borrow();
         for (i, or) in self.out_regs.iter().enumerate() {
             regs[*or] = v[i];
         }
     }
+
+    fn as_any(&self) -> Option<&dyn std::any::Any> { Some(self) }
 }
 
@@ -1,9 +1,15 @@
-use crate::signals::{OpIn, Op, OpPort, OpIOSpec};
+use crate::signals::{OpIn, Op, OpPort, OpIOSpec, ExecContext};
+use crate::smoothing::Smoothing;
 
 pub struct Sin {
     values:   [OpIn; 4],
     defaults: [OpIn; 4],
     out:      usize,
+    /// Current phase in cycles, in the range 0.0 to 1.0.
+    phase:    f64,
+    /// Frequency of the previous tick, the phase advances with
+    /// in the next one.
+    freq:     f64,
 }
 
 impl Sin {
@@ -17,6 +23,8 @@ impl Sin {
         Sin {
             values:   defs,
             out:      0,
+            phase:    0.0,
+            freq:     0.0,
             defaults: [
                 OpIn::Constant(1.0),
                 OpIn::Constant(0.0),
@@ -27,15 +35,28 @@ impl Sin {
     }
 }
 
+impl Default for Sin {
+    fn default() -> Self { Self::new() }
+}
+
 impl Op for Sin {
+    fn type_name(&self) -> &'static str { "sin" }
+
     fn io_spec(&self, index: usize) -> OpIOSpec {
         OpIOSpec {
             inputs: vec![
-                OpPort::new("amp",    0.0, 9999.0),
+                OpPort::new("amp",    0.0, 9999.0)
+                    .with_smoothing(Smoothing::Linear(0.01))
+                    .with_description("Scales the output, including vert"),
                 OpPort::new("phase", -2.0 * std::f32::consts::PI,
-                                         2.0 * std::f32::consts::PI),
-                OpPort::new("vert",  -9999.0,  9999.0),
-                OpPort::new("freq",      0.0, 11025.0),
+                                         2.0 * std::f32::consts::PI)
+                    .with_unit("rad")
+                    .with_description("Phase offset"),
+                OpPort::new("vert",  -9999.0,  9999.0)
+                    .with_smoothing(Smoothing::Linear(0.01))
+                    .with_description("Vertical offset, added before amp"),
+                OpPort::new("freq",      0.0, 11025.0)
+                    .with_unit("Hz"),
             ],
             input_values: self.values.to_vec(),
             input_defaults: self.defaults.to_vec(),
@@ -53,30 +74,55 @@ impl Op for Sin {
         self.out = start_reg;
     }
 
-    fn get_output_reg(&mut self, name: &str) -> Option<usize> {
+    fn output_index(&self, name: &str) -> Option<usize> {
         match name {
-            "out"   => Some(self.out),
+            "out"   => Some(0),
             _       => None,
         }
     }
 
-    fn set_input(&mut self, name: &str, to: OpIn, as_default: bool) -> bool {
-        let s = if as_default { &mut self.defaults } else { &mut self.values };
+    fn output_reg(&self, idx: usize) -> Option<usize> {
+        match idx {
+            0       => Some(self.out),
+            _       => None,
+        }
+    }
+
+    fn input_index(&self, name: &str) -> Option<usize> {
         match name {
-            "amp"   => { s[0] = to; true },
-            "phase" => { s[1] = to; true },
-            "vert"  => { s[2] = to; true },
-            "freq"  => { s[3] = to; true },
-            _       => false,
+            "amp"   => Some(0),
+            "phase" => Some(1),
+            "vert"  => Some(2),
+            "freq"  => Some(3),
+            _       => None,
         }
     }
 
-    fn exec(&mut self, t: f32, regs: &mut [f32]) {
+    fn input_value(&self, idx: usize) -> Option<OpIn> {
+        self.values.get(idx).copied()
+    }
+
+    fn set_input_by_index(&mut self, idx: usize, to: OpIn, as_default: bool) -> bool {
+        let s = if as_default { &mut self.defaults } else { &mut self.values };
+        if idx >= s.len() { return false; }
+        s[idx] = to;
+        true
+    }
+
+    fn exec(&mut self, ctx: &ExecContext, regs: &mut [f32]) {
+        // Accumulating the phase keeps it continuous if f is modulated.
+        // It advances over the time that actually passed since the
+        // previous tick, see `ExecContext::dt`.
+        self.phase = (self.phase + self.freq * (ctx.dt as f64)).rem_euclid(1.0);
+
         let a = self.values[0].calc(regs);
         let p = self.values[1].calc(regs);
         let v = self.values[2].calc(regs);
         let f = self.values[3].calc(regs);
-        regs[self.out] = a * (((f * t) + p).sin() + v);
+
+        let ph = (self.phase * std::f64::consts::TAU) as f32;
+        regs[self.out] = a * ((ph + p).sin() + v);
+        self.freq = f as f64;
         //d// println!("OUT: {}, {}", regs[self.out], self.out);
     }
 }
@@ -0,0 +1,28 @@
+use crate::signals::NamedOpIn;
+use serde::Serialize;
+use serde::Deserialize;
+
+/// A complete description of the ops, groups and their connections
+/// of a `Simulator`. See also `Simulator::save_patch` and
+/// `Simulator::load_patch`.
+#[derive(Debug, PartialEq, Clone, Default, Serialize, Deserialize)]
+pub struct Patch {
+    pub groups:         Vec<String>,
+    pub ops:            Vec<PatchOp>,
+    /// `(from_op, to_op)` names, see also `Simulator::set_delay_edge`.
+    pub delay_edges:    Vec<(String, String)>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct PatchOp {
+    pub op_type:        String,
+    pub type_args:      Vec<usize>,
+    pub name:           String,
+    pub group:          String,
+    pub inputs:         Vec<(String, NamedOpIn)>,
+    pub defaults:       Vec<(String, NamedOpIn)>,
+    /// `(input_name, source_reg_name, depth)`,
+    /// see also `Simulator::set_op_modulation`.
+    #[serde(default)]
+    pub modulations:    Vec<(String, String, f32)>,
+}
@@ -0,0 +1,88 @@
+use crate::signals::{Op, OpPort};
+use serde::Serialize;
+use serde::Deserialize;
+
+pub type OpConstructor = Box<dyn Fn(&[usize]) -> Box<dyn Op>>;
+
+/// Static description of an op type, for instance to let a UI list
+/// the available ops.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct OpTypeInfo {
+    pub type_name:      String,
+    pub description:    String,
+    pub inputs:         Vec<OpPort>,
+    pub outputs:        Vec<OpPort>,
+    pub does_render:    bool,
+}
+
+/// Maps op type names to constructors and metadata, so that ops can be
+/// listed and instanciated by name, for instance when loading a `Patch`.
+pub struct OpRegistry {
+    types: Vec<(OpTypeInfo, OpConstructor)>,
+}
+
+impl OpRegistry {
+    /// Creates a registry with all ops from the `ops` module registered.
+    pub fn new() -> Self {
+        let mut reg = Self::empty();
+        crate::ops::register_ops(&mut reg);
+        reg
+    }
+
+    pub fn empty() -> Self {
+        OpRegistry { types: Vec::new() }
+    }
+
+    /// Registers a constructor for `type_name`. It receives the
+    /// `Op::type_args` of the op to create, which may come from a loaded
+    /// `Patch`, so it should cap them to what the op supports.
+    /// `Simulator::load_patch` reports arguments the op did not take.
+    /// The ports listed in the `OpTypeInfo` are taken from an op
+    /// constructed without arguments.
+    /// A previously registered op type of the same name is replaced.
+    pub fn register<F>(&mut self, type_name: &str, description: &str, constructor: F)
+        where F: Fn(&[usize]) -> Box<dyn Op> + 'static {
+
+        let proto = constructor(&[]);
+        let spec  = proto.io_spec(0);
+        let info  = OpTypeInfo {
+            type_name:   type_name.to_string(),
+            description: description.to_string(),
+            inputs:      spec.inputs,
+            outputs:     spec.outputs,
+            does_render: proto.does_render(),
+        };
+
+        let constructor = Box::new(constructor);
+        if let Some(t) = self.types.iter_mut().find(|(i, _)| i.type_name == type_name) {
+            *t = (info, constructor);
+        } else {
+            self.types.push((info, constructor));
+        }
+    }
+
+    pub fn create(&self, type_name: &str, type_args: &[usize]) -> Option<Box<dyn Op>> {
+        self.types.iter()
+            .find(|(i, _)| i.type_name == type_name)
+            .map(|(_, c)| c(type_args))
+    }
+
+    pub fn type_info(&self, type_name: &str) -> Option<&OpTypeInfo> {
+        self.types.iter()
+            .find(|(i, _)| i.type_name == type_name)
+            .map(|(i, _)| i)
+    }
+
+    /// Returns the infos of all registered op types in registration order.
+    pub fn type_infos(&self) -> Vec<&OpTypeInfo> {
+        self.types.iter().map(|(i, _)| i).collect()
+    }
+
+    pub fn type_names(&self) -> Vec<&str> {
+        self.types.iter().map(|(i, _)| &i.type_name[..]).collect()
+    }
+}
+
+impl Default for OpRegistry {
+    fn default() -> Self { Self::new() }
+}
@@ -0,0 +1,112 @@
+//! Bounded single producer, single consumer ring buffer.
+//!
+//! The slots are allocated once on construction, so pushing and popping
+//! neither allocates nor locks. This makes it usable for communication
+//! with a real time audio thread.
+
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct Shared<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    /// Position of the next slot to read, only written by the `Consumer`.
+    head:  AtomicUsize,
+    /// Position of the next slot to write, only written by the `Producer`.
+    tail:  AtomicUsize,
+}
+
+// The slots between head and tail are only accessed by the Consumer,
+// the others only by the Producer. The hand over is synchronized by
+// the Release/Acquire pairs on head and tail.
+unsafe impl<T: Send> Send for Shared<T> { }
+unsafe impl<T: Send> Sync for Shared<T> { }
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        let mut head = *self.head.get_mut();
+        let tail     = *self.tail.get_mut();
+        while head != tail {
+            let slot = head % self.slots.len();
+            unsafe { std::ptr::drop_in_place((*self.slots[slot].get()).as_mut_ptr()); }
+            head = head.wrapping_add(1);
+        }
+    }
+}
+
+pub struct Producer<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub struct Consumer<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// Creates a ring buffer that can hold `capacity` elements.
+pub fn ring_buffer<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    let capacity = capacity.max(1);
+    let slots : Vec<UnsafeCell<MaybeUninit<T>>> =
+        (0..capacity).map(|_| UnsafeCell::new(MaybeUninit::uninit())).collect();
+
+    let shared = Arc::new(Shared {
+        slots: slots.into_boxed_slice(),
+        head:  AtomicUsize::new(0),
+        tail:  AtomicUsize::new(0),
+    });
+
+    (Producer { shared: shared.clone() }, Consumer { shared })
+}
+
+impl<T> Producer<T> {
+    /// Appends `v` to the buffer, or hands it back if the buffer is full.
+    pub fn push(&mut self, v: T) -> Result<(), T> {
+        let s    = &*self.shared;
+        let tail = s.tail.load(Ordering::Relaxed);
+        let head = s.head.load(Ordering::Acquire);
+        if tail.wrapping_sub(head) >= s.slots.len() {
+            return Err(v);
+        }
+
+        unsafe { (*s.slots[tail % s.slots.len()].get()).as_mut_ptr().write(v); }
+        s.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Returns true if the `Consumer` was dropped.
+    pub fn is_abandoned(&self) -> bool {
+        Arc::strong_count(&self.shared) < 2
+    }
+}
+
+impl<T> Consumer<T> {
+    pub fn pop(&mut self) -> Option<T> {
+        let s    = &*self.shared;
+        let head = s.head.load(Ordering::Relaxed);
+        let tail = s.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+
+        let v = unsafe { (*s.slots[head % s.slots.len()].get()).as_ptr().read() };
+        s.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(v)
+    }
+
+    /// Returns true if the `Producer` was dropped.
+    pub fn is_abandoned(&self) -> bool {
+        Arc::strong_count(&self.shared) < 2
+    }
+}
+
+impl<T> std::fmt::Debug for Producer<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Producer")
+    }
+}
+
+impl<T> std::fmt::Debug for Consumer<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Consumer")
+    }
+}
@@ -15,7 +15,7 @@ impl SampleRow {
     }
 
     pub fn read_from_regs(&mut self, regs: &[f32], pos: usize) {
-        if self.sample_row.len() < regs.len() {
+        if self.sample_row.len() != regs.len() {
             self.sample_row.resize(regs.len(), 0.0);
         }
 
@@ -25,4 +25,80 @@ impl SampleRow {
     }
 }
 
+impl Default for SampleRow {
+    fn default() -> Self { Self::new() }
+}
+
+const FRESH : usize = 0x4;
+
+/// Three `SampleRow` buffers, one owned by the writer, one by the reader
+/// and one in the middle for the hand over.
+struct Exchange {
+    rows:   [std::cell::UnsafeCell<SampleRow>; 3],
+    /// Index of the middle buffer, or'ed with `FRESH` if the writer
+    /// published a new row that the reader did not fetch yet.
+    middle: std::sync::atomic::AtomicUsize,
+}
+
+// Each of the three rows is only accessed by the side that currently
+// owns its index, the ownership is handed over by atomic swaps.
+unsafe impl Send for Exchange { }
+unsafe impl Sync for Exchange { }
+
+/// Publishes `SampleRow`s without locking, see `new_sample_row_exchange`.
+pub struct SampleRowWriter {
+    ex:   std::sync::Arc<Exchange>,
+    back: usize,
+}
+
+/// Fetches the latest published `SampleRow`, see `new_sample_row_exchange`.
+pub struct SampleRowReader {
+    ex:    std::sync::Arc<Exchange>,
+    front: usize,
+}
+
+/// Creates a lock free triple buffer to hand the latest `SampleRow`
+/// from the `Simulator` to the UI. `num_regs` preallocates the rows.
+pub fn new_sample_row_exchange(num_regs: usize) -> (SampleRowWriter, SampleRowReader) {
+    let new_row = || {
+        let mut row = SampleRow::new();
+        row.sample_row.resize(num_regs, 0.0);
+        std::cell::UnsafeCell::new(row)
+    };
+
+    let ex = std::sync::Arc::new(Exchange {
+        rows:   [new_row(), new_row(), new_row()],
+        middle: std::sync::atomic::AtomicUsize::new(1),
+    });
 
+    (SampleRowWriter { ex: ex.clone(), back: 0 },
+     SampleRowReader { ex, front: 2 })
+}
+
+impl SampleRowWriter {
+    pub fn publish(&mut self, regs: &[f32], pos: usize) {
+        let row = unsafe { &mut *self.ex.rows[self.back].get() };
+        row.read_from_regs(regs, pos);
+
+        self.back =
+            self.ex.middle.swap(
+                self.back | FRESH, std::sync::atomic::Ordering::AcqRel)
+            & !FRESH;
+    }
+}
+
+impl SampleRowReader {
+    /// Returns the latest published row, if there was a new
+    /// one since the last call.
+    pub fn fetch(&mut self) -> Option<&mut SampleRow> {
+        if self.ex.middle.load(std::sync::atomic::Ordering::Relaxed) & FRESH == 0 {
+            return None;
+        }
+
+        self.front =
+            self.ex.middle.swap(
+                self.front, std::sync::atomic::Ordering::AcqRel)
+            & !FRESH;
+        Some(unsafe { &mut *self.ex.rows[self.front].get() })
+    }
+}
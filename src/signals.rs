@@ -1,4 +1,9 @@
-use crate::sample_row::SampleRow;
+use crate::sample_row::{SampleRowWriter, SampleRowReader, new_sample_row_exchange};
+use crate::ringbuf::{Producer, Consumer, ring_buffer};
+use crate::patch::{Patch, PatchOp};
+use crate::registry::OpRegistry;
+use crate::smoothing::{Smoothing, InputRamp};
+use crate::expr::{Expr, ExprId, ExprArena, ExprError};
 use serde::Serialize;
 use serde::Deserialize;
 
@@ -11,12 +16,92 @@ pub enum OpIn {
     RegMul(usize,f32),
     RegAddMul(usize,f32,f32),
     RegMulAdd(usize,f32,f32),
-    RegLerp(usize,f32,f32),
+    /// `a * x + b * (1 - x)`, which maps 0.0 to `b` and 1.0 to `a`.
+    /// Saved as `RegLerp`.
+    #[deprecated(note = "maps reversed, use OpIn::RegLin(i, 0.0, 1.0, a, b, false)")]
+    #[serde(rename = "RegLerp")]
+    RegLerpRev(usize,f32,f32),
     RegSStep(usize,f32,f32),
-    RegMap(usize,f32,f32,f32,f32),
+    /// `(reg, a_frm, b_frm, a_to, b_to)`, maps `a_frm` to `b_to` and
+    /// `b_frm` to `a_to`. Saved as `RegMap`.
+    #[deprecated(note = "maps reversed, use OpIn::RegLin")]
+    #[serde(rename = "RegMap")]
+    RegMapRev(usize,f32,f32,f32,f32),
+    /// `(reg, a_frm, b_frm, a_to, b_to, clamp)`: Maps `a_frm` to `a_to`
+    /// and `b_frm` to `b_to` linearly. With `clamp` the result stays
+    /// within `a_to` and `b_to`.
+    RegLin(usize,f32,f32,f32,f32,bool),
+    /// Like `RegLin`, but `a_to` to `b_to` is covered exponentially,
+    /// so that equal steps of the register give equal ratios of
+    /// the result. Meant for frequencies and amplitudes.
+    /// `a_to` and `b_to` must have the same sign and not be zero,
+    /// otherwise this maps linearly.
+    RegExp(usize,f32,f32,f32,f32,bool),
+    /// The inverse of `RegExp`: equal ratios of the register within
+    /// `a_frm` and `b_frm` give equal steps of the result, like
+    /// mapping a frequency to a linear control value.
+    /// `a_frm` and `b_frm` must have the same sign and not be zero,
+    /// otherwise this maps linearly.
+    RegLog(usize,f32,f32,f32,f32,bool),
+    /// An expression over any number of registers, kept by the
+    /// `Simulator`, see `Simulator::parse_expr`. It is evaluated by the
+    /// `Simulator`, which passes its value to `Op::exec` as
+    /// `OpIn::Constant`.
+    Expr(ExprId),
+}
+
+/// Calls `f` with every register index `op_in` reads from,
+/// including the ones of an expression in `exprs`.
+fn for_each_input_reg<F>(exprs: &ExprArena, op_in: &OpIn, f: F) where F: FnMut(usize) {
+    match *op_in {
+        OpIn::Expr(id) => { if let Some(e) = exprs.get(id) { e.for_each_reg(f) } },
+        _              => op_in.for_each_reg(f),
+    }
+}
+
+/// Whether `a` and `b` read the same set of registers.
+fn reads_same_regs(exprs: &ExprArena, a: &OpIn, b: &OpIn) -> bool {
+    let contains = |op_in: &OpIn, r: usize| {
+        let mut found = false;
+        for_each_input_reg(exprs, op_in, |q| found |= q == r);
+        found
+    };
+
+    let mut same = true;
+    for_each_input_reg(exprs, a, |r| same &= contains(b, r));
+    for_each_input_reg(exprs, b, |r| same &= contains(a, r));
+    same
+}
+
+/// Position of `v` within `a` to `b`, 0.0 at `a` and 1.0 at `b`.
+fn range_pos(v: f32, a: f32, b: f32, clamp: bool) -> f32 {
+    let x = if b == a { 0.0 } else { (v - a) / (b - a) };
+    if clamp { x.clamp(0.0, 1.0) } else { x }
+}
+
+fn exp_range_pos(v: f32, a: f32, b: f32, clamp: bool) -> f32 {
+    if a * b <= 0.0 || a == b {
+        return range_pos(v, a, b, clamp);
+    }
+
+    // Values on the other side of zero are treated as far below the range.
+    let x = (v / a).max(f32::MIN_POSITIVE).ln() / (b / a).ln();
+    if clamp { x.clamp(0.0, 1.0) } else { x }
+}
+
+fn exp_range_value(x: f32, a: f32, b: f32) -> f32 {
+    if a * b <= 0.0 {
+        a + (b - a) * x
+    } else {
+        a * (b / a).powf(x)
+    }
 }
 
 impl OpIn {
+    /// Evaluates the input. An `OpIn::Expr` evaluates to 0.0 here, as
+    /// only the `Simulator` knows the expression, see
+    /// `Simulator::calc_input`.
+    #[allow(deprecated)]
     pub fn calc(&self, regs: &[f32]) -> f32 {
         match self {
             OpIn::Constant(v)            => *v,
@@ -26,19 +111,254 @@ impl OpIn {
             OpIn::RegMul(i, v)           => v * regs[*i],
             OpIn::RegAddMul(i, a, v)     => v * (regs[*i] + a),
             OpIn::RegMulAdd(i, v, a)     => (v * regs[*i]) + a,
-            OpIn::RegLerp(i, a, b)       => (a * regs[*i]) + (b * (1.0 - regs[*i])),
+            OpIn::RegLerpRev(i, a, b)    => (a * regs[*i]) + (b * (1.0 - regs[*i])),
             OpIn::RegSStep(i, a, b)      => {
                 let x = (regs[*i] - a) / (b - a);
                 let x = if x < 0.0 { 0.0 } else { x };
                 let x = if x > 1.0 { 1.0 } else { x };
                 x * x * (3.0 - 2.0 * x)
             },
-            OpIn::RegMap(i, a_frm, b_frm, a_to, b_to) => {
+            OpIn::RegMapRev(i, a_frm, b_frm, a_to, b_to) => {
                 let x = (regs[*i] - a_frm) / (b_frm - a_frm);
                 (a_to * x) + (b_to * (1.0 - x))
             },
+            OpIn::RegLin(i, a_frm, b_frm, a_to, b_to, clamp) => {
+                let x = range_pos(regs[*i], *a_frm, *b_frm, *clamp);
+                a_to + (b_to - a_to) * x
+            },
+            OpIn::RegExp(i, a_frm, b_frm, a_to, b_to, clamp) => {
+                let x = range_pos(regs[*i], *a_frm, *b_frm, *clamp);
+                exp_range_value(x, *a_to, *b_to)
+            },
+            OpIn::RegLog(i, a_frm, b_frm, a_to, b_to, clamp) => {
+                let x = exp_range_pos(regs[*i], *a_frm, *b_frm, *clamp);
+                a_to + (b_to - a_to) * x
+            },
+            OpIn::Expr(_)                => 0.0,
+        }
+    }
+
+    /// Calls `f` with every register index this input reads from.
+    /// The registers of an `OpIn::Expr` are not known here.
+    #[allow(deprecated)]
+    pub fn for_each_reg<F>(&self, mut f: F) where F: FnMut(usize) {
+        match self {
+            OpIn::Constant(_)               => (),
+            OpIn::Reg(i)                    => f(*i),
+            OpIn::RegMix2(ia, ib, _)        => { f(*ia); f(*ib); },
+            OpIn::RegAdd(i, _)              => f(*i),
+            OpIn::RegMul(i, _)              => f(*i),
+            OpIn::RegAddMul(i, _, _)        => f(*i),
+            OpIn::RegMulAdd(i, _, _)        => f(*i),
+            OpIn::RegLerpRev(i, _, _)       => f(*i),
+            OpIn::RegSStep(i, _, _)         => f(*i),
+            OpIn::RegMapRev(i, _, _, _, _)  => f(*i),
+            OpIn::RegLin(i, _, _, _, _, _)  => f(*i),
+            OpIn::RegExp(i, _, _, _, _, _)  => f(*i),
+            OpIn::RegLog(i, _, _, _, _, _)  => f(*i),
+            OpIn::Expr(_)                   => (),
+        }
+    }
+
+    /// Returns a copy of this input with all register indices mapped
+    /// through `f`. Returns `None` if `f` could not map one of them.
+    /// An `OpIn::Expr` is returned as it is.
+    #[allow(deprecated)]
+    pub fn map_regs<F>(&self, mut f: F) -> Option<OpIn>
+        where F: FnMut(usize) -> Option<usize> {
+
+        Some(match *self {
+            OpIn::Constant(v)               => OpIn::Constant(v),
+            OpIn::Reg(i)                    => OpIn::Reg(f(i)?),
+            OpIn::RegMix2(ia, ib, am)       => OpIn::RegMix2(f(ia)?, f(ib)?, am),
+            OpIn::RegAdd(i, v)              => OpIn::RegAdd(f(i)?, v),
+            OpIn::RegMul(i, v)              => OpIn::RegMul(f(i)?, v),
+            OpIn::RegAddMul(i, a, v)        => OpIn::RegAddMul(f(i)?, a, v),
+            OpIn::RegMulAdd(i, v, a)        => OpIn::RegMulAdd(f(i)?, v, a),
+            OpIn::RegLerpRev(i, a, b)       => OpIn::RegLerpRev(f(i)?, a, b),
+            OpIn::RegSStep(i, a, b)         => OpIn::RegSStep(f(i)?, a, b),
+            OpIn::RegMapRev(i, a_frm, b_frm, a_to, b_to) =>
+                OpIn::RegMapRev(f(i)?, a_frm, b_frm, a_to, b_to),
+            OpIn::RegLin(i, a_frm, b_frm, a_to, b_to, clamp) =>
+                OpIn::RegLin(f(i)?, a_frm, b_frm, a_to, b_to, clamp),
+            OpIn::RegExp(i, a_frm, b_frm, a_to, b_to, clamp) =>
+                OpIn::RegExp(f(i)?, a_frm, b_frm, a_to, b_to, clamp),
+            OpIn::RegLog(i, a_frm, b_frm, a_to, b_to, clamp) =>
+                OpIn::RegLog(f(i)?, a_frm, b_frm, a_to, b_to, clamp),
+            OpIn::Expr(id)                  => OpIn::Expr(id),
+        })
+    }
+}
+
+/// An `OpIn` that refers to registers by name instead of by index.
+/// The register indices in `op_in` are indices into `regs`, which
+/// contains the register names in the form `"op_name.output_name"`.
+/// This form stays valid if ops are added, removed or reordered and is
+/// resolved to register indices by `Simulator::resolve_op_in`.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct NamedOpIn {
+    pub op_in: OpIn,
+    pub regs:  Vec<String>,
+    /// The source of an expression, with the registers as `r<index>`
+    /// into `regs`. It takes the place of `op_in`, which then holds
+    /// the value of the expression when it was named.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expr:  Option<String>,
+}
+
+impl NamedOpIn {
+    pub fn new(op_in: OpIn, regs: &[&str]) -> Self {
+        NamedOpIn {
+            op_in,
+            regs: regs.iter().map(|r| r.to_string()).collect(),
+            expr: None,
         }
     }
+
+    /// An expression, in which `r0` refers to the first of `regs`.
+    pub fn from_expr(src: &str, regs: &[&str]) -> Self {
+        NamedOpIn {
+            expr: Some(src.to_string()),
+            ..NamedOpIn::new(OpIn::Constant(0.0), regs)
+        }
+    }
+
+    /// Resolves the register names with `resolve_reg`. An expression
+    /// is compiled, but not yet added to a `Simulator`.
+    pub fn resolve<F>(&self, mut resolve_reg: F) -> Result<InputValue, SimulatorError>
+        where F: FnMut(&str) -> Result<usize, SimulatorError> {
+
+        let mut regs = Vec::with_capacity(self.regs.len());
+        for name in self.regs.iter() {
+            regs.push(resolve_reg(name)?);
+        }
+
+        let mut error = None;
+        let mut map = |i: usize| {
+            let r = regs.get(i).copied();
+            if r.is_none() { error = Some(SimulatorError::RegOutOfRange(i)); }
+            r
+        };
+
+        let value =
+            match (&self.expr, self.op_in) {
+                (Some(src), _) => {
+                    let expr = Expr::parse(src).map_err(SimulatorError::BadExpr)?;
+                    expr.map_regs(&mut map).map(InputValue::Expr)
+                },
+                (None, OpIn::Expr(id)) =>
+                    return Err(SimulatorError::UnknownExpr(id)),
+                (None, op_in) => op_in.map_regs(&mut map).map(InputValue::OpIn),
+            };
+
+        value.ok_or_else(|| error.unwrap())
+    }
+}
+
+/// A resolved `NamedOpIn`, see `NamedOpIn::resolve`.
+#[derive(Debug, PartialEq, Clone)]
+pub enum InputValue {
+    OpIn(OpIn),
+    /// An expression, to be added with `Simulator::add_expr`.
+    Expr(Expr),
+}
+
+/// Input values by input name, by op name.
+pub type SerializedInputs = Vec<(String, Vec<(String, NamedOpIn)>)>;
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub enum SimulatorError {
+    /// There is no op with that name.
+    UnknownOp(String),
+    /// The op (first) has no output with that name (second).
+    UnknownOutput(String, String),
+    /// A register name that is not of the form `"op_name.output_name"`.
+    BadRegName(String),
+    /// A register index that does not refer to any register.
+    RegOutOfRange(usize),
+    /// The op (first) has no input with that name (second).
+    UnknownInput(String, String),
+    /// There is no op type with that name in the `OpRegistry`.
+    UnknownOpType(String),
+    /// The op (first) did not take the type arguments (second), it was
+    /// created with the ones its `Op::type_args` returns instead.
+    BadTypeArgs(String, Vec<usize>),
+    /// There is no group with that name.
+    UnknownGroup(String),
+    /// There is no group with that index.
+    GroupIndexOutOfRange(usize),
+    /// There is no op with that index.
+    OpIndexOutOfRange(usize),
+    /// The op (first) has no input with that index (second).
+    InputIndexOutOfRange(String, usize),
+    /// The other side of a `SimulatorCommunicator` is gone.
+    Disconnected,
+    /// The queue to the other side of a `SimulatorCommunicator` is full.
+    QueueFull,
+    /// An expression for `OpIn::Expr` could not be parsed.
+    BadExpr(ExprError),
+    /// The `Simulator` has no expression with that id.
+    UnknownExpr(ExprId),
+}
+
+impl std::fmt::Display for SimulatorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SimulatorError::UnknownOp(op) =>
+                write!(f, "Unknown op '{}'", op),
+            SimulatorError::UnknownOutput(op, out) =>
+                write!(f, "Op '{}' has no output '{}'", op, out),
+            SimulatorError::BadRegName(name) =>
+                write!(f, "Bad register name '{}', expected 'op_name.output_name'", name),
+            SimulatorError::RegOutOfRange(idx) =>
+                write!(f, "Register index {} out of range", idx),
+            SimulatorError::UnknownInput(op, inp) =>
+                write!(f, "Op '{}' has no input '{}'", op, inp),
+            SimulatorError::UnknownOpType(typ) =>
+                write!(f, "Unknown op type '{}'", typ),
+            SimulatorError::BadTypeArgs(op, args) =>
+                write!(f, "Op '{}' does not take the type arguments {:?}", op, args),
+            SimulatorError::UnknownGroup(grp) =>
+                write!(f, "Unknown group '{}'", grp),
+            SimulatorError::GroupIndexOutOfRange(idx) =>
+                write!(f, "Group index {} out of range", idx),
+            SimulatorError::OpIndexOutOfRange(idx) =>
+                write!(f, "Op index {} out of range", idx),
+            SimulatorError::InputIndexOutOfRange(op, idx) =>
+                write!(f, "Op '{}' has no input with index {}", op, idx),
+            SimulatorError::Disconnected =>
+                write!(f, "Communication peer disconnected"),
+            SimulatorError::QueueFull =>
+                write!(f, "Communication queue full"),
+            SimulatorError::BadExpr(e) =>
+                write!(f, "{}", e),
+            SimulatorError::UnknownExpr(id) =>
+                write!(f, "Unknown expression {:?}", id),
+        }
+    }
+}
+
+impl std::error::Error for SimulatorError { }
+
+/// Lowest level of a `PortScale::Db` port above its `min`,
+/// relative to its `max`.
+pub const PORT_DB_FLOOR : f32 = -60.0;
+
+/// How the range of an `OpPort` maps to normalized values and is
+/// displayed, see `OpPort::normalize` and `OpPort::format_value`.
+#[derive(Debug, PartialEq, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum PortScale {
+    #[default]
+    Lin,
+    /// Equal steps of the normalized value give equal ratios, like
+    /// for frequencies. Needs `min` and `max` with the same sign and
+    /// not zero, otherwise the range is scaled linearly.
+    Log,
+    /// The values are amplitudes, displayed in decibels. Normalized
+    /// values are linear in decibels from `PORT_DB_FLOOR` below `max`
+    /// (or `min`, if it is above that) up to `max`.
+    /// A normalized 0.0 is `min`, which may be silence.
+    Db,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
@@ -46,14 +366,164 @@ pub struct OpPort {
     pub min: f32,
     pub max: f32,
     pub name: String,
+    /// Default smoothing of changes of this input,
+    /// see also `Simulator::set_input_smoothing`.
+    #[serde(default)]
+    pub smoothing: Smoothing,
+    #[serde(default)]
+    pub scale: PortScale,
+    /// Unit of the values, like `"Hz"`, `"s"` or `"rad"`.
+    /// Empty for plain numbers.
+    #[serde(default)]
+    pub unit: String,
+    /// The values are multiples of `step` above `min`.
+    /// 0.0 for continuous values.
+    #[serde(default)]
+    pub step: f32,
+    /// Names of the values `min`, `min + 1.0`, ... for enumerated inputs,
+    /// see `with_choices`.
+    #[serde(default)]
+    pub choices: Vec<String>,
+    #[serde(default)]
+    pub description: String,
 }
 
 impl OpPort {
     pub fn new(name: &str, min: f32, max: f32) -> Self {
-        OpPort { name: name.to_string(), min, max }
+        OpPort {
+            name: name.to_string(),
+            min,
+            max,
+            smoothing: Smoothing::None,
+            scale: PortScale::Lin,
+            unit: String::new(),
+            step: 0.0,
+            choices: Vec::new(),
+            description: String::new(),
+        }
+    }
+
+    pub fn with_smoothing(mut self, smoothing: Smoothing) -> Self {
+        self.smoothing = smoothing;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: PortScale) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn with_unit(mut self, unit: &str) -> Self {
+        self.unit = unit.to_string();
+        self
+    }
+
+    pub fn with_step(mut self, step: f32) -> Self {
+        self.step = step;
+        self
+    }
+
+    /// Makes this an enumerated input with the values 0.0 to
+    /// `choices.len() - 1`.
+    pub fn with_choices(mut self, choices: &[&str]) -> Self {
+        self.choices = choices.iter().map(|c| c.to_string()).collect();
+        self.min     = 0.0;
+        self.max     = (choices.len().max(1) - 1) as f32;
+        self.step    = 1.0;
+        self
+    }
+
+    pub fn with_description(mut self, description: &str) -> Self {
+        self.description = description.to_string();
+        self
+    }
+
+    /// Limits `v` to `min` and `max`.
+    pub fn clamp(&self, v: f32) -> f32 {
+        v.max(self.min).min(self.max)
+    }
+
+    /// Rounds `v` to the nearest multiple of `step` above `min`.
+    pub fn quantize(&self, v: f32) -> f32 {
+        if self.step <= 0.0 { return v; }
+        self.min + ((v - self.min) / self.step).round() * self.step
+    }
+
+    /// The lowest amplitude of the decibel range of `PortScale::Db`.
+    fn db_floor(&self) -> f32 {
+        self.min.max(self.max * 10.0_f32.powf(PORT_DB_FLOOR / 20.0))
+    }
+
+    /// Maps `v` within `min` and `max` to 0.0 to 1.0, according
+    /// to `scale`. Values outside the range are clamped.
+    pub fn normalize(&self, v: f32) -> f32 {
+        match self.scale {
+            PortScale::Lin => range_pos(v, self.min, self.max, true),
+            PortScale::Log => exp_range_pos(v, self.min, self.max, true),
+            PortScale::Db  =>
+                if v <= self.min { 0.0 }
+                else { exp_range_pos(v, self.db_floor(), self.max, true) },
+        }
+    }
+
+    /// The inverse of `normalize`, quantized to `step`.
+    pub fn denormalize(&self, x: f32) -> f32 {
+        let x = x.clamp(0.0, 1.0);
+        let v =
+            match self.scale {
+                PortScale::Lin => self.min + (self.max - self.min) * x,
+                PortScale::Log => exp_range_value(x, self.min, self.max),
+                PortScale::Db  =>
+                    if x <= 0.0 { self.min }
+                    else { exp_range_value(x, self.db_floor(), self.max) },
+            };
+        self.clamp(self.quantize(v))
+    }
+
+    /// Returns an input that maps the normalized value in the
+    /// register `reg` to this port's range, for automation.
+    /// It is not quantized to `step`, and for `PortScale::Db`
+    /// 0.0 maps to the floor level instead of `min`.
+    pub fn normalized_reg(&self, reg: usize) -> OpIn {
+        match self.scale {
+            PortScale::Lin => OpIn::RegLin(reg, 0.0, 1.0, self.min, self.max, true),
+            PortScale::Log => OpIn::RegExp(reg, 0.0, 1.0, self.min, self.max, true),
+            PortScale::Db  => OpIn::RegExp(reg, 0.0, 1.0, self.db_floor(), self.max, true),
+        }
+    }
+
+    /// Formats `v` for display, as the name of the choice, in
+    /// decibels for `PortScale::Db`, or with the `unit`.
+    pub fn format_value(&self, v: f32) -> String {
+        if !self.choices.is_empty() {
+            let i = (v - self.min).round().max(0.0) as usize;
+            if let Some(c) = self.choices.get(i) {
+                return c.clone();
+            }
+        }
+
+        let s =
+            match self.scale {
+                PortScale::Db if v <= 0.0 => "-inf dB".to_string(),
+                PortScale::Db => format!("{:.1} dB", 20.0 * v.log10()),
+                _ if self.step >= 1.0 => format!("{}", v.round()),
+                _ => format!("{:.3}", v),
+            };
+
+        if self.unit.is_empty() { s } else { format!("{} {}", s, self.unit) }
     }
 }
 
+/// A source register that is added onto an op input, scaled by `depth`.
+/// See also `Simulator::set_op_modulation`.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct Modulation {
+    /// Index of the modulated input in `OpIOSpec::inputs`.
+    pub input:  usize,
+    pub source: usize,
+    pub depth:  f32,
+}
+
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct OpIOSpec {
     pub index:              usize,
@@ -65,29 +535,165 @@ pub struct OpIOSpec {
     pub output_regs:        Vec<usize>,
 }
 
+/// Default for `Simulator::sample_rate`.
+pub const DEFAULT_SAMPLE_RATE  : f32 = 44100.0;
+/// Default for `Simulator::control_rate`.
+pub const DEFAULT_CONTROL_RATE : f32 = DEFAULT_SAMPLE_RATE / 64.0;
+
+/// Number of events `Simulator::schedule_event` can hold
+/// without allocating.
+pub const DEFAULT_EVENT_QUEUE_SIZE : usize = 256;
+/// Number of expressions a `Simulator` can hold without allocating.
+pub const DEFAULT_EXPR_CAPACITY : usize = 256;
+
+/// Timing information passed to `Op::exec`.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct ExecContext {
+    /// Number of `exec` ticks since the start of the `Simulator`.
+    pub tick:           u64,
+    /// Time of this tick in seconds.
+    pub time:           f64,
+    /// Seconds since the previous tick. This is `1.0 / control_rate`,
+    /// unless `Simulator::process` executed the ops early for an event.
+    /// Ops that advance over time should advance by `dt` at the start
+    /// of `Op::exec`.
+    pub dt:             f32,
+    /// `exec` ticks per second.
+    pub control_rate:   f32,
+    /// Audio samples per second, as used by `Op::render`.
+    pub sample_rate:    f32,
+}
+
+impl ExecContext {
+    /// Number of samples of a regular tick, `sample_rate / control_rate`.
+    pub fn samples_per_tick(&self) -> usize {
+        (self.sample_rate / self.control_rate).round().max(1.0) as usize
+    }
+}
+
+/// The kinds of `Event` an op can react to. Velocities, controller
+/// values and pressures are normalized to the range 0.0 to 1.0,
+/// the pitch bend to -1.0 to 1.0. Channels are counted from 0.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum EventKind {
+    NoteOn          { channel: u8, note: u8, velocity: f32 },
+    NoteOff         { channel: u8, note: u8, velocity: f32 },
+    ControlChange   { channel: u8, controller: u8, value: f32 },
+    PitchBend       { channel: u8, value: f32 },
+    /// Channel pressure.
+    Aftertouch      { channel: u8, value: f32 },
+    /// Polyphonic key pressure.
+    PolyAftertouch  { channel: u8, note: u8, value: f32 },
+    /// Transport start.
+    Start,
+    /// Transport stop.
+    Stop,
+    /// Transport continues from where it was stopped.
+    Continue,
+    /// Clock tick, 24 per quarter note like MIDI clock.
+    Clock,
+}
+
+/// An event that is passed to `Op::event` by `Simulator::event`.
 #[derive(Debug, PartialEq, Copy, Clone)]
-pub enum Event {
-    NoteOn(u8),
-    NoteOff(u8),
+pub struct Event {
+    /// Position of the event in samples, relative to the start
+    /// of the block that is rendered next.
+    pub sample_offs: usize,
+    pub kind:        EventKind,
+}
+
+impl Event {
+    /// Creates an event at the start of the next rendered block.
+    pub fn new(kind: EventKind) -> Self {
+        Event { sample_offs: 0, kind }
+    }
+
+    /// Creates an event `sample_offs` samples into the next rendered block.
+    pub fn at(sample_offs: usize, kind: EventKind) -> Self {
+        Event { sample_offs, kind }
+    }
+
+    pub fn note_on(channel: u8, note: u8, velocity: f32) -> Self {
+        Self::new(EventKind::NoteOn { channel, note, velocity })
+    }
+
+    pub fn note_off(channel: u8, note: u8, velocity: f32) -> Self {
+        Self::new(EventKind::NoteOff { channel, note, velocity })
+    }
+
+    /// The channel of channel events, `None` for transport and clock events.
+    pub fn channel(&self) -> Option<u8> {
+        match self.kind {
+            EventKind::NoteOn         { channel, .. }
+          | EventKind::NoteOff        { channel, .. }
+          | EventKind::ControlChange  { channel, .. }
+          | EventKind::PitchBend      { channel, .. }
+          | EventKind::Aftertouch     { channel, .. }
+          | EventKind::PolyAftertouch { channel, .. } => Some(channel),
+            EventKind::Start
+          | EventKind::Stop
+          | EventKind::Continue
+          | EventKind::Clock => None,
+        }
+    }
 }
 
 pub trait Op {
+    /// The type name this op is registered with in the `OpRegistry`.
+    fn type_name(&self) -> &'static str;
+    /// Structural arguments the op was constructed with, which are
+    /// passed to the `OpRegistry` constructor when loading a `Patch`.
+    fn type_args(&self) -> Vec<usize> { Vec::new() }
+
     fn io_spec(&self, index: usize) -> OpIOSpec;
 
     fn init_regs(&mut self, start_reg: usize, regs: &mut [f32]);
 
-    fn get_output_reg(&mut self, name: &str) -> Option<usize>;
-    fn set_input(&mut self, name: &str, to: OpIn, as_default: bool) -> bool;
-    fn exec(&mut self, t: f32, regs: &mut [f32]);
+    /// Returns the register of the output at `idx` in `OpIOSpec::outputs`.
+    fn output_reg(&self, idx: usize) -> Option<usize>;
+    /// Sets the input at `idx` in `OpIOSpec::inputs`.
+    fn set_input_by_index(&mut self, idx: usize, to: OpIn, as_default: bool) -> bool;
+    fn exec(&mut self, ctx: &ExecContext, regs: &mut [f32]);
+
+    /// Maps an input name to its index. Ops should override this
+    /// with a lookup that does not need to allocate an `OpIOSpec`.
+    fn input_index(&self, name: &str) -> Option<usize> {
+        self.io_spec(0).inputs.iter().position(|p| p.name == name)
+    }
+
+    /// Maps an output name to its index. Ops should override this
+    /// with a lookup that does not need to allocate an `OpIOSpec`.
+    fn output_index(&self, name: &str) -> Option<usize> {
+        self.io_spec(0).outputs.iter().position(|p| p.name == name)
+    }
+
+    fn get_output_reg(&mut self, name: &str) -> Option<usize> {
+        self.output_index(name).and_then(|idx| self.output_reg(idx))
+    }
+
+    /// Returns the current value of the input at `idx`. Ops should
+    /// override this with a version that does not need to allocate
+    /// an `OpIOSpec`.
+    fn input_value(&self, idx: usize) -> Option<OpIn> {
+        self.io_spec(0).input_values.get(idx).copied()
+    }
+
+    fn set_input(&mut self, name: &str, to: OpIn, as_default: bool) -> bool {
+        match self.input_index(name) {
+            Some(idx) => self.set_input_by_index(idx, to, as_default),
+            None      => false,
+        }
+    }
 
     fn does_render(&self) -> bool { false }
-    fn render(&mut self, _num_samples: usize, _offs: usize, _input_idx: usize, _bufs: &mut Vec<Vec<f32>>) { }
+    fn render(&mut self, _num_samples: usize, _offs: usize, _input_idx: usize, _bufs: &mut [Vec<f32>]) { }
     fn event(&mut self, _ev: &Event) { }
 
     fn input_count(&self) -> usize { self.io_spec(0).inputs.len() }
     fn output_count(&self) -> usize { self.io_spec(0).outputs.len() }
 
-    fn deserialize_inputs(&mut self, inputs: &Vec<(String, OpIn)>) {
+    fn deserialize_inputs(&mut self, inputs: &[(String, OpIn)]) {
         for (p, v) in inputs.iter() { self.set_input(p, *v, false); }
     }
 
@@ -100,203 +706,1146 @@ pub trait Op {
                 .collect();
         vals
     }
+
+    /// Ops that the host application needs to reach after they were
+    /// created by the `OpRegistry`, like `OutProxy`, return `Some(self)`
+    /// here. See also `Simulator::get_op_as`.
+    fn as_any(&self) -> Option<&dyn std::any::Any> { None }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct OpGroup {
     pub name: String,
     pub index: usize,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct OpInfo {
     pub name:  String,
     pub does_render: bool,
     pub group: OpGroup,
 }
 
+/// Interned input name, see `SimulatorCommunicator::set_op_input`.
+/// The `SimulatorCommunicator` keeps a reference to every name it
+/// interned, so the audio thread never frees one.
+pub type InputName = std::sync::Arc<str>;
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum SimulatorUIInput {
     Refresh,
-    SetOpInput(usize, String, OpIn, bool),
+    SetOpInput(usize, InputName, OpIn, bool),
+    /// Like `SetOpInput`, but addresses the input by its index.
+    SetOpInputIdx(usize, usize, OpIn, bool),
+    /// Like `SetOpInput`, but with an expression for `Simulator::add_expr`.
+    SetOpInputExpr(usize, InputName, Expr, bool),
+    /// `(op, input_idx, source_reg, depth)`, see `Simulator::set_op_modulation`.
+    SetOpModulation(usize, usize, usize, f32),
+    /// `(op, input_idx, source_reg)`
+    RemoveOpModulation(usize, usize, usize),
     SaveInputs,
-    LoadInputs(Vec<(String, Vec<(String, OpIn)>)>),
+    /// `(op, input_idx, value)`, as resolved by
+    /// `SimulatorCommunicator::load_input_values`.
+    LoadInputs(Vec<(usize, usize, InputValue)>),
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub enum SimulatorUIEvent {
-    OpSpecUpdate(Vec<(OpIOSpec, OpInfo)>),
-    SerializedInputValues(Vec<(String, Vec<(String, OpIn)>)>),
+    /// See `Simulator::get_specs`.
+    OpSpecUpdate(Vec<(OpIOSpec, OpInfo, Vec<Modulation>)>),
+    SerializedInputValues(SerializedInputs),
+    /// A `SimulatorUIInput` could not be applied.
+    Error(SimulatorError),
+}
+
+/// Heap memory the audio thread hands back to the UI thread,
+/// to be freed there.
+// The values are never read, only dropped.
+#[allow(dead_code)]
+enum Garbage {
+    /// An expression the `Simulator` no longer needs.
+    Expr(Expr),
+    /// The emptied values of a `SimulatorUIInput::LoadInputs`.
+    Inputs(Vec<(usize, usize, InputValue)>),
 }
 
+/// Default for `SimulatorCommunicatorEndpoint::set_message_budget`.
+pub const DEFAULT_UI_MESSAGE_BUDGET : usize = 64;
+/// Default number of messages that fit into the queues between
+/// `SimulatorCommunicator` and `SimulatorCommunicatorEndpoint`.
+pub const DEFAULT_UI_QUEUE_SIZE : usize = 1024;
+
+/// The audio thread side of a `SimulatorCommunicator`.
+///
+/// The messages are passed through preallocated lock free ring buffers.
+/// Setting and loading inputs neither allocates nor locks: names and
+/// expressions are resolved on the UI thread, and the heap memory of the
+/// messages and of replaced expressions is handed back to the UI thread
+/// to be freed there. Only the replies to `Refresh` and `SaveInputs`,
+/// error reports, a new modulation source and more expressions than
+/// `DEFAULT_EXPR_CAPACITY` allocate.
 #[derive(Debug)]
 pub struct SimulatorCommunicatorEndpoint {
-    tx: std::sync::mpsc::Sender<SimulatorUIEvent>,
-    rx: std::sync::mpsc::Receiver<SimulatorUIInput>,
+    tx:             Producer<SimulatorUIEvent>,
+    rx:             Consumer<SimulatorUIInput>,
+    recycle:        Producer<Garbage>,
+    batch:          Vec<SimulatorUIInput>,
+    message_budget: usize,
 }
 
 impl SimulatorCommunicatorEndpoint {
-    pub fn handle_ui_messages(&mut self, sim: &mut Simulator)
-    {
-        let r = self.rx.try_recv();
-        match r {
-            Ok(SimulatorUIInput::SetOpInput(idx, in_name, op_in, def)) => {
+    /// Sets the maximum number of messages `handle_ui_messages`
+    /// receives per call. Should not be called from the audio thread,
+    /// as it may allocate.
+    pub fn set_message_budget(&mut self, budget: usize) {
+        self.message_budget = budget.max(1);
+        self.batch.reserve(self.message_budget);
+    }
+
+    /// Applies all pending messages from the UI to `sim`, up to the
+    /// message budget. Repeated `SimulatorUIInput::SetOpInput` for the
+    /// same op input are coalesced, so only the latest value is applied.
+    /// Errors from applying the messages are sent back to the UI as
+    /// `SimulatorUIEvent::Error`.
+    /// Returns `SimulatorError::Disconnected` if the UI side is gone
+    /// and `SimulatorError::QueueFull` if the UI does not fetch the
+    /// events sent to it.
+    pub fn handle_ui_messages(&mut self, sim: &mut Simulator) -> Result<(), SimulatorError> {
+        // Only SetOpInput messages after the last other message may be
+        // coalesced, so that their order relative to LoadInputs is kept.
+        let mut coalesce_from = 0;
+
+        // Whether the batch after `coalesce_from` addresses an input of
+        // the op by name (`by_name`) or by index. A name and an index
+        // might address the same input, so such messages must stay
+        // in order.
+        let addresses = |batch: &[SimulatorUIInput], op: usize, by_name: bool| {
+            batch.iter().any(|m| match m {
+                SimulatorUIInput::SetOpInput(idx, _, _, _)       => by_name && *idx == op,
+                SimulatorUIInput::SetOpInputIdx(idx, _, _, _)    => !by_name && *idx == op,
+                _ => false,
+            })
+        };
+
+        for _ in 0..self.message_budget {
+            match self.rx.pop() {
+                Some(SimulatorUIInput::SetOpInput(idx, in_name, op_in, def)) => {
+                    if addresses(&self.batch[coalesce_from..], idx, false) {
+                        coalesce_from = self.batch.len();
+                    }
+
+                    let prev =
+                        self.batch[coalesce_from..].iter_mut().find(|m| {
+                            if let SimulatorUIInput::SetOpInput(p_idx, p_name, _, p_def) = m {
+                                *p_idx == idx && *p_name == in_name && *p_def == def
+                            } else {
+                                false
+                            }
+                        });
+
+                    if let Some(SimulatorUIInput::SetOpInput(_, _, p_op_in, _)) = prev {
+                        *p_op_in = op_in;
+                    } else {
+                        self.batch.push(SimulatorUIInput::SetOpInput(idx, in_name, op_in, def));
+                    }
+                },
+                Some(SimulatorUIInput::SetOpInputIdx(idx, in_idx, op_in, def)) => {
+                    if addresses(&self.batch[coalesce_from..], idx, true) {
+                        coalesce_from = self.batch.len();
+                    }
+
+                    let prev =
+                        self.batch[coalesce_from..].iter_mut().find(|m| {
+                            if let SimulatorUIInput::SetOpInputIdx(p_idx, p_in_idx, _, p_def) = m {
+                                *p_idx == idx && *p_in_idx == in_idx && *p_def == def
+                            } else {
+                                false
+                            }
+                        });
+
+                    if let Some(SimulatorUIInput::SetOpInputIdx(_, _, p_op_in, _)) = prev {
+                        *p_op_in = op_in;
+                    } else {
+                        self.batch.push(SimulatorUIInput::SetOpInputIdx(idx, in_idx, op_in, def));
+                    }
+                },
+                Some(msg) => {
+                    self.batch.push(msg);
+                    coalesce_from = self.batch.len();
+                },
+                None => break,
+            }
+        }
+
+        let mut batch = std::mem::take(&mut self.batch);
+        let mut ret = Ok(());
+        for msg in batch.drain(..) {
+            if let Err(e) = self.handle_message(sim, msg) {
+                if ret.is_ok() { ret = Err(e); }
+            }
+        }
+        self.batch = batch;
+
+        if ret.is_ok() && self.rx.is_abandoned() {
+            return Err(SimulatorError::Disconnected);
+        }
+
+        ret
+    }
+
+    fn handle_message(&mut self, sim: &mut Simulator, msg: SimulatorUIInput) -> Result<(), SimulatorError> {
+        match msg {
+            SimulatorUIInput::SetOpInput(idx, in_name, op_in, def) => {
                 //d// println!("SETINPUT: {}", in_name);
-                if !sim.set_op_input(idx, &in_name, op_in, def) {
-                    panic!(format!("Expected op input name {}/{}/{:?}", idx, in_name, op_in));
+                if let Err(e) = sim.set_op_input(idx, &in_name, op_in, def) {
+                    self.send(SimulatorUIEvent::Error(e))?;
                 }
             },
-            Ok(SimulatorUIInput::Refresh) => {
-                self.tx.send(SimulatorUIEvent::OpSpecUpdate(sim.get_specs()))
-                    .expect("communication with ui thread");
+            SimulatorUIInput::SetOpInputIdx(idx, in_idx, op_in, def) => {
+                if let Err(e) = sim.set_op_input_by_index(idx, in_idx, op_in, def) {
+                    self.send(SimulatorUIEvent::Error(e))?;
+                }
+            },
+            SimulatorUIInput::SetOpInputExpr(idx, in_name, expr, def) => {
+                let id = self.insert_expr(sim, expr);
+                let res = sim.set_op_input(idx, &in_name, OpIn::Expr(id), def);
+                sim.exprs.discard_unused(id);
+                if let Err(e) = res {
+                    self.send(SimulatorUIEvent::Error(e))?;
+                }
+            },
+            SimulatorUIInput::SetOpModulation(idx, in_idx, source, depth) => {
+                if let Err(e) = sim.set_op_modulation_by_index(idx, in_idx, source, depth) {
+                    self.send(SimulatorUIEvent::Error(e))?;
+                }
+            },
+            SimulatorUIInput::RemoveOpModulation(idx, in_idx, source) => {
+                sim.remove_op_modulation(idx, in_idx, source);
             },
-            Ok(SimulatorUIInput::LoadInputs(inputs)) => {
-                sim.deserialize_inputs(inputs);
+            SimulatorUIInput::Refresh => {
+                self.send(SimulatorUIEvent::OpSpecUpdate(sim.get_specs()))?;
+            },
+            SimulatorUIInput::LoadInputs(mut values) => {
+                let mut ret = Ok(());
+                for (idx, in_idx, value) in values.drain(..) {
+                    let res =
+                        match value {
+                            InputValue::OpIn(op_in) =>
+                                sim.load_input_value(idx, in_idx, op_in),
+                            InputValue::Expr(expr) => {
+                                let id  = self.insert_expr(sim, expr);
+                                let res = sim.load_input_value(idx, in_idx, OpIn::Expr(id));
+                                sim.exprs.discard_unused(id);
+                                res
+                            },
+                        };
+
+                    if let Err(e) = res {
+                        if ret.is_ok() { ret = self.send(SimulatorUIEvent::Error(e)); }
+                    }
+                }
+                // If the UI does not drain the recycled values,
+                // they are freed here.
+                let _ = self.recycle.push(Garbage::Inputs(values));
+                ret?;
             },
-            Ok(SimulatorUIInput::SaveInputs) => {
-                self.tx.send(SimulatorUIEvent::SerializedInputValues(
-                                sim.serialize_inputs()))
-                    .expect("communication with ui thread");
+            SimulatorUIInput::SaveInputs => {
+                self.send(SimulatorUIEvent::SerializedInputValues(
+                            sim.serialize_inputs()))?;
             },
-            Err(std::sync::mpsc::TryRecvError::Empty) => (),
-            Err(std::sync::mpsc::TryRecvError::Disconnected) => (),
         }
+
+        Ok(())
+    }
+
+    /// Adds `expr` to the expressions of `sim`. The expression that was
+    /// left in the reused slot is handed back to the UI thread.
+    fn insert_expr(&mut self, sim: &mut Simulator, expr: Expr) -> ExprId {
+        let (id, old) = sim.exprs.insert(expr);
+        if let Some(old) = old {
+            // If the UI does not drain the recycled values,
+            // it is freed here.
+            let _ = self.recycle.push(Garbage::Expr(old));
+        }
+        id
     }
 
+    fn send(&mut self, ev: SimulatorUIEvent) -> Result<(), SimulatorError> {
+        if self.tx.is_abandoned() {
+            return Err(SimulatorError::Disconnected);
+        }
+        self.tx.push(ev).map_err(|_| SimulatorError::QueueFull)
+    }
 }
 
+/// The UI thread side of the communication with the `Simulator`,
+/// see also `SimulatorCommunicatorEndpoint`.
 #[derive(Debug)]
 pub struct SimulatorCommunicator {
-    tx: std::sync::mpsc::Sender<SimulatorUIInput>,
-    rx: std::sync::mpsc::Receiver<SimulatorUIEvent>,
-    ep: Option<SimulatorCommunicatorEndpoint>,
+    tx:         Producer<SimulatorUIInput>,
+    rx:         Consumer<SimulatorUIEvent>,
+    recycled:   Consumer<Garbage>,
+    ep:         Option<SimulatorCommunicatorEndpoint>,
+    /// Events that were received while waiting for a reply,
+    /// they are passed on by the next `update`.
+    pending:    Vec<SimulatorUIEvent>,
+    names:      Vec<InputName>,
 }
 
 impl SimulatorCommunicator {
     pub fn new() -> Self {
-        let (simuiin_tx, simuiin_rx) = std::sync::mpsc::channel::<SimulatorUIInput>();
-        let (simuiev_tx, simuiev_rx) = std::sync::mpsc::channel::<SimulatorUIEvent>();
+        Self::with_queue_size(DEFAULT_UI_QUEUE_SIZE)
+    }
+
+    pub fn with_queue_size(queue_size: usize) -> Self {
+        let (simuiin_tx, simuiin_rx) = ring_buffer::<SimulatorUIInput>(queue_size);
+        let (simuiev_tx, simuiev_rx) = ring_buffer::<SimulatorUIEvent>(queue_size);
+        let (recycle_tx, recycle_rx) = ring_buffer::<Garbage>(queue_size);
 
         SimulatorCommunicator {
-            tx: simuiin_tx,
-            rx: simuiev_rx,
+            tx:         simuiin_tx,
+            rx:         simuiev_rx,
+            recycled:   recycle_rx,
             ep: Some(SimulatorCommunicatorEndpoint {
-                tx: simuiev_tx,
-                rx: simuiin_rx,
+                tx:             simuiev_tx,
+                rx:             simuiin_rx,
+                recycle:        recycle_tx,
+                batch:          Vec::with_capacity(DEFAULT_UI_MESSAGE_BUDGET),
+                message_budget: DEFAULT_UI_MESSAGE_BUDGET,
             }),
+            pending:    Vec::new(),
+            names:      Vec::new(),
         }
     }
 
     pub fn get_endpoint(&mut self) -> SimulatorCommunicatorEndpoint {
-        std::mem::replace(&mut self.ep, None)
+        self.ep.take()
         .expect("SimulatorCommunicatorEndpoint can only be retrieved once")
     }
 
-    pub fn set_op_input(&mut self, op_index: usize, input_name: &str, op_in: OpIn, as_default: bool) {
-        self.tx.send(SimulatorUIInput::SetOpInput(
-                        op_index, input_name.to_string(), op_in, as_default))
-            .expect("communication with backend thread");
+    fn send(&mut self, msg: SimulatorUIInput) -> Result<(), SimulatorError> {
+        while self.recycled.pop().is_some() { }
+
+        if self.tx.is_abandoned() {
+            return Err(SimulatorError::Disconnected);
+        }
+        self.tx.push(msg).map_err(|_| SimulatorError::QueueFull)
     }
 
-    pub fn save_input_values(&mut self) -> Vec<(String, Vec<(String, OpIn)>)> {
-        self.tx.send(SimulatorUIInput::SaveInputs)
-            .expect("communication with backend thread");
-        let r = self.rx.recv();
-        if let Ok(SimulatorUIEvent::SerializedInputValues(v)) = r {
-            v
-        } else {
-            vec![]
+    /// Waits for the next event from the endpoint.
+    fn recv(&mut self) -> Result<SimulatorUIEvent, SimulatorError> {
+        loop {
+            if let Some(ev) = self.rx.pop() {
+                return Ok(ev);
+            }
+            if self.rx.is_abandoned() {
+                return Err(SimulatorError::Disconnected);
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+
+    /// Returns the interned version of `name`.
+    pub fn intern_input_name(&mut self, name: &str) -> InputName {
+        if let Some(n) = self.names.iter().find(|n| &***n == name) {
+            return n.clone();
+        }
+
+        let n : InputName = name.into();
+        self.names.push(n.clone());
+        n
+    }
+
+    pub fn set_op_input(&mut self, op_index: usize, input_name: &str, op_in: OpIn, as_default: bool)
+        -> Result<(), SimulatorError> {
+
+        let input_name = self.intern_input_name(input_name);
+        self.send(SimulatorUIInput::SetOpInput(
+                    op_index, input_name, op_in, as_default))
+    }
+
+    /// Sets the input to the expression `expr`, which is compiled on
+    /// this thread with `Expr::parse`. Its registers are given as
+    /// `r<index>`. See also `Simulator::add_expr`.
+    pub fn set_op_input_expr(&mut self, op_index: usize, input_name: &str, expr: Expr, as_default: bool)
+        -> Result<(), SimulatorError> {
+
+        let input_name = self.intern_input_name(input_name);
+        self.send(SimulatorUIInput::SetOpInputExpr(
+                    op_index, input_name, expr, as_default))
+    }
+
+    /// Like `set_op_input`, but addresses the input by its index in
+    /// `OpIOSpec::inputs`, which is cheaper to apply for the `Simulator`.
+    pub fn set_op_input_idx(&mut self, op_index: usize, input_idx: usize, op_in: OpIn, as_default: bool)
+        -> Result<(), SimulatorError> {
+
+        self.send(SimulatorUIInput::SetOpInputIdx(
+                    op_index, input_idx, op_in, as_default))
+    }
+
+    /// See `Simulator::set_op_modulation_by_index`. The `Simulator`
+    /// only allocates for a new modulation source of an input.
+    pub fn set_op_modulation(&mut self, op_index: usize, input_idx: usize, source: usize, depth: f32)
+        -> Result<(), SimulatorError> {
+
+        self.send(SimulatorUIInput::SetOpModulation(op_index, input_idx, source, depth))
+    }
+
+    /// See `Simulator::remove_op_modulation`.
+    pub fn remove_op_modulation(&mut self, op_index: usize, input_idx: usize, source: usize)
+        -> Result<(), SimulatorError> {
+
+        self.send(SimulatorUIInput::RemoveOpModulation(op_index, input_idx, source))
+    }
+
+    pub fn save_input_values(&mut self) -> Result<SerializedInputs, SimulatorError> {
+        self.send(SimulatorUIInput::SaveInputs)?;
+        loop {
+            match self.recv()? {
+                SimulatorUIEvent::SerializedInputValues(v) => return Ok(v),
+                ev => self.pending.push(ev),
+            }
+        }
+    }
+
+    /// Sets the inputs to the values saved by `save_input_values`.
+    /// The names are resolved and the expressions compiled on this
+    /// thread, with the specs requested from the `Simulator`. Inputs
+    /// that can't be resolved are skipped and reported as
+    /// `SimulatorUIEvent::Error` by the next `update`.
+    pub fn load_input_values(&mut self, inputs: &[(String, Vec<(String, NamedOpIn)>)])
+        -> Result<(), SimulatorError> {
+
+        let specs = self.request_specs()?;
+
+        let mut values = Vec::new();
+        for (op_name, op_inputs) in inputs.iter() {
+            let spec =
+                match specs.iter().find(|(_, info, _)| info.name == *op_name) {
+                    Some((spec, _, _)) => spec,
+                    None => {
+                        self.pending.push(SimulatorUIEvent::Error(
+                            SimulatorError::UnknownOp(op_name.clone())));
+                        continue;
+                    },
+                };
+
+            for (in_name, named) in op_inputs.iter() {
+                let value =
+                    match spec.inputs.iter().position(|p| p.name == *in_name) {
+                        Some(in_idx) =>
+                            named.resolve(|name| resolve_reg_in_specs(&specs, name))
+                                 .map(|v| (spec.index, in_idx, v)),
+                        None => Err(SimulatorError::UnknownInput(
+                                    op_name.clone(), in_name.clone())),
+                    };
+
+                match value {
+                    Ok(v)  => values.push(v),
+                    Err(e) => self.pending.push(SimulatorUIEvent::Error(e)),
+                }
+            }
         }
+
+        self.send(SimulatorUIInput::LoadInputs(values))
     }
 
-    pub fn load_input_values(&mut self, inputs: &Vec<(String, Vec<(String, OpIn)>)>) {
-        self.tx.send(SimulatorUIInput::LoadInputs(inputs.clone()))
-            .expect("communication with backend thread");
+    /// Requests the specs of the ops and waits for them. Events
+    /// received until then are passed on by the next `update`.
+    fn request_specs(&mut self)
+        -> Result<Vec<(OpIOSpec, OpInfo, Vec<Modulation>)>, SimulatorError> {
+
+        self.send(SimulatorUIInput::Refresh)?;
+        loop {
+            match self.recv()? {
+                SimulatorUIEvent::OpSpecUpdate(specs) => return Ok(specs),
+                ev => self.pending.push(ev),
+            }
+        }
     }
 
-    pub fn update<F, T>(&mut self, mut cb: F) -> Option<T>
+    /// Requests a `SimulatorUIEvent::OpSpecUpdate` and waits for it.
+    /// All events received until then, like `SimulatorUIEvent::Error`,
+    /// are passed to `cb` too. Returns the result of `cb` for the
+    /// `OpSpecUpdate`.
+    pub fn update<F, T>(&mut self, mut cb: F) -> Result<T, SimulatorError>
         where F: FnMut(SimulatorUIEvent) -> T {
 
-        self.tx.send(SimulatorUIInput::Refresh)
-            .expect("communication with backend thread");
-        let r = self.rx.recv();
-        if let Ok(ev) = r {
-            Some(cb(ev))
-        } else {
-            None
+        self.send(SimulatorUIInput::Refresh)?;
+        for ev in std::mem::take(&mut self.pending).into_iter() {
+            cb(ev);
+        }
+
+        loop {
+            let ev = self.recv()?;
+            let is_update = matches!(ev, SimulatorUIEvent::OpSpecUpdate(_));
+            let ret = cb(ev);
+            if is_update {
+                return Ok(ret);
+            }
         }
     }
 }
 
+impl Default for SimulatorCommunicator {
+    fn default() -> Self { Self::new() }
+}
+
+/// Like `Simulator::resolve_reg`, but looks the register up in the
+/// specs from a `SimulatorUIEvent::OpSpecUpdate`.
+fn resolve_reg_in_specs(specs: &[(OpIOSpec, OpInfo, Vec<Modulation>)], name: &str)
+    -> Result<usize, SimulatorError> {
+
+    let (op_name, out_name) =
+        name.rsplit_once('.')
+            .ok_or_else(|| SimulatorError::BadRegName(name.to_string()))?;
+    let (spec, _, _) =
+        specs.iter().find(|(_, info, _)| info.name == op_name)
+            .ok_or_else(|| SimulatorError::UnknownOp(op_name.to_string()))?;
+
+    spec.outputs.iter().position(|p| p.name == out_name)
+        .and_then(|i| spec.output_regs.get(i).copied())
+        .ok_or_else(|| SimulatorError::UnknownOutput(
+            op_name.to_string(), out_name.to_string()))
+}
+
 pub struct Simulator {
     pub regs:               Vec<f32>,
     pub ops:                Vec<Box<dyn Op>>,
     pub op_infos:           Vec<OpInfo>,
     pub op_groups:          Vec<OpGroup>,
     pub render_groups:      Vec<Vec<usize>>,
-    pub sample_row:         SampleRow,
+    /// `(start_reg, reg_count)` of the output registers of each op.
+    pub op_regs:            Vec<(usize, usize)>,
+    /// Receives the registers after each `exec`, see `new_scope_reader`.
+    pub scope_writer:       Option<SampleRowWriter>,
     pub scope_sample_len:   usize,
     pub scope_sample_pos:   usize,
+    /// Op indices in the order `exec` runs them.
+    pub exec_order:         Vec<usize>,
+    /// Ops that could not be ordered because of a feedback cycle.
+    pub feedback_ops:       Vec<usize>,
+    /// `(from_op, to_op)` edges that are deliberately delayed by one tick.
+    pub delay_edges:        Vec<(usize, usize)>,
+    /// Smoothing of each input of each op, initialized from `OpPort`.
+    pub input_smoothing:    Vec<Vec<Smoothing>>,
+    /// `(min, max)` of each input of each op, from `OpPort`.
+    pub input_ranges:       Vec<Vec<(f32, f32)>>,
+    /// `exec` ticks per second, see `set_rates`.
+    pub control_rate:       f32,
+    /// Audio samples per second, see `set_rates`.
+    pub sample_rate:        f32,
+    /// Number of `exec` calls since the start or `reset_time`.
+    pub tick:               u64,
+    ramps:                  Vec<InputRamp>,
+    exec_order_dirty:       bool,
+    order_scratch:          ExecOrderScratch,
+    /// Events for `process`, sorted by `Event::sample_offs`.
+    /// The group is `None` for broadcast events.
+    event_queue:            Vec<(Option<usize>, Event)>,
+    /// Samples until `process` calls the next `exec`.
+    exec_countdown:         f64,
+    /// Samples `process` rendered since the last `exec`.
+    samples_since_exec:     f64,
+    /// `ExecContext::time` of the previous `exec`.
+    exec_time:              f64,
+    /// Modulations of the inputs of each op, see `set_op_modulation`.
+    modulations:            Vec<Vec<InputModulation>>,
+    /// See `set_input_clamping`.
+    clamp_inputs:           bool,
+    /// `(input, value)` of the inputs `exec` overrides while an op
+    /// runs, to restore them afterwards.
+    exec_input_bases:       Vec<(usize, OpIn)>,
+    /// The expressions of `OpIn::Expr`.
+    exprs:                  ExprArena,
+    /// The expressions the `[value, default]` of each input of each
+    /// op refer to, as counted in `exprs`.
+    input_exprs:            Vec<Vec<[Option<ExprId>; 2]>>,
+    /// Expressions from `add_expr` that might not be set to an input
+    /// yet. The next `exec` frees them if they are not.
+    new_exprs:              Vec<ExprId>,
+}
+
+/// Buffers of `Simulator::sort_ops`, kept so that updating the
+/// exec order on the audio thread does not allocate.
+#[derive(Debug, Default)]
+struct ExecOrderScratch {
+    /// The op that writes each register, `usize::MAX` for none.
+    reg_writer: Vec<usize>,
+    /// `(from_op, to_op)` dependencies, sorted.
+    edges:      Vec<(usize, usize)>,
+    in_degree:  Vec<usize>,
+    ready:      std::collections::BinaryHeap<std::cmp::Reverse<usize>>,
+    left:       Vec<usize>,
+    remaining:  Vec<usize>,
+}
+
+/// All modulations of one op input.
+#[derive(Debug, Clone)]
+struct InputModulation {
+    input:      usize,
+    /// `(source_reg, depth)`
+    sources:    Vec<(usize, f32)>,
 }
 
 impl Simulator {
     pub fn new() -> Self {
-        let sim = Simulator {
+        Simulator {
             regs:               Vec::new(),
             ops:                Vec::new(),
             op_groups:          Vec::new(),
             op_infos:           Vec::new(),
             render_groups:      Vec::new(),
-            sample_row:         SampleRow::new(),
+            op_regs:            Vec::new(),
+            scope_writer:       None,
             scope_sample_len:   128, // SCOPE_SAMPLES
             scope_sample_pos:   0,
-        };
-        sim
+            exec_order:         Vec::new(),
+            feedback_ops:       Vec::new(),
+            delay_edges:        Vec::new(),
+            input_smoothing:    Vec::new(),
+            input_ranges:       Vec::new(),
+            control_rate:       DEFAULT_CONTROL_RATE,
+            sample_rate:        DEFAULT_SAMPLE_RATE,
+            tick:               0,
+            ramps:              Vec::new(),
+            exec_order_dirty:   true,
+            order_scratch:      ExecOrderScratch::default(),
+            event_queue:        Vec::with_capacity(DEFAULT_EVENT_QUEUE_SIZE),
+            exec_countdown:     0.0,
+            samples_since_exec: 0.0,
+            exec_time:          0.0,
+            modulations:        Vec::new(),
+            clamp_inputs:       false,
+            exec_input_bases:   Vec::new(),
+            exprs:              ExprArena::with_capacity(DEFAULT_EXPR_CAPACITY),
+            input_exprs:        Vec::new(),
+            new_exprs:          Vec::new(),
+        }
     }
 
-    pub fn deserialize_inputs(&mut self, op_inputs: Vec<(String, Vec<(String, OpIn)>)>) {
+    /// Sets the inputs of the ops by op name, resolving the register
+    /// names. Inputs that can't be resolved are skipped and reported
+    /// in the returned errors.
+    pub fn deserialize_inputs(&mut self, op_inputs: &[(String, Vec<(String, NamedOpIn)>)])
+        -> Result<(), Vec<SimulatorError>> {
+
+        let mut errors = Vec::new();
         for (k, v) in op_inputs.iter() {
-            if let Some((idx, _)) =
-                    self.op_infos.iter().enumerate()
-                                 .find(|(_, i)| i.name == *k) {
+            let idx =
+                match self.get_op_index(k) {
+                    Some(idx) => idx,
+                    None => {
+                        errors.push(SimulatorError::UnknownOp(k.clone()));
+                        continue;
+                    },
+                };
 
-                self.ops[idx].deserialize_inputs(v);
+            for (in_name, named) in v.iter() {
+                if let Err(e) = self.load_input(idx, in_name, named, false) {
+                    errors.push(e);
+                }
             }
         }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
     }
 
-    pub fn serialize_inputs(&self) -> Vec<(String, Vec<(String, OpIn)>)> {
-        let mut valmap : Vec<(String, Vec<(String, OpIn)>)> = Vec::new();
+    /// Returns the input values of all ops by op name, with the
+    /// registers referred to by name.
+    pub fn serialize_inputs(&self) -> SerializedInputs {
+        let mut valmap : SerializedInputs = Vec::new();
         for (o, info) in self.ops.iter().zip(self.op_infos.iter()) {
-            valmap.push((info.name.clone(), o.serialize_inputs()));
+            valmap.push((
+                info.name.clone(),
+                o.serialize_inputs()
+                 .into_iter()
+                 // Inputs with out of range registers can't be
+                 // executed anyways, so they are not saved.
+                 .filter_map(|(in_name, op_in)|
+                    self.name_op_in(&op_in).ok().map(|n| (in_name, n)))
+                 .collect()));
         }
         valmap
     }
 
-    pub fn add_group(&mut self, name: &str) -> usize {
-        self.op_groups.push(OpGroup { name: name.to_string(), index: self.op_groups.len() });
-        self.render_groups.push(Vec::new());
-        self.op_groups.len() - 1
-    }
+    /// Returns the name of the register `idx` in the form
+    /// `"op_name.output_name"`.
+    pub fn reg_name(&self, idx: usize) -> Result<String, SimulatorError> {
+        let op_idx =
+            self.op_regs.iter()
+                .position(|(start, count)| idx >= *start && idx < (start + count))
+                .ok_or(SimulatorError::RegOutOfRange(idx))?;
 
-    pub fn get_specs(&self) -> Vec<(OpIOSpec, OpInfo)> {
-        self.ops
-            .iter()
-            .enumerate()
-            .map(|(i, o)|
-                (o.io_spec(i), self.op_infos[i].clone()))
-            .collect()
+        let spec = self.ops[op_idx].io_spec(op_idx);
+        spec.output_regs.iter()
+            .position(|r| *r == idx)
+            .map(|i| format!("{}.{}", self.op_infos[op_idx].name, spec.outputs[i].name))
+            .ok_or(SimulatorError::RegOutOfRange(idx))
     }
 
-    pub fn get_op_index(&self, op_name: &str) -> Option<usize> {
-        let on = op_name.to_string();
-        if let Some((i, _)) =
-            self.op_infos.iter().enumerate().find(|(_i, o)| o.name == on) {
+    /// Resolves a register name of the form `"op_name.output_name"` to
+    /// the register index reported by `Op::get_output_reg`.
+    pub fn resolve_reg(&mut self, name: &str) -> Result<usize, SimulatorError> {
+        let (op_name, out_name) =
+            name.rsplit_once('.')
+                .ok_or_else(|| SimulatorError::BadRegName(name.to_string()))?;
+        let idx =
+            self.get_op_index(op_name)
+                .ok_or_else(|| SimulatorError::UnknownOp(op_name.to_string()))?;
 
-            Some(i)
-        } else {
-            None
-        }
+        self.ops[idx].get_output_reg(out_name)
+            .ok_or_else(|| SimulatorError::UnknownOutput(
+                op_name.to_string(), out_name.to_string()))
+    }
+
+    /// Converts `op_in` into its named form, see also `NamedOpIn`.
+    pub fn name_op_in(&self, op_in: &OpIn) -> Result<NamedOpIn, SimulatorError> {
+        let mut regs  = Vec::new();
+        let mut error = None;
+
+        let mut name_reg = |r: usize| {
+            match self.reg_name(r) {
+                Ok(name) => {
+                    regs.push(name);
+                    Some(regs.len() - 1)
+                },
+                Err(e) => {
+                    error = Some(e);
+                    None
+                },
+            }
+        };
+
+        let (op_in, expr) =
+            match *op_in {
+                OpIn::Expr(id) => {
+                    let e = self.exprs.get(id).ok_or(SimulatorError::UnknownExpr(id))?;
+                    let src = e.map_regs(&mut name_reg).map(|e| e.as_str().to_string());
+                    (Some(OpIn::Constant(e.eval(&self.regs))), src)
+                },
+                _ => (op_in.map_regs(&mut name_reg), None),
+            };
+
+        match (op_in, error) {
+            (Some(op_in), None) => Ok(NamedOpIn { op_in, regs, expr }),
+            (_, Some(e))        => Err(e),
+            (None, None)        => unreachable!(),
+        }
+    }
+
+    /// Adds the compiled expression `expr`, with registers given by
+    /// index, and returns the `OpIn::Expr` that refers to it. The
+    /// expression is kept while inputs refer to it. If it is not set to
+    /// an input, it is freed by the next `exec`. Fails if `expr` reads
+    /// registers that don't exist.
+    pub fn add_expr(&mut self, expr: Expr) -> Result<OpIn, SimulatorError> {
+        let mut bad_reg = None;
+        expr.for_each_reg(|r| {
+            if r >= self.regs.len() { bad_reg = Some(r); }
+        });
+        if let Some(r) = bad_reg {
+            return Err(SimulatorError::RegOutOfRange(r));
+        }
+
+        let (id, _) = self.exprs.insert(expr);
+        self.new_exprs.push(id);
+        Ok(OpIn::Expr(id))
+    }
+
+    /// Returns the expression an `OpIn::Expr` refers to.
+    pub fn expr(&self, id: ExprId) -> Option<&Expr> {
+        self.exprs.get(id)
+    }
+
+    /// Evaluates `op_in` with the current registers, which unlike
+    /// `OpIn::calc` includes expressions.
+    pub fn calc_input(&self, op_in: &OpIn) -> f32 {
+        match *op_in {
+            OpIn::Expr(id) => self.exprs.eval(id, &self.regs),
+            _              => op_in.calc(&self.regs),
+        }
+    }
+
+    /// Parses an `OpIn::Expr`, in which registers can be referred to by
+    /// their name like `"lfo.out"`, or by index like `r3`.
+    /// See `add_expr` for how long the expression is kept.
+    pub fn parse_expr(&mut self, src: &str) -> Result<OpIn, SimulatorError> {
+        let mut error = None;
+        let expr =
+            Expr::parse_with(src, |name| {
+                match self.resolve_reg(name) {
+                    Ok(r) => Some(r),
+                    Err(_) if name.starts_with('r') && !name.contains('.') => {
+                        let r = name[1..].parse::<usize>().ok()?;
+                        if r < self.regs.len() { Some(r) }
+                        else { error = Some(SimulatorError::RegOutOfRange(r)); None }
+                    },
+                    Err(e) => { error = Some(e); None },
+                }
+            });
+
+        match (expr, error) {
+            (Ok(e), _)        => self.add_expr(e),
+            (Err(_), Some(e)) => Err(e),
+            (Err(e), None)    => Err(SimulatorError::BadExpr(e)),
+        }
+    }
+
+    /// Resolves the register names of `named` to register indices.
+    /// An expression is added like with `add_expr`.
+    pub fn resolve_op_in(&mut self, named: &NamedOpIn) -> Result<OpIn, SimulatorError> {
+        match named.resolve(|name| self.resolve_reg(name))? {
+            InputValue::OpIn(op_in) => Ok(op_in),
+            InputValue::Expr(expr)  => self.add_expr(expr),
+        }
+    }
+
+    /// Resolves `named` and sets it to the input `in_name` of the op at
+    /// `idx` right away, without smoothing.
+    fn load_input(&mut self, idx: usize, in_name: &str, named: &NamedOpIn, as_default: bool)
+        -> Result<(), SimulatorError> {
+
+        let in_idx =
+            self.ops[idx].input_index(in_name).ok_or_else(||
+                SimulatorError::UnknownInput(
+                    self.op_infos[idx].name.clone(), in_name.to_string()))?;
+        let op_in = self.resolve_op_in(named)?;
+
+        self.set_loaded_input(idx, in_idx, op_in, as_default)
+    }
+
+    /// Sets the input of a `SimulatorUIInput::LoadInputs`.
+    fn load_input_value(&mut self, idx: usize, input_idx: usize, to: OpIn)
+        -> Result<(), SimulatorError> {
+
+        if idx >= self.ops.len() {
+            return Err(SimulatorError::OpIndexOutOfRange(idx));
+        }
+        self.check_input_value(&to)?;
+        self.set_loaded_input(idx, input_idx, to, false)
+    }
+
+    /// Sets a loaded input right away, without smoothing.
+    fn set_loaded_input(&mut self, idx: usize, input_idx: usize, to: OpIn, as_default: bool)
+        -> Result<(), SimulatorError> {
+
+        if !as_default {
+            self.ramps.retain(|r| r.op != idx || r.input != input_idx);
+        }
+        if !self.set_input_counted(idx, input_idx, to, as_default) {
+            return Err(SimulatorError::InputIndexOutOfRange(
+                self.op_infos[idx].name.clone(), input_idx));
+        }
+        self.exec_order_dirty = true;
+        Ok(())
+    }
+
+    /// Sets the input of the op at `idx` and keeps count of the
+    /// expressions it refers to. Returns false if there is no such input.
+    fn set_input_counted(&mut self, idx: usize, input_idx: usize, to: OpIn, as_default: bool) -> bool {
+        if !self.ops[idx].set_input_by_index(input_idx, to, as_default) {
+            return false;
+        }
+
+        let new = if let OpIn::Expr(id) = to { Some(id) } else { None };
+        if let Some(id) = new {
+            self.exprs.retain(id);
+        }
+        if let Some(slot) = self.input_exprs[idx].get_mut(input_idx) {
+            if let Some(old) = std::mem::replace(&mut slot[as_default as usize], new) {
+                self.exprs.release(old);
+            }
+        }
+        true
+    }
+
+    /// Frees the expressions the inputs of the op at `idx` refer to.
+    fn release_input_exprs(&mut self, idx: usize) {
+        for id in self.input_exprs[idx].iter().flatten().flatten() {
+            self.exprs.release(*id);
+        }
+    }
+
+    /// Removes all ops and groups.
+    pub fn clear(&mut self) {
+        self.regs.clear();
+        self.ops.clear();
+        self.op_infos.clear();
+        self.op_groups.clear();
+        self.render_groups.clear();
+        self.op_regs.clear();
+        self.exec_order.clear();
+        self.feedback_ops.clear();
+        self.delay_edges.clear();
+        self.input_smoothing.clear();
+        self.input_ranges.clear();
+        self.ramps.clear();
+        self.event_queue.clear();
+        self.modulations.clear();
+        self.exprs.clear();
+        self.input_exprs.clear();
+        self.new_exprs.clear();
+        self.exec_order_dirty = true;
+    }
+
+    /// Returns a complete description of the ops, groups and
+    /// connections of this simulator.
+    pub fn save_patch(&self) -> Patch {
+        let name_inputs = |spec_inputs: &[OpPort], values: &[OpIn]| {
+            spec_inputs.iter()
+                .zip(values.iter())
+                .filter_map(|(p, v)|
+                    self.name_op_in(v).ok().map(|n| (p.name.clone(), n)))
+                .collect()
+        };
+
+        let ops =
+            self.ops.iter().zip(self.op_infos.iter()).enumerate()
+                .map(|(i, (o, info))| {
+                    let spec = o.io_spec(i);
+                    PatchOp {
+                        op_type:   o.type_name().to_string(),
+                        type_args: o.type_args(),
+                        name:      info.name.clone(),
+                        group:     info.group.name.clone(),
+                        inputs:    name_inputs(&spec.inputs, &spec.input_values),
+                        defaults:  name_inputs(&spec.inputs, &spec.input_defaults),
+                        modulations:
+                            self.get_modulations(i).iter()
+                                .filter_map(|m|
+                                    self.reg_name(m.source).ok().map(|src|
+                                        (spec.inputs[m.input].name.clone(), src, m.depth)))
+                                .collect(),
+                    }
+                })
+                .collect();
+
+        Patch {
+            groups:      self.op_groups.iter().map(|g| g.name.clone()).collect(),
+            ops,
+            delay_edges:
+                self.delay_edges.iter()
+                    .map(|(from, to)| (self.op_infos[*from].name.clone(),
+                                       self.op_infos[*to].name.clone()))
+                    .collect(),
+        }
+    }
+
+    /// Replaces all ops and groups of this simulator with the ones
+    /// from `patch`. The ops are instanciated by their type name via
+    /// `registry`. Ops, inputs and connections that can't be restored
+    /// are skipped and reported in the returned errors.
+    pub fn load_patch(&mut self, patch: &Patch, registry: &OpRegistry)
+        -> Result<(), Vec<SimulatorError>> {
+
+        self.clear();
+
+        let mut errors = Vec::new();
+        for grp in patch.groups.iter() {
+            self.add_group(grp);
+        }
+
+        let mut loaded = Vec::with_capacity(patch.ops.len());
+        for pop in patch.ops.iter() {
+            let grp_idx =
+                match self.op_groups.iter().position(|g| g.name == pop.group) {
+                    Some(idx) => idx,
+                    None => {
+                        errors.push(SimulatorError::UnknownGroup(pop.group.clone()));
+                        continue;
+                    },
+                };
+
+            match registry.create(&pop.op_type, &pop.type_args) {
+                Some(op) => {
+                    // Arguments that were left out take their defaults.
+                    if !op.type_args().starts_with(&pop.type_args) {
+                        errors.push(SimulatorError::BadTypeArgs(
+                            pop.name.clone(), pop.type_args.clone()));
+                    }
+                    self.add_op(op, pop.name.clone(), grp_idx);
+                    loaded.push((self.ops.len() - 1, pop));
+                },
+                None => {
+                    errors.push(SimulatorError::UnknownOpType(pop.op_type.clone()));
+                },
+            }
+        }
+
+        // Inputs are resolved after all ops were added, so that
+        // connections to ops later in the patch can be resolved too.
+        for (idx, pop) in loaded.into_iter() {
+            for (inputs, as_default) in [(&pop.defaults, true), (&pop.inputs, false)].iter() {
+                for (in_name, named) in inputs.iter() {
+                    if let Err(e) = self.load_input(idx, in_name, named, *as_default) {
+                        errors.push(e);
+                    }
+                }
+            }
+
+            for (in_name, src_name, depth) in pop.modulations.iter() {
+                let res =
+                    self.resolve_reg(src_name).and_then(|src|
+                        self.set_op_modulation(idx, in_name, src, *depth));
+                if let Err(e) = res {
+                    errors.push(e);
+                }
+            }
+        }
+
+        for (from, to) in patch.delay_edges.iter() {
+            match (self.get_op_index(from), self.get_op_index(to)) {
+                (Some(from), Some(to)) => self.set_delay_edge(from, to, true),
+                (None, _) => errors.push(SimulatorError::UnknownOp(from.clone())),
+                (_, None) => errors.push(SimulatorError::UnknownOp(to.clone())),
+            }
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    pub fn add_group(&mut self, name: &str) -> usize {
+        self.op_groups.push(OpGroup { name: name.to_string(), index: self.op_groups.len() });
+        self.render_groups.push(Vec::new());
+        self.op_groups.len() - 1
+    }
+
+    /// Returns the `OpIOSpec`, `OpInfo` and the modulations of the
+    /// inputs, see `get_modulations`, of every op.
+    pub fn get_specs(&self) -> Vec<(OpIOSpec, OpInfo, Vec<Modulation>)> {
+        self.ops
+            .iter()
+            .enumerate()
+            .map(|(i, o)|
+                (o.io_spec(i), self.op_infos[i].clone(), self.get_modulations(i)))
+            .collect()
+    }
+
+    /// Returns the modulations of the inputs of the op at `idx`,
+    /// sorted by input.
+    pub fn get_modulations(&self, idx: usize) -> Vec<Modulation> {
+        let mods = match self.modulations.get(idx) {
+            Some(mods) => mods,
+            None       => return Vec::new(),
+        };
+
+        mods.iter()
+            .flat_map(|im|
+                im.sources.iter().map(move |(source, depth)|
+                    Modulation { input: im.input, source: *source, depth: *depth }))
+            .collect()
+    }
+
+    /// Adds the register `source`, scaled by `depth`, onto the value of
+    /// the input `input_name` of the op at `idx`, or changes the depth
+    /// if `source` already modulates that input. Any number of
+    /// sources can modulate an input. The input itself keeps its value,
+    /// which is the base value the modulations are added onto.
+    /// The modulated value is seen by `Op::exec`, not by `Op::render`.
+    pub fn set_op_modulation(&mut self, idx: usize, input_name: &str, source: usize, depth: f32)
+        -> Result<(), SimulatorError> {
+
+        if idx >= self.ops.len() {
+            return Err(SimulatorError::OpIndexOutOfRange(idx));
+        }
+
+        match self.ops[idx].input_index(input_name) {
+            Some(in_idx) => self.set_op_modulation_by_index(idx, in_idx, source, depth),
+            None => Err(SimulatorError::UnknownInput(
+                        self.op_infos[idx].name.clone(), input_name.to_string())),
+        }
+    }
+
+    /// Like `set_op_modulation`, but addresses the input by its index
+    /// in `OpIOSpec::inputs`. Allocates only if `source` did not
+    /// modulate the input yet.
+    pub fn set_op_modulation_by_index(&mut self, idx: usize, input_idx: usize, source: usize, depth: f32)
+        -> Result<(), SimulatorError> {
+
+        if idx >= self.ops.len() {
+            return Err(SimulatorError::OpIndexOutOfRange(idx));
+        }
+        if input_idx >= self.ops[idx].input_count() {
+            return Err(SimulatorError::InputIndexOutOfRange(
+                self.op_infos[idx].name.clone(), input_idx));
+        }
+        if source >= self.regs.len() {
+            return Err(SimulatorError::RegOutOfRange(source));
+        }
+
+        let mods = &mut self.modulations[idx];
+        let pos =
+            match mods.iter().position(|im| im.input == input_idx) {
+                Some(pos) => pos,
+                None => {
+                    let pos = mods.partition_point(|im| im.input < input_idx);
+                    mods.insert(pos, InputModulation {
+                        input:   input_idx,
+                        sources: Vec::new(),
+                    });
+                    pos
+                },
+            };
+
+        let sources = &mut mods[pos].sources;
+        match sources.iter_mut().find(|(s, _)| *s == source) {
+            Some((_, d)) => { *d = depth; },
+            None => {
+                sources.push((source, depth));
+                self.exec_order_dirty = true;
+                self.reserve_exec_order();
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Removes the modulation of the input `input_idx` of the op at `idx`
+    /// by `source`. Returns false if there is no such modulation.
+    pub fn remove_op_modulation(&mut self, idx: usize, input_idx: usize, source: usize) -> bool {
+        let mods =
+            match self.modulations.get_mut(idx) {
+                Some(mods) => mods,
+                None       => return false,
+            };
+
+        let pos =
+            match mods.iter().position(|im| im.input == input_idx) {
+                Some(pos) => pos,
+                None      => return false,
+            };
+
+        let len = mods[pos].sources.len();
+        mods[pos].sources.retain(|(s, _)| *s != source);
+        if mods[pos].sources.len() == len {
+            return false;
+        }
+        if mods[pos].sources.is_empty() {
+            mods.remove(pos);
+        }
+
+        self.exec_order_dirty = true;
+        true
+    }
+
+    /// Returns the op at `idx` as `T`, if it is one and supports
+    /// `Op::as_any`. This is how the host gets at ops created by
+    /// `load_patch`, for example the values of an `OutProxy`.
+    pub fn get_op_as<T: 'static>(&self, idx: usize) -> Option<&T> {
+        self.ops.get(idx)?.as_any()?.downcast_ref::<T>()
+    }
+
+    pub fn get_op_index(&self, op_name: &str) -> Option<usize> {
+        let on = op_name.to_string();
+        if let Some((i, _)) =
+            self.op_infos.iter().enumerate().find(|(_i, o)| o.name == on) {
+
+            Some(i)
+        } else {
+            None
+        }
     }
 
     pub fn add_op(&mut self, mut op: Box<dyn Op>, op_name: String, group_index: usize) -> Option<usize> {
@@ -305,6 +1854,13 @@ impl Simulator {
         self.regs.resize(new_reg_count, 0.0);
         op.init_regs(new_start_reg, &mut self.regs[..]);
         let out_reg = op.get_output_reg("out");
+        self.op_regs.push((new_start_reg, new_reg_count - new_start_reg));
+        let spec = op.io_spec(0);
+        self.input_smoothing.push(spec.inputs.iter().map(|p| p.smoothing).collect());
+        self.input_ranges.push(spec.inputs.iter().map(|p| (p.min, p.max)).collect());
+        self.exec_input_bases.reserve(op.input_count());
+        self.modulations.push(Vec::new());
+        self.input_exprs.push(vec![[None; 2]; spec.inputs.len()]);
 
         self.op_infos.push(OpInfo {
             name: op_name,
@@ -313,10 +1869,205 @@ impl Simulator {
         });
         self.ops.push(op);
         self.render_groups[group_index].push(self.ops.len() - 1);
+        self.exec_order_dirty = true;
+        self.reserve_exec_order();
 
         out_reg
     }
 
+    /// Removes the op at `idx` together with its output registers.
+    /// The registers of the following ops are compacted and all inputs
+    /// of the remaining ops are rewritten to the new register indices.
+    /// Inputs that were connected to the removed op keep the
+    /// last value they evaluated to as `OpIn::Constant`.
+    pub fn remove_op(&mut self, idx: usize) -> Option<Box<dyn Op>> {
+        if idx >= self.ops.len() {
+            return None;
+        }
+
+        let (start, count) = self.op_regs[idx];
+        self.relocate_op_regs(idx, 0, |_| None);
+        self.release_input_exprs(idx);
+
+        let op = self.ops.remove(idx);
+        self.op_infos.remove(idx);
+        self.op_regs.remove(idx);
+        self.input_smoothing.remove(idx);
+        self.input_ranges.remove(idx);
+        self.modulations.remove(idx);
+        self.input_exprs.remove(idx);
+
+        self.ramps.retain(|r| r.op != idx);
+        for r in self.ramps.iter_mut() {
+            if r.op > idx { r.op -= 1; }
+        }
+        self.regs.drain(start..(start + count));
+
+        for grp in self.render_groups.iter_mut() {
+            grp.retain(|i| *i != idx);
+            for i in grp.iter_mut() {
+                if *i > idx { *i -= 1; }
+            }
+        }
+
+        self.delay_edges.retain(|(from, to)| *from != idx && *to != idx);
+        for (from, to) in self.delay_edges.iter_mut() {
+            if *from > idx { *from -= 1; }
+            if *to   > idx { *to   -= 1; }
+        }
+
+        self.exec_order_dirty = true;
+
+        Some(op)
+    }
+
+    /// Replaces the op at `idx` by `op`, keeping its name, group and
+    /// position. Connections to outputs of the old op are moved over
+    /// to the outputs of the new op with the same name, the remaining
+    /// ones are disconnected like in `remove_op`. The modulations of
+    /// the inputs of the old op are removed.
+    /// Returns the old op.
+    pub fn replace_op(&mut self, idx: usize, mut op: Box<dyn Op>) -> Option<Box<dyn Op>> {
+        if idx >= self.ops.len() {
+            return None;
+        }
+
+        let (start, count) = self.op_regs[idx];
+        let new_count      = op.output_count();
+
+        let mut new_regs = Vec::with_capacity(self.regs.len() + new_count - count);
+        new_regs.extend_from_slice(&self.regs[..start]);
+        new_regs.resize(start + new_count, 0.0);
+        new_regs.extend_from_slice(&self.regs[(start + count)..]);
+        op.init_regs(start, &mut new_regs[..]);
+
+        let old_spec = self.ops[idx].io_spec(idx);
+        let out_map : Vec<(usize, Option<usize>)> =
+            old_spec.outputs.iter()
+                .zip(old_spec.output_regs.iter())
+                .map(|(port, reg)| (*reg, op.get_output_reg(&port.name)))
+                .collect();
+
+        self.relocate_op_regs(idx, new_count, |r| {
+            out_map.iter().find(|(old, _)| *old == r).and_then(|(_, new)| *new)
+        });
+        self.release_input_exprs(idx);
+
+        self.regs = new_regs;
+        self.op_regs[idx] = (start, new_count);
+        let spec = op.io_spec(idx);
+        self.input_smoothing[idx] = spec.inputs.iter().map(|p| p.smoothing).collect();
+        self.input_ranges[idx]    = spec.inputs.iter().map(|p| (p.min, p.max)).collect();
+        self.input_exprs[idx]     = vec![[None; 2]; spec.inputs.len()];
+        self.ramps.retain(|r| r.op != idx);
+        self.exec_input_bases.reserve(op.input_count());
+        self.modulations[idx].clear();
+        self.op_infos[idx].does_render = op.does_render();
+        self.exec_order_dirty = true;
+
+        let old = std::mem::replace(&mut self.ops[idx], op);
+        self.reserve_exec_order();
+        Some(old)
+    }
+
+    /// Moves the op at `idx` into the group `group_index`.
+    pub fn move_op_to_group(&mut self, idx: usize, group_index: usize) -> bool {
+        if idx >= self.ops.len() || group_index >= self.op_groups.len() {
+            return false;
+        }
+
+        for grp in self.render_groups.iter_mut() {
+            grp.retain(|i| *i != idx);
+        }
+        self.render_groups[group_index].push(idx);
+        self.op_infos[idx].group = self.op_groups[group_index].clone();
+
+        true
+    }
+
+    /// Rewrites the register indices of all ops except `idx` for the case
+    /// that the output registers of the op at `idx` are resized to
+    /// `new_count`. The registers of `idx` itself are mapped with
+    /// `map_own`, inputs it can't map are disconnected.
+    /// This does not touch `self.regs` and `self.op_regs[idx]`.
+    fn relocate_op_regs<F>(&mut self, idx: usize, new_count: usize, map_own: F)
+        where F: Fn(usize) -> Option<usize> {
+
+        let (start, count) = self.op_regs[idx];
+        let end   = start + count;
+        let map = |r: usize| {
+            if r < start                { Some(r) }
+            else if r < end             { map_own(r) }
+            else                        { Some(r - count + new_count) }
+        };
+
+        // Expressions are mapped into new ones, so the changes are
+        // collected first and then applied with their counting.
+        let old_regs = &self.regs[..];
+        let mut changes = Vec::new();
+        for (i, op) in self.ops.iter().enumerate() {
+            if i == idx { continue; }
+
+            let spec = op.io_spec(i);
+            for j in 0..spec.inputs.len() {
+                for (v, as_default) in [(spec.input_values[j], false),
+                                        (spec.input_defaults[j], true)].iter() {
+                    let new_v =
+                        match *v {
+                            OpIn::Expr(id) => {
+                                let e = match self.exprs.get(id) { Some(e) => e, None => continue };
+                                match e.map_regs(map) {
+                                    Some(new_e) if new_e == *e => continue,
+                                    Some(new_e) => InputValue::Expr(new_e),
+                                    None => InputValue::OpIn(OpIn::Constant(e.eval(old_regs))),
+                                }
+                            },
+                            _ => {
+                                let new_v =
+                                    v.map_regs(map).unwrap_or_else(
+                                        || OpIn::Constant(v.calc(old_regs)));
+                                if new_v == *v { continue; }
+                                InputValue::OpIn(new_v)
+                            },
+                        };
+                    changes.push((i, j, new_v, *as_default));
+                }
+            }
+        }
+
+        for (i, j, new_v, as_default) in changes.into_iter() {
+            let new_v =
+                match new_v {
+                    InputValue::OpIn(op_in) => op_in,
+                    InputValue::Expr(e)     => OpIn::Expr(self.exprs.insert(e).0),
+                };
+            self.set_input_counted(i, j, new_v, as_default);
+        }
+
+        // Modulations from registers that are gone are removed.
+        for mods in self.modulations.iter_mut() {
+            for im in mods.iter_mut() {
+                im.sources.retain_mut(|(src, _)| {
+                    match map(*src) {
+                        Some(r) => { *src = r; true },
+                        None    => false,
+                    }
+                });
+            }
+            mods.retain(|im| !im.sources.is_empty());
+        }
+
+        // The ops write their initial register values, but we want to
+        // keep the current ones. So let them initialize a scratch buffer.
+        let mut scratch = vec![0.0; self.regs.len() - count + new_count];
+        for (i, (op, regs)) in self.ops.iter_mut().zip(self.op_regs.iter_mut()).enumerate() {
+            if i <= idx { continue; }
+
+            regs.0 = regs.0 - count + new_count;
+            op.init_regs(regs.0, &mut scratch[..]);
+        }
+    }
+
     pub fn set_reg(&mut self, idx: usize, v: f32) -> bool {
         if self.regs.len() > idx {
             self.regs[idx] = v;
@@ -334,27 +2085,405 @@ impl Simulator {
         }
     }
 
-    pub fn set_op_input(&mut self, idx: usize, input_name: &str, to: OpIn, as_default: bool) -> bool {
+    /// Sets the input `input_name` of the op at `idx`. Fails if there is
+    /// no such op or input, or if `to` reads registers that don't exist.
+    pub fn set_op_input(&mut self, idx: usize, input_name: &str, to: OpIn, as_default: bool)
+        -> Result<(), SimulatorError> {
+
         //d// println!("SETSET {} {} {:?}", idx, input_name, to);
         if idx >= self.ops.len() {
-            return false;
+            return Err(SimulatorError::OpIndexOutOfRange(idx));
+        }
+
+        match self.ops[idx].input_index(input_name) {
+            Some(in_idx) => self.set_op_input_by_index(idx, in_idx, to, as_default),
+            None => Err(SimulatorError::UnknownInput(
+                        self.op_infos[idx].name.clone(), input_name.to_string())),
         }
-        self.ops[idx].set_input(input_name, to, as_default)
     }
 
-    pub fn exec(&mut self, t: f32, ext_scopes: std::sync::Arc<std::sync::Mutex<SampleRow>>) {
-        for r in self.ops.iter_mut() {
-            r.as_mut().exec(t, &mut self.regs[..]);
+    /// Like `set_op_input`, but addresses the input by its index
+    /// in `OpIOSpec::inputs`.
+    pub fn set_op_input_by_index(&mut self, idx: usize, input_idx: usize, to: OpIn, as_default: bool)
+        -> Result<(), SimulatorError> {
+
+        if idx >= self.ops.len() {
+            return Err(SimulatorError::OpIndexOutOfRange(idx));
         }
+        self.check_input_value(&to)?;
 
-        self.sample_row.read_from_regs(&self.regs[..], self.scope_sample_pos);
-        self.scope_sample_pos =
-            (self.scope_sample_pos + 1) % self.scope_sample_len;
+        // Only the registers an input reads matter for the exec order.
+        // Updating the order when they don't change is avoided, as it
+        // is costly for a knob that is turned.
+        let regs_changed =
+            !as_default
+            && match self.ops[idx].input_value(input_idx) {
+                Some(old) => !reads_same_regs(&self.exprs, &old, &to),
+                None      => true,
+            };
+
+        if !as_default {
+            let ramp_pos =
+                self.ramps.iter().position(|r| r.op == idx && r.input == input_idx);
+            let smoothing =
+                self.input_smoothing[idx].get(input_idx).copied()
+                    .unwrap_or(Smoothing::None);
+
+            let cur_value =
+                match ramp_pos {
+                    Some(pos) => Some(self.ramps[pos].value()),
+                    None =>
+                        match self.ops[idx].input_value(input_idx) {
+                            Some(OpIn::Constant(v)) => Some(v),
+                            _ => None,
+                        },
+                };
+
+            match (to, cur_value) {
+                (OpIn::Constant(target), Some(cur)) if smoothing != Smoothing::None => {
+                    // The ramp sets the input in the following exec calls.
+                    match ramp_pos {
+                        Some(pos) =>
+                            self.ramps[pos].retarget(
+                                target, smoothing, self.control_rate),
+                        None => self.ramps.push(
+                            InputRamp::new(
+                                idx, input_idx, cur, target,
+                                smoothing, self.control_rate)),
+                    }
+                    return Ok(());
+                },
+                _ => {
+                    if let Some(pos) = ramp_pos { self.ramps.swap_remove(pos); }
+                },
+            }
+        }
+
+        if !self.set_input_counted(idx, input_idx, to, as_default) {
+            return Err(SimulatorError::InputIndexOutOfRange(
+                self.op_infos[idx].name.clone(), input_idx));
+        }
+
+        if regs_changed {
+            self.exec_order_dirty = true;
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the expression and the registers `to` refers to exist.
+    fn check_input_value(&self, to: &OpIn) -> Result<(), SimulatorError> {
+        if let OpIn::Expr(id) = *to {
+            if self.exprs.get(id).is_none() {
+                return Err(SimulatorError::UnknownExpr(id));
+            }
+        }
+        let reg_count = self.regs.len();
+        let mut bad_reg    = None;
+        for_each_input_reg(&self.exprs, to, |r| {
+            if r >= reg_count { bad_reg = Some(r); }
+        });
+        match bad_reg {
+            Some(r) => Err(SimulatorError::RegOutOfRange(r)),
+            None    => Ok(()),
+        }
+    }
+
+    /// Overrides the smoothing of the input at `input_idx` of the
+    /// op at `idx`, which defaults to `OpPort::smoothing`.
+    pub fn set_input_smoothing(&mut self, idx: usize, input_idx: usize, smoothing: Smoothing)
+        -> Result<(), SimulatorError> {
+
+        let sm =
+            self.input_smoothing.get_mut(idx)
+                .ok_or(SimulatorError::OpIndexOutOfRange(idx))?;
+        match sm.get_mut(input_idx) {
+            Some(s) => { *s = smoothing; Ok(()) },
+            None => Err(SimulatorError::InputIndexOutOfRange(
+                        self.op_infos[idx].name.clone(), input_idx)),
+        }
+    }
+
+    /// Enables clamping of all inputs to the `min` and `max` of their
+    /// `OpPort` in `exec`, after the modulations are added. As with
+    /// modulations, the clamped values are only seen by `Op::exec`,
+    /// the inputs keep their values. Off by default.
+    pub fn set_input_clamping(&mut self, clamp: bool) {
+        self.clamp_inputs = clamp;
+    }
+
+    /// Marks the connection from `from_op` to `to_op` as a deliberate
+    /// one tick delay. `to_op` then reads the value `from_op` wrote in the
+    /// previous tick and the edge is not reported as feedback cycle.
+    pub fn set_delay_edge(&mut self, from_op: usize, to_op: usize, delayed: bool) {
+        self.delay_edges.retain(|e| *e != (from_op, to_op));
+        if delayed {
+            self.delay_edges.push((from_op, to_op));
+        }
+        self.exec_order_dirty = true;
+    }
+
+    /// Recalculates `exec_order` from the registers the ops read via
+    /// their `input_values` and write via their `output_regs`.
+    /// Ops are sorted topologically, independent ops keep their
+    /// insertion order. If there are feedback cycles that are not
+    /// marked with `set_delay_edge`, the involved ops are returned as
+    /// error and executed in insertion order after all other ops.
+    pub fn update_exec_order(&mut self) -> Result<(), Vec<usize>> {
+        self.sort_ops();
+        if self.feedback_ops.is_empty() {
+            Ok(())
+        } else {
+            Err(self.feedback_ops.clone())
+        }
+    }
+
+    /// Reserves the buffers `sort_ops` needs for the current ops,
+    /// so that `exec` can update the exec order without allocating.
+    /// Also makes room for a ramp on every input of every op.
+    fn reserve_exec_order(&mut self) {
+        let op_count = self.ops.len();
+        let input_count = self.input_ranges.iter().map(|r| r.len()).sum::<usize>();
+        let edge_count =
+            self.input_ranges.iter().map(|r| r.len() * 2).sum::<usize>()
+            + self.modulations.iter().flatten().map(|im| im.sources.len()).sum::<usize>();
+
+        let sc = &mut self.order_scratch;
+        sc.reg_writer.reserve(self.regs.len());
+        sc.edges.reserve(edge_count);
+        sc.in_degree.reserve(op_count);
+        sc.ready.reserve(op_count);
+        sc.left.reserve(op_count);
+        sc.remaining.reserve(op_count);
+        self.exec_order.reserve(op_count);
+        self.feedback_ops.reserve(op_count);
+        self.ramps.reserve(input_count.saturating_sub(self.ramps.len()));
+    }
+
+    /// The implementation of `update_exec_order`. Only allocates if
+    /// inputs read more registers than `reserve_exec_order` accounted
+    /// for, which only `OpIn::Expr` can.
+    fn sort_ops(&mut self) {
+        use std::cmp::Reverse;
+
+        self.exec_order_dirty = false;
+
+        let op_count = self.ops.len();
+        let sc       = &mut self.order_scratch;
+
+        sc.reg_writer.clear();
+        sc.reg_writer.resize(self.regs.len(), usize::MAX);
+        for (i, (start, count)) in self.op_regs.iter().enumerate() {
+            for r in *start..(start + count) {
+                if r < sc.reg_writer.len() { sc.reg_writer[r] = i; }
+            }
+        }
+
+        sc.edges.clear();
+        for (to, op) in self.ops.iter().enumerate() {
+            let reg_writer  = &sc.reg_writer;
+            let edges       = &mut sc.edges;
+            let delay_edges = &self.delay_edges;
+            let mut add_edge = |r: usize| {
+                let from =
+                    match reg_writer.get(r) {
+                        Some(from) if *from != usize::MAX => *from,
+                        _ => return,
+                    };
+                if !delay_edges.contains(&(from, to)) {
+                    edges.push((from, to));
+                }
+            };
+
+            for j in 0..self.input_ranges[to].len() {
+                if let Some(v) = op.input_value(j) {
+                    for_each_input_reg(&self.exprs, &v, &mut add_edge);
+                }
+            }
+            for im in self.modulations[to].iter() {
+                for (src, _) in im.sources.iter() {
+                    add_edge(*src);
+                }
+            }
+        }
+        sc.edges.sort_unstable();
+        sc.edges.dedup();
+
+        // Edges are sorted by their source, so these are the consumers of `i`.
+        fn consumers(edges: &[(usize, usize)], i: usize) -> impl Iterator<Item = usize> + '_ {
+            let start = edges.partition_point(|(from, _)| *from < i);
+            edges[start..].iter()
+                .take_while(move |(from, _)| *from == i)
+                .map(|(_, to)| *to)
+                .filter(move |to| *to != i)
+        }
+
+        self.feedback_ops.clear();
+        sc.in_degree.clear();
+        sc.in_degree.resize(op_count, 0);
+        for (from, to) in sc.edges.iter() {
+            if from == to {
+                self.feedback_ops.push(*to);
+            } else {
+                sc.in_degree[*to] += 1;
+            }
+        }
+
+        sc.ready.clear();
+        for i in 0..op_count {
+            if sc.in_degree[i] == 0 { sc.ready.push(Reverse(i)); }
+        }
+
+        self.exec_order.clear();
+        while let Some(Reverse(i)) = sc.ready.pop() {
+            self.exec_order.push(i);
+            for c in consumers(&sc.edges, i) {
+                sc.in_degree[c] -= 1;
+                if sc.in_degree[c] == 0 {
+                    sc.ready.push(Reverse(c));
+                }
+            }
+        }
+
+        // Everything left over is either part of a cycle or depends on one.
+        // Strip the ops that only depend on cycles, so that only
+        // the ops on the cycles remain for reporting.
+        sc.left.clear();
+        let in_degree = &sc.in_degree;
+        sc.left.extend((0..op_count).filter(|i| in_degree[*i] > 0));
+        self.exec_order.extend_from_slice(&sc.left[..]);
+
+        loop {
+            let len = sc.left.len();
+            sc.remaining.clear();
+            sc.remaining.extend_from_slice(&sc.left[..]);
+            let (edges, remaining) = (&sc.edges, &sc.remaining);
+            sc.left.retain(|i| consumers(edges, *i).any(|c| remaining.contains(&c)));
+            if sc.left.len() == len { break; }
+        }
+
+        for i in sc.left.iter() {
+            if !self.feedback_ops.contains(i) { self.feedback_ops.push(*i); }
+        }
+        self.feedback_ops.sort_unstable();
+    }
+
+    /// Returns a reader for the register values the following calls
+    /// to `exec` publish. A previously returned reader stops receiving.
+    pub fn new_scope_reader(&mut self) -> SampleRowReader {
+        let (writer, reader) = new_sample_row_exchange(self.regs.len());
+        self.scope_writer = Some(writer);
+        reader
+    }
+
+    /// Sets the number of `exec` calls per second and the sample
+    /// rate of the audio buffers passed to `render`.
+    pub fn set_rates(&mut self, control_rate: f32, sample_rate: f32) {
+        self.control_rate = control_rate;
+        self.sample_rate  = sample_rate;
+    }
+
+    /// Restarts the time passed to the ops at 0.
+    pub fn reset_time(&mut self) {
+        self.tick               = 0;
+        self.exec_countdown     = 0.0;
+        self.samples_since_exec = 0.0;
+        self.exec_time          = 0.0;
+    }
+
+    /// Returns the timing information for the next `exec`.
+    pub fn exec_context(&self) -> ExecContext {
+        self.exec_context_after(1.0 / self.control_rate)
+    }
+
+    fn exec_context_after(&self, dt: f32) -> ExecContext {
+        ExecContext {
+            tick:           self.tick,
+            time:           if self.tick == 0 { 0.0 } else { self.exec_time + dt as f64 },
+            dt,
+            control_rate:   self.control_rate,
+            sample_rate:    self.sample_rate,
+        }
+    }
+
+    /// Executes all ops for one control tick and advances the time
+    /// by `1.0 / control_rate` seconds.
+    pub fn exec(&mut self) {
+        self.exec_after(1.0 / self.control_rate);
+    }
+
+    /// Executes all ops for a tick `dt` seconds after the previous one.
+    fn exec_after(&mut self, dt: f32) {
+        let ctx = self.exec_context_after(dt);
+        self.exec_time = ctx.time;
+
+        if self.exec_order_dirty {
+            // Feedback cycles are reported via feedback_ops,
+            // the ops are executed in a stable order anyways.
+            self.sort_ops();
+        }
+
+        let exprs = &mut self.exprs;
+        for id in self.new_exprs.drain(..) {
+            exprs.discard_unused(id);
+        }
 
-        if let Ok(ref mut m) = ext_scopes.try_lock() {
-//            use std::ops::DerefMut;
-            std::mem::swap(&mut self.sample_row, &mut *m);
+        for r in self.ramps.iter_mut() {
+            let v = r.next_value();
+            self.ops[r.op].set_input_by_index(r.input, OpIn::Constant(v), false);
         }
+        self.ramps.retain(|r| !r.is_done());
+
+        let clamp = self.clamp_inputs;
+        for i in self.exec_order.iter() {
+            let op     = &mut self.ops[*i];
+            let mods   = &self.modulations[*i];
+            let in_exprs = &self.input_exprs[*i];
+            let regs   = &mut self.regs[..];
+            if mods.is_empty() && !clamp && in_exprs.iter().all(|e| e[0].is_none()) {
+                op.exec(&ctx, regs);
+                continue;
+            }
+
+            // The op sees the evaluated expressions and the modulated and
+            // clamped values only while it is executed, so the inputs keep
+            // their base values everywhere else.
+            let bases = &mut self.exec_input_bases;
+            for (j, (min, max)) in self.input_ranges[*i].iter().enumerate() {
+                let im = mods.iter().find(|im| im.input == j);
+                let is_expr = in_exprs.get(j).is_some_and(|e| e[0].is_some());
+                if im.is_none() && !clamp && !is_expr { continue; }
+
+                let base  = op.input_value(j).unwrap_or(OpIn::Constant(0.0));
+                let mut v =
+                    match base {
+                        OpIn::Expr(id) => self.exprs.eval(id, regs),
+                        _              => base.calc(regs),
+                    };
+                if let Some(im) = im {
+                    v = im.sources.iter().fold(v, |v, (src, depth)| v + depth * regs[*src]);
+                }
+                if clamp {
+                    v = v.max(*min).min(*max);
+                }
+
+                bases.push((j, base));
+                op.set_input_by_index(j, OpIn::Constant(v), false);
+            }
+
+            op.exec(&ctx, regs);
+
+            for (j, base) in bases.drain(..) {
+                op.set_input_by_index(j, base, false);
+            }
+        }
+
+        if let Some(writer) = self.scope_writer.as_mut() {
+            writer.publish(&self.regs[..], self.scope_sample_pos);
+        }
+        self.scope_sample_pos =
+            (self.scope_sample_pos + 1) % self.scope_sample_len;
+        self.tick += 1;
     }
 
     pub fn new_group_sample_buffers(&self, size: usize) -> Vec<Vec<f32>> {
@@ -367,6 +2496,7 @@ impl Simulator {
         v
     }
 
+    /// Passes the `event` to all ops in the group `group_idx`.
     pub fn event(&mut self, group_idx: usize, event: &Event) {
         if group_idx >= self.render_groups.len() { return; }
         for i in self.render_groups[group_idx].iter() {
@@ -375,23 +2505,144 @@ impl Simulator {
         }
     }
 
+    /// Passes the `event` to all ops, regardless of their group.
+    /// Meant for transport and clock events.
+    pub fn broadcast_event(&mut self, event: &Event) {
+        for op in self.ops.iter_mut() {
+            op.event(event);
+        }
+    }
+
+    /// Queues the `event` for the ops in the group `group_idx`.
+    /// It is passed to the ops by `process` right before the sample
+    /// at `event.sample_offs` is rendered. Events at the same
+    /// sample are passed on in the order they were scheduled.
+    ///
+    /// Returns `SimulatorError::GroupIndexOutOfRange` if there is no
+    /// such group and `SimulatorError::QueueFull` if the queue already
+    /// holds `DEFAULT_EVENT_QUEUE_SIZE` events, so that scheduling from
+    /// the audio thread does not allocate.
+    pub fn schedule_event(&mut self, group_idx: usize, event: Event)
+        -> Result<(), SimulatorError> {
+
+        if group_idx >= self.render_groups.len() {
+            return Err(SimulatorError::GroupIndexOutOfRange(group_idx));
+        }
+        self.queue_event(Some(group_idx), event)
+    }
+
+    /// Like `schedule_event`, but the `event` is passed to all ops
+    /// like with `broadcast_event`.
+    pub fn schedule_broadcast_event(&mut self, event: Event)
+        -> Result<(), SimulatorError> {
+
+        self.queue_event(None, event)
+    }
+
+    fn queue_event(&mut self, group_idx: Option<usize>, event: Event)
+        -> Result<(), SimulatorError> {
+
+        if self.event_queue.len() >= self.event_queue.capacity() {
+            return Err(SimulatorError::QueueFull);
+        }
+        let pos =
+            self.event_queue.partition_point(
+                |(_, ev)| ev.sample_offs <= event.sample_offs);
+        self.event_queue.insert(pos, (group_idx, event));
+        Ok(())
+    }
+
+    /// Number of events waiting in the queue of `schedule_event`.
+    pub fn scheduled_event_count(&self) -> usize { self.event_queue.len() }
+
+    /// Renders `num_samples` samples, calling `exec` every
+    /// `sample_rate / control_rate` samples in between.
+    /// The block is split at the scheduled events, so that each event
+    /// reaches its ops right before its sample is rendered, with
+    /// `sample_offs` set to 0. Events after the end of the block are
+    /// kept and their `sample_offs` is moved to the next block.
+    ///
+    /// After passing on events, `exec` runs right away and the
+    /// regular ticks continue from there, so that ops which react in
+    /// `exec`, like the envelopes, start at the sample of the event.
+    /// `ExecContext::dt` is the time actually passed since the previous
+    /// tick then. Like with `render`, `sample_offs` is the
+    /// index of the first value in the interleaved stereo `grp_bufs`.
+    pub fn process(&mut self, num_samples: usize, sample_offs: usize,
+                   grp_bufs: &mut [Vec<f32>]) {
+
+        let samples_per_tick =
+            (self.sample_rate as f64 / self.control_rate as f64).max(1.0);
+
+        let mut pos = 0;
+        while pos < num_samples {
+            let mut due = 0;
+            while due < self.event_queue.len()
+                  && self.event_queue[due].1.sample_offs <= pos {
+
+                let (group, mut ev) = self.event_queue[due];
+                ev.sample_offs = 0;
+                match group {
+                    Some(g) => self.event(g, &ev),
+                    None    => self.broadcast_event(&ev),
+                }
+                due += 1;
+            }
+            self.event_queue.drain(0..due);
+
+            // Events restart the tick grid.
+            if due > 0 {
+                self.exec_countdown = 0.0;
+            }
+
+            if self.exec_countdown <= 0.0 {
+                let dt =
+                    if self.tick == 0 { 1.0 / self.control_rate }
+                    else { (self.samples_since_exec / self.sample_rate as f64) as f32 };
+                self.exec_after(dt);
+                self.exec_countdown    += samples_per_tick;
+                self.samples_since_exec = 0.0;
+            }
+
+            let mut len =
+                (num_samples - pos).min((self.exec_countdown.ceil() as usize).max(1));
+            if let Some((_, ev)) = self.event_queue.first() {
+                len = len.min(ev.sample_offs - pos);
+            }
+
+            self.render(len, sample_offs + pos * 2, grp_bufs);
+            pos += len;
+            self.exec_countdown     -= len as f64;
+            self.samples_since_exec += len as f64;
+        }
+
+        for (_, ev) in self.event_queue.iter_mut() {
+            ev.sample_offs = ev.sample_offs.saturating_sub(num_samples);
+        }
+    }
+
     pub fn render_silence(&mut self, num_samples: usize, sample_offs: usize,
-                  grp_bufs: &mut Vec<Vec<f32>>) {
+                  grp_bufs: &mut [Vec<f32>]) {
 
         for gb in grp_bufs.iter_mut() {
-            for i in sample_offs..(sample_offs + (num_samples * 2)) {
-                gb[i] = 0.0;
+            for s in gb[sample_offs..(sample_offs + (num_samples * 2))].iter_mut() {
+                *s = 0.0;
             }
         }
     }
 
     pub fn render(&mut self, num_samples: usize, sample_offs: usize,
-                  grp_bufs: &mut Vec<Vec<f32>>) {
+                  grp_bufs: &mut [Vec<f32>]) {
 
-        for (ig, grp) in self.render_groups.iter().enumerate() {
-            for i in sample_offs..(sample_offs + (num_samples * 2)) {
-                grp_bufs[ig][i] = 0.0;
+        // All buffers are cleared first, so that ops like `AudioSend`
+        // can mix into groups that are rendered after their own.
+        for gb in grp_bufs.iter_mut() {
+            for s in gb[sample_offs..(sample_offs + (num_samples * 2))].iter_mut() {
+                *s = 0.0;
             }
+        }
+
+        for (ig, grp) in self.render_groups.iter().enumerate() {
             for i in grp.iter() {
                 self.ops[*i].render(num_samples, sample_offs, ig, grp_bufs);
             }
@@ -399,6 +2650,10 @@ impl Simulator {
     }
 }
 
+impl Default for Simulator {
+    fn default() -> Self { Self::new() }
+}
+
 pub struct DebugRegisters {
     pub debug_regs: Vec<(String, OpIn)>,
 }
@@ -421,6 +2676,10 @@ impl DebugRegisters {
     }
 }
 
+impl Default for DebugRegisters {
+    fn default() -> Self { Self::new() }
+}
+
 pub trait RegisterView {
     fn start_print_registers(&mut self);
     fn print_register(&mut self, name: &str, value: f32);
@@ -0,0 +1,129 @@
+use serde::Serialize;
+use serde::Deserialize;
+
+/// How changes of a constant input value are smoothed, to prevent
+/// zipper noise and clicks. The times are given in seconds.
+#[derive(Debug, PartialEq, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum Smoothing {
+    /// Changes take effect immediately.
+    #[default]
+    None,
+    /// Ramps linearly to the new value.
+    Linear(f32),
+    /// Approaches the new value exponentially, it is within -60dB
+    /// of the change after the given time.
+    Exponential(f32),
+}
+
+/// An ongoing ramp of an op input from its old to its new value.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct InputRamp {
+    pub op:     usize,
+    pub input:  usize,
+    cur:        f32,
+    target:     f32,
+    step:       f32,
+    remaining:  usize,
+    linear:     bool,
+}
+
+impl InputRamp {
+    pub fn new(op: usize, input: usize, from: f32, to: f32,
+               smoothing: Smoothing, control_rate: f32) -> Self {
+        let mut r = InputRamp {
+            op, input,
+            cur:        from,
+            target:     from,
+            step:       0.0,
+            remaining:  0,
+            linear:     true,
+        };
+        r.retarget(to, smoothing, control_rate);
+        r
+    }
+
+    /// Starts ramping from the current value to `to`. `control_rate`
+    /// is the number of `next_value` calls per second.
+    pub fn retarget(&mut self, to: f32, smoothing: Smoothing, control_rate: f32) {
+        let (time, linear) =
+            match smoothing {
+                Smoothing::None           => (0.0, true),
+                Smoothing::Linear(t)      => (t, true),
+                Smoothing::Exponential(t) => (t, false),
+            };
+        let ticks = (time * control_rate).round().max(1.0);
+
+        self.target    = to;
+        self.remaining = ticks as usize;
+        self.linear    = linear;
+        self.step      =
+            if linear { (to - self.cur) / ticks }
+            else      { 0.001_f32.powf(1.0 / ticks) };
+    }
+
+    pub fn value(&self) -> f32 { self.cur }
+
+    pub fn is_done(&self) -> bool { self.remaining == 0 }
+
+    /// Advances the ramp by one tick and returns the new value.
+    pub fn next_value(&mut self) -> f32 {
+        if self.remaining <= 1 {
+            self.remaining = 0;
+            self.cur       = self.target;
+        } else {
+            self.remaining -= 1;
+            self.cur =
+                if self.linear { self.cur + self.step }
+                else { self.target + (self.cur - self.target) * self.step };
+        }
+        self.cur
+    }
+}
+
+/// Interpolates a value that is calculated once per `exec` tick across
+/// the samples until the next tick, so that changes don't step at block
+/// boundaries. Set the new value in `Op::exec` with `set` and iterate
+/// over the per sample values in `Op::render` with `ramp`. The ramp
+/// continues across `render` calls, which may be shorter than a tick.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ControlInterp {
+    value:      f32,
+    target:     f32,
+    step:       f32,
+    remaining:  usize,
+}
+
+impl ControlInterp {
+    pub fn new(value: f32) -> Self {
+        ControlInterp { value, target: value, step: 0.0, remaining: 0 }
+    }
+
+    /// Ramps from the current value to `target` within the next
+    /// `num_samples` samples, usually the samples per tick
+    /// (see `ExecContext::samples_per_tick`).
+    pub fn set(&mut self, target: f32, num_samples: usize) {
+        let num_samples = num_samples.max(1);
+        self.target    = target;
+        self.step      = (target - self.value) / (num_samples as f32);
+        self.remaining = num_samples;
+    }
+
+    pub fn target(&self) -> f32 { self.target }
+
+    /// Returns the value for the next sample. Once the ramp is over,
+    /// this is the target value.
+    pub fn next_value(&mut self) -> f32 {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            self.value =
+                if self.remaining == 0 { self.target }
+                else { self.value + self.step };
+        }
+        self.value
+    }
+
+    /// Returns the values for the next `num_samples` samples.
+    pub fn ramp(&mut self, num_samples: usize) -> impl Iterator<Item = f32> + '_ {
+        (0..num_samples).map(move |_| self.next_value())
+    }
+}
@@ -0,0 +1,106 @@
+//! Minimal writer for RIFF WAVE files.
+
+use std::convert::TryFrom;
+use std::io::{Error, ErrorKind, Write};
+
+/// Sample format of a written WAV file.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum WavFormat {
+    Int16,
+    Int24,
+    Float32,
+}
+
+impl WavFormat {
+    fn bytes_per_sample(self) -> u16 {
+        match self {
+            WavFormat::Int16   => 2,
+            WavFormat::Int24   => 3,
+            WavFormat::Float32 => 4,
+        }
+    }
+}
+
+/// Writes the interleaved `samples` with `channels` channels as WAV file.
+/// The integer formats clip the samples to the range -1.0 to 1.0.
+/// Float files get the extended `fmt ` chunk and the `fact` chunk that
+/// non PCM formats require. Fails with `ErrorKind::InvalidInput` if the
+/// file would exceed the 4 GiB limit of RIFF.
+pub fn write_wav<W: Write>(out: &mut W, samples: &[f32], channels: u16,
+                           sample_rate: u32, format: WavFormat)
+    -> std::io::Result<()> {
+
+    if channels == 0 {
+        return Err(Error::new(ErrorKind::InvalidInput, "WAV file without channels"));
+    }
+
+    let is_float         = format == WavFormat::Float32;
+    let bytes_per_sample = format.bytes_per_sample();
+    let block_align      = channels * bytes_per_sample;
+    let format_tag : u16 = if is_float { 3 } else { 1 };
+    let fmt_len    : u32 = if is_float { 18 } else { 16 };
+
+    // The data chunk is padded to an even length.
+    let data_len  = samples.len() as u64 * u64::from(bytes_per_sample);
+    let pad_len   = data_len % 2;
+    let fact_len  = if is_float { 12 } else { 0 };
+    let riff_len  =
+        4 + (8 + u64::from(fmt_len)) + fact_len + 8 + data_len + pad_len;
+    let too_large = || Error::new(ErrorKind::InvalidInput, "WAV file exceeds 4 GiB");
+    let riff_len  = u32::try_from(riff_len).map_err(|_| too_large())?;
+    let byte_rate =
+        sample_rate.checked_mul(u32::from(block_align)).ok_or_else(too_large)?;
+
+    let mut hdr = Vec::with_capacity(58);
+    hdr.extend_from_slice(b"RIFF");
+    hdr.extend_from_slice(&riff_len.to_le_bytes());
+    hdr.extend_from_slice(b"WAVE");
+    hdr.extend_from_slice(b"fmt ");
+    hdr.extend_from_slice(&fmt_len.to_le_bytes());
+    hdr.extend_from_slice(&format_tag.to_le_bytes());
+    hdr.extend_from_slice(&channels.to_le_bytes());
+    hdr.extend_from_slice(&sample_rate.to_le_bytes());
+    hdr.extend_from_slice(&byte_rate.to_le_bytes());
+    hdr.extend_from_slice(&block_align.to_le_bytes());
+    hdr.extend_from_slice(&(bytes_per_sample * 8).to_le_bytes());
+    if is_float {
+        // cbSize, no extension data follows.
+        hdr.extend_from_slice(&0u16.to_le_bytes());
+        hdr.extend_from_slice(b"fact");
+        hdr.extend_from_slice(&4u32.to_le_bytes());
+        let frames = (samples.len() / usize::from(channels)) as u32;
+        hdr.extend_from_slice(&frames.to_le_bytes());
+    }
+    hdr.extend_from_slice(b"data");
+    hdr.extend_from_slice(&(data_len as u32).to_le_bytes());
+    out.write_all(&hdr)?;
+
+    // Converted in chunks, to not hold a second copy of the samples.
+    let mut buf = [0u8; 4 * 1024];
+    for chunk in samples.chunks(1024) {
+        let mut len = 0;
+        for s in chunk.iter() {
+            let c = s.clamp(-1.0, 1.0);
+            match format {
+                WavFormat::Int16 => {
+                    let v = (c * 32767.0).round() as i16;
+                    buf[len..(len + 2)].copy_from_slice(&v.to_le_bytes());
+                },
+                WavFormat::Int24 => {
+                    let v = (c * 8_388_607.0).round() as i32;
+                    buf[len..(len + 3)].copy_from_slice(&v.to_le_bytes()[0..3]);
+                },
+                WavFormat::Float32 => {
+                    buf[len..(len + 4)].copy_from_slice(&s.to_le_bytes());
+                },
+            }
+            len += usize::from(bytes_per_sample);
+        }
+        out.write_all(&buf[0..len])?;
+    }
+
+    if pad_len > 0 {
+        out.write_all(&[0])?;
+    }
+    Ok(())
+}
@@ -0,0 +1,108 @@
+//! Tests of editing a running `Simulator` with `replace_op` and
+//! `move_op_to_group`.
+
+use wctr_signal_ops::*;
+use wctr_signal_ops::ops::OutProxy;
+
+fn input(sim: &Simulator, op: usize, name: &str) -> Option<OpIn> {
+    sim.ops[op].input_value(sim.ops[op].input_index(name).unwrap())
+}
+
+/// `src` with three outputs, a `sin` op `b` that reads all of them and
+/// `late` after them, whose register moves when `src` changes size.
+fn setup() -> Simulator {
+    let registry = OpRegistry::new();
+    let mut sim = Simulator::new();
+    sim.add_group("main");
+    sim.add_group("fx");
+
+    let src = OutProxy::new(3);
+    src.values.borrow_mut().copy_from_slice(&[1.0, 2.0, 3.0]);
+    sim.add_op(Box::new(src), "src".to_string(), 0);
+    sim.add_op(registry.create("sin", &[]).unwrap(), "b".to_string(), 0);
+    let late = OutProxy::new(1);
+    late.values.borrow_mut()[0] = 4.0;
+    sim.add_op(Box::new(late), "late".to_string(), 0);
+
+    let r = |sim: &mut Simulator, name: &str| sim.resolve_reg(name).unwrap();
+    let (out0, out1, out2, late) =
+        (r(&mut sim, "src.out0"), r(&mut sim, "src.out1"),
+         r(&mut sim, "src.out2"), r(&mut sim, "late.out0"));
+    sim.set_op_input(1, "amp",   OpIn::Reg(out0), false).unwrap();
+    sim.set_op_input(1, "phase", OpIn::RegAdd(out1, 0.5), false).unwrap();
+    sim.set_op_input(1, "freq",  OpIn::RegAdd(out2, 0.5), false).unwrap();
+    sim.set_op_input(1, "vert",  OpIn::Reg(late), false).unwrap();
+    sim.exec();
+    sim
+}
+
+#[test]
+fn replacing_with_fewer_outputs_remaps_the_remaining_ones() {
+    let mut sim = setup();
+    let old_late = sim.resolve_reg("late.out0").unwrap();
+    let b_out    = sim.resolve_reg("b.out").unwrap();
+    let b_value  = sim.get_reg(b_out);
+
+    let old = sim.replace_op(0, Box::new(OutProxy::new(2))).unwrap();
+    assert_eq!(old.output_count(), 3);
+    assert_eq!(sim.op_infos[0].name, "src");
+
+    let out0 = sim.resolve_reg("src.out0").unwrap();
+    let out1 = sim.resolve_reg("src.out1").unwrap();
+    let late = sim.resolve_reg("late.out0").unwrap();
+    assert!(sim.resolve_reg("src.out2").is_err());
+    assert_eq!(late, old_late - 1);
+
+    assert_eq!(input(&sim, 1, "amp"),   Some(OpIn::Reg(out0)));
+    assert_eq!(input(&sim, 1, "phase"), Some(OpIn::RegAdd(out1, 0.5)));
+    // The output is gone, so the input keeps the value it last read.
+    assert_eq!(input(&sim, 1, "freq"),  Some(OpIn::Constant(3.5)));
+    assert_eq!(input(&sim, 1, "vert"),  Some(OpIn::Reg(late)));
+
+    // The registers of the other ops keep their values, the new
+    // outputs start at zero.
+    assert_eq!(sim.get_reg(late), 4.0);
+    let b_out = sim.resolve_reg("b.out").unwrap();
+    assert_eq!(sim.get_reg(b_out), b_value);
+    assert_eq!(sim.get_reg(out0), 0.0);
+}
+
+#[test]
+fn replacing_with_renamed_outputs_disconnects_the_inputs() {
+    let mut sim = setup();
+    let b_out = sim.resolve_reg("b.out").unwrap();
+    let v     = sim.get_reg(b_out);
+    let registry = OpRegistry::new();
+    sim.add_op(registry.create("sin", &[]).unwrap(), "c".to_string(), 0);
+    let c = sim.get_op_index("c").unwrap();
+    sim.set_op_input(c, "freq", OpIn::RegAdd(b_out, 1.0), false).unwrap();
+    let op_in = sim.parse_expr("b.out * 2").unwrap();
+    sim.set_op_input(c, "phase", op_in, false).unwrap();
+
+    // An `OutProxy` calls its output `out0` instead of `out`.
+    sim.replace_op(1, Box::new(OutProxy::new(1))).unwrap();
+    assert!(sim.resolve_reg("b.out").is_err());
+    assert_eq!(input(&sim, c, "freq"),  Some(OpIn::Constant(v + 1.0)));
+    assert_eq!(input(&sim, c, "phase"), Some(OpIn::Constant(v * 2.0)));
+    // The inputs of the new op are its own.
+    assert_eq!(sim.ops[1].input_count(), 0);
+}
+
+#[test]
+fn moving_an_op_to_another_group() {
+    let mut sim = setup();
+    assert_eq!(sim.render_groups, vec![vec![0, 1, 2], vec![]]);
+
+    assert!(sim.move_op_to_group(1, 1));
+    assert_eq!(sim.render_groups, vec![vec![0, 2], vec![1]]);
+    assert_eq!((&sim.op_infos[1].group.name[..], sim.op_infos[1].group.index), ("fx", 1));
+
+    // Moving it again does not list it twice.
+    assert!(sim.move_op_to_group(1, 1));
+    assert_eq!(sim.render_groups, vec![vec![0, 2], vec![1]]);
+
+    assert!(!sim.move_op_to_group(1, 2));
+    assert!(!sim.move_op_to_group(3, 0));
+    assert_eq!(sim.render_groups, vec![vec![0, 2], vec![1]]);
+    assert_eq!(sim.op_infos[1].group.name, "fx");
+}
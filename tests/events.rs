@@ -0,0 +1,112 @@
+//! Tests of passing events to the ops of a group or to all ops.
+
+use wctr_signal_ops::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+type EventLog = Rc<RefCell<Vec<EventKind>>>;
+
+/// Records the kinds of the events it receives.
+struct Recorder {
+    log: EventLog,
+}
+
+impl Op for Recorder {
+    fn type_name(&self) -> &'static str { "recorder" }
+
+    fn io_spec(&self, index: usize) -> OpIOSpec {
+        OpIOSpec {
+            index,
+            inputs:           vec![],
+            input_values:     vec![],
+            input_defaults:   vec![],
+            audio_out_groups: vec![],
+            outputs:          vec![],
+            output_regs:      vec![],
+        }
+    }
+
+    fn init_regs(&mut self, _start_reg: usize, _regs: &mut [f32]) { }
+    fn output_reg(&self, _idx: usize) -> Option<usize> { None }
+    fn set_input_by_index(&mut self, _idx: usize, _to: OpIn, _as_default: bool) -> bool { false }
+    fn event(&mut self, ev: &Event) { self.log.borrow_mut().push(ev.kind); }
+    fn exec(&mut self, _ctx: &ExecContext, _regs: &mut [f32]) { }
+}
+
+/// A recorder in each of the groups "a" and "b".
+fn setup() -> (Simulator, EventLog, EventLog) {
+    let a = Rc::new(RefCell::new(Vec::new()));
+    let b = Rc::new(RefCell::new(Vec::new()));
+    let mut sim = Simulator::new();
+    sim.add_group("a");
+    sim.add_group("b");
+    sim.add_op(Box::new(Recorder { log: a.clone() }), "a".to_string(), 0);
+    sim.add_op(Box::new(Recorder { log: b.clone() }), "b".to_string(), 1);
+    sim.set_rates(1000.0, 16000.0);
+    (sim, a, b)
+}
+
+const NOTE_ON : EventKind = EventKind::NoteOn { channel: 3, note: 60, velocity: 1.0 };
+
+#[test]
+fn channel_events_report_their_channel() {
+    let kinds = [
+        (NOTE_ON,                                                     Some(3)),
+        (EventKind::NoteOff { channel: 1, note: 60, velocity: 0.0 },  Some(1)),
+        (EventKind::ControlChange { channel: 2, controller: 7, value: 1.0 }, Some(2)),
+        (EventKind::PitchBend { channel: 15, value: -1.0 },           Some(15)),
+        (EventKind::Aftertouch { channel: 0, value: 0.5 },            Some(0)),
+        (EventKind::PolyAftertouch { channel: 4, note: 60, value: 0.5 }, Some(4)),
+        (EventKind::Start,                                            None),
+        (EventKind::Stop,                                             None),
+        (EventKind::Continue,                                         None),
+        (EventKind::Clock,                                            None),
+    ];
+    for (kind, channel) in kinds.iter() {
+        assert_eq!(Event::new(*kind).channel(), *channel, "{:?}", kind);
+    }
+    assert_eq!(Event::note_on(3, 60, 1.0), Event::new(NOTE_ON));
+}
+
+#[test]
+fn events_reach_the_ops_of_their_group() {
+    let (mut sim, a, b) = setup();
+
+    sim.event(1, &Event::new(NOTE_ON));
+    // There is no such group.
+    sim.event(2, &Event::new(NOTE_ON));
+    sim.broadcast_event(&Event::new(EventKind::Start));
+
+    assert_eq!(*a.borrow(), vec![EventKind::Start]);
+    assert_eq!(*b.borrow(), vec![NOTE_ON, EventKind::Start]);
+}
+
+#[test]
+fn scheduled_events_reach_the_ops_of_their_group() {
+    let (mut sim, a, b) = setup();
+    let mut bufs = sim.new_group_sample_buffers(32);
+
+    sim.schedule_event(1, Event::at(8, NOTE_ON)).unwrap();
+    sim.schedule_broadcast_event(Event::at(4, EventKind::Clock)).unwrap();
+    sim.process(32, 0, &mut bufs);
+
+    assert_eq!(*a.borrow(), vec![EventKind::Clock]);
+    assert_eq!(*b.borrow(), vec![EventKind::Clock, NOTE_ON]);
+}
+
+#[test]
+fn sequences_broadcast_events_without_channel() {
+    let (mut sim, a, b) = setup();
+    let mut bufs = sim.new_group_sample_buffers(32);
+
+    let events = [Event::new(NOTE_ON), Event::new(EventKind::Clock)];
+    let mut seq = MidiSequence::new(
+        events.iter().enumerate()
+            .map(|(i, event)| TimedEvent { time: i as f64 * 0.001, track: 0, event: *event })
+            .collect());
+    seq.schedule_block(&mut sim, 1, 32, 16000.0).unwrap();
+    sim.process(32, 0, &mut bufs);
+
+    assert_eq!(*a.borrow(), vec![EventKind::Clock]);
+    assert_eq!(*b.borrow(), vec![NOTE_ON, EventKind::Clock]);
+}
@@ -0,0 +1,82 @@
+//! Tests of the exec order `Simulator::update_exec_order` derives from
+//! the registers the ops read and write.
+
+use wctr_signal_ops::*;
+
+/// A simulator with a `sin` op per name, in that order.
+fn sim_with(names: &[&str]) -> Simulator {
+    let registry = OpRegistry::new();
+    let mut sim = Simulator::new();
+    sim.add_group("main");
+    for name in names.iter() {
+        sim.add_op(registry.create("sin", &[]).unwrap(), name.to_string(), 0);
+    }
+    sim
+}
+
+/// Lets the `freq` of op `to` read the output of op `from`.
+fn connect(sim: &mut Simulator, from: &str, to: &str) {
+    let r  = sim.resolve_reg(&format!("{}.out", from)).unwrap();
+    let to = sim.get_op_index(to).unwrap();
+    sim.set_op_input(to, "freq", OpIn::RegAdd(r, 1.0), false).unwrap();
+}
+
+#[test]
+fn dependencies_run_first_regardless_of_add_order() {
+    let mut sim = sim_with(&["c", "x", "b", "a", "y"]);
+    connect(&mut sim, "a", "b");
+    connect(&mut sim, "b", "c");
+    assert_eq!(sim.update_exec_order(), Ok(()));
+    // Independent ops keep their insertion order.
+    assert_eq!(sim.exec_order, vec![1, 3, 2, 0, 4]);
+
+    let mut sim = sim_with(&["a", "b", "c"]);
+    connect(&mut sim, "a", "b");
+    connect(&mut sim, "b", "c");
+    assert_eq!(sim.update_exec_order(), Ok(()));
+    assert_eq!(sim.exec_order, vec![0, 1, 2]);
+
+    // Reading a register through an expression counts too.
+    let mut sim = sim_with(&["b", "a"]);
+    let op_in = sim.parse_expr("a.out * 2").unwrap();
+    sim.set_op_input(0, "freq", op_in, false).unwrap();
+    sim.exec();
+    assert_eq!(sim.exec_order, vec![1, 0]);
+}
+
+#[test]
+fn cycles_and_self_loops_are_reported() {
+    let mut sim = sim_with(&["a", "b", "c", "d", "e"]);
+    connect(&mut sim, "a", "b");
+    connect(&mut sim, "b", "a");
+    connect(&mut sim, "c", "c");
+    // Depends on the cycle, but is not part of it.
+    connect(&mut sim, "b", "d");
+
+    assert_eq!(sim.update_exec_order(), Err(vec![0, 1, 2]));
+    assert_eq!(sim.feedback_ops, vec![0, 1, 2]);
+    // The ops of the cycle and the ones after it still run,
+    // after all other ops.
+    assert_eq!(sim.exec_order, vec![2, 4, 0, 1, 3]);
+}
+
+#[test]
+fn delay_edges_clear_the_cycle_report() {
+    let mut sim = sim_with(&["a", "b", "c"]);
+    connect(&mut sim, "a", "b");
+    connect(&mut sim, "b", "a");
+    connect(&mut sim, "c", "c");
+    assert_eq!(sim.update_exec_order(), Err(vec![0, 1, 2]));
+
+    // `b` reads the value `a` wrote in the previous tick, so `a` runs
+    // after it.
+    sim.set_delay_edge(0, 1, true);
+    assert_eq!(sim.update_exec_order(), Err(vec![2]));
+    sim.set_delay_edge(2, 2, true);
+    assert_eq!(sim.update_exec_order(), Ok(()));
+    assert!(sim.feedback_ops.is_empty());
+    assert_eq!(sim.exec_order, vec![1, 0, 2]);
+
+    sim.set_delay_edge(0, 1, false);
+    assert_eq!(sim.update_exec_order(), Err(vec![0, 1]));
+}
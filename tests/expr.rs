@@ -0,0 +1,254 @@
+//! Tests of the expressions of `OpIn::Expr`.
+
+use wctr_signal_ops::*;
+use wctr_signal_ops::expr::{EXPR_MAX_NESTING, EXPR_MAX_STACK};
+use wctr_signal_ops::ops::OutProxy;
+
+const VERT : usize = 2;
+
+fn eval(src: &str, regs: &[f32]) -> f32 {
+    Expr::parse(src).unwrap().eval(regs)
+}
+
+fn canonical(src: &str) -> String {
+    Expr::parse(src).unwrap().as_str().to_string()
+}
+
+fn expr_id(op_in: OpIn) -> Option<ExprId> {
+    match op_in { OpIn::Expr(id) => Some(id), _ => None }
+}
+
+#[test]
+fn evaluates_operators_and_functions() {
+    let regs = [2.0, -3.0, 0.5];
+    assert_eq!(eval("1.5", &regs), 1.5);
+    assert_eq!(eval("r0 + r1", &regs), -1.0);
+    assert_eq!(eval("r0 - r1", &regs), 5.0);
+    assert_eq!(eval("r0 * r2", &regs), 1.0);
+    assert_eq!(eval("r1 / r0", &regs), -1.5);
+    assert_eq!(eval("-r1", &regs), 3.0);
+    assert_eq!(eval("--r1", &regs), -3.0);
+    assert_eq!(eval("min(r0, r1)", &regs), -3.0);
+    assert_eq!(eval("max(r0, r1)", &regs), 2.0);
+    assert_eq!(eval("clamp(r1, -1, 1)", &regs), -1.0);
+    assert_eq!(eval("abs(r1)", &regs), 3.0);
+    assert_eq!(eval("pow(r0, 3)", &regs), 8.0);
+    assert_eq!(eval("sin(0)", &regs), 0.0);
+    assert_eq!(eval("cos(0)", &regs), 1.0);
+    assert_eq!(eval("pi", &regs), std::f32::consts::PI);
+}
+
+#[test]
+fn evaluates_comparisons_and_select() {
+    let regs = [1.0, 2.0];
+    assert_eq!(eval("r0 < r1", &regs), 1.0);
+    assert_eq!(eval("r0 > r1", &regs), 0.0);
+    assert_eq!(eval("r0 <= 1", &regs), 1.0);
+    assert_eq!(eval("r0 >= 2", &regs), 0.0);
+    assert_eq!(eval("r0 == 1", &regs), 1.0);
+    assert_eq!(eval("r0 != 1", &regs), 0.0);
+    assert_eq!(eval("r0 > 0 ? 10 : 20", &regs), 10.0);
+    assert_eq!(eval("r0 - 1 ? 10 : 20", &regs), 20.0);
+}
+
+#[test]
+fn precedence() {
+    assert_eq!(eval("1 + 2 * 3", &[]), 7.0);
+    assert_eq!(eval("(1 + 2) * 3", &[]), 9.0);
+    assert_eq!(eval("8 - 4 - 2", &[]), 2.0);
+    assert_eq!(eval("8 / 4 / 2", &[]), 1.0);
+    assert_eq!(eval("-2 * 3", &[]), -6.0);
+    assert_eq!(eval("1 + 1 < 3", &[]), 1.0);
+    assert_eq!(eval("0 ? 1 : 0 ? 2 : 3", &[]), 3.0);
+    assert_eq!(eval("1 ? 0 ? 4 : 5 : 6", &[]), 5.0);
+
+    assert_eq!(canonical("r1 + r2 * 3"), "(r1 + (r2 * 3))");
+    assert_eq!(canonical("-r1 * 2 < 3 ? 1 : 0"), "((((-r1) * 2) < 3) ? 1 : 0)");
+}
+
+#[test]
+fn canonical_form_parses_to_the_same_expression() {
+    for src in ["r1 + r2 * -r3", "min(r0, 2) ? pow(r1, 0.5) : clamp(r2, 0, 1)",
+                "(r0 <= 1) == (r1 > 2)", "abs(sin(r0 * pi))"].iter() {
+        let e = Expr::parse(src).unwrap();
+        let again = Expr::parse(e.as_str()).unwrap();
+        assert_eq!(e, again, "{}", src);
+        assert_eq!(e.as_str(), again.as_str());
+    }
+}
+
+#[test]
+fn constants_round_trip_through_the_canonical_form() {
+    let max = format!("{}", f32::MAX);
+    for src in [&max[..], "0.000001", "123456.79"].iter() {
+        let e = Expr::parse(src).unwrap();
+        let again = Expr::parse(e.as_str()).unwrap();
+        assert_eq!(again.eval(&[]), e.eval(&[]), "{}", src);
+    }
+    assert_eq!(Expr::parse(&format!("{} * 2", max)).unwrap().eval(&[]), f32::INFINITY);
+
+    // Too large for an `f32`, it would be written as `inf`.
+    let huge = "9".repeat(40);
+    assert_eq!(Expr::parse(&huge),
+               Err(ExprError::Syntax(0, "number out of range".to_string())));
+    assert!(matches!(Expr::parse(&format!("r0 + {}", huge)), Err(ExprError::Syntax(5, _))));
+}
+
+#[test]
+fn errors() {
+    assert!(matches!(Expr::parse("1 +"), Err(ExprError::Syntax(3, _))));
+    assert!(matches!(Expr::parse("(1"), Err(ExprError::Syntax(2, _))));
+    assert!(matches!(Expr::parse("1 2"), Err(ExprError::Syntax(2, _))));
+    assert!(matches!(Expr::parse("1 # 2"), Err(ExprError::Syntax(2, _))));
+    assert!(matches!(Expr::parse("1..2"), Err(ExprError::Syntax(0, _))));
+    // Comparisons do not chain.
+    assert!(matches!(Expr::parse("1 < 2 < 3"), Err(ExprError::Syntax(6, _))));
+    assert_eq!(Expr::parse("foo + 1"), Err(ExprError::UnknownName("foo".to_string())));
+    assert_eq!(Expr::parse("r"), Err(ExprError::UnknownName("r".to_string())));
+    assert_eq!(Expr::parse("tan(1)"), Err(ExprError::UnknownName("tan".to_string())));
+    assert_eq!(Expr::parse("min(1)"), Err(ExprError::BadArgCount("min".to_string())));
+    assert_eq!(Expr::parse("abs()"), Err(ExprError::BadArgCount("abs".to_string())));
+}
+
+#[test]
+fn stack_depth_is_limited() {
+    // Each `(1 + ` keeps one more value on the stack.
+    let nested = |n: usize| format!("{}1{}", "(1 + ".repeat(n), ")".repeat(n));
+    assert!(Expr::parse(&nested(EXPR_MAX_STACK - 1)).is_ok());
+    assert_eq!(Expr::parse(&nested(EXPR_MAX_STACK)), Err(ExprError::TooDeep));
+}
+
+#[test]
+fn nesting_is_limited() {
+    let parens = |n: usize| format!("{}r0{}", "(".repeat(n), ")".repeat(n));
+    assert!(Expr::parse(&parens(EXPR_MAX_NESTING - 1)).is_ok());
+    assert_eq!(Expr::parse(&parens(EXPR_MAX_NESTING)), Err(ExprError::TooDeep));
+
+    // Would overflow the stack of the parser without the limit.
+    assert_eq!(Expr::parse(&parens(1_000_000)), Err(ExprError::TooDeep));
+    assert_eq!(Expr::parse(&"-".repeat(1_000_000)), Err(ExprError::TooDeep));
+    assert_eq!(Expr::parse(&"abs(".repeat(1_000_000)), Err(ExprError::TooDeep));
+    assert_eq!(Expr::parse(&"1 ? ".repeat(1_000_000)), Err(ExprError::TooDeep));
+}
+
+#[test]
+fn map_regs() {
+    let e = Expr::parse("r0 + r2 * r0").unwrap();
+    let mut regs = Vec::new();
+    e.for_each_reg(|r| regs.push(r));
+    assert_eq!(regs, vec![0, 2, 0]);
+
+    let mapped = e.map_regs(|r| Some(r + 1)).unwrap();
+    assert_eq!(mapped.as_str(), "(r1 + (r3 * r1))");
+    assert_eq!(e.map_regs(Some).unwrap(), e);
+    assert!(e.map_regs(|r| if r == 2 { None } else { Some(r) }).is_none());
+}
+
+#[test]
+fn serde_round_trip() {
+    let registry = OpRegistry::new();
+    let mut sim = Simulator::new();
+    sim.add_group("main");
+    sim.add_op(registry.create("sin", &[]).unwrap(), "osc".to_string(), 0);
+
+    let op_in = sim.parse_expr("osc.out > 0.5 ? min(osc.out, 2) : -1").unwrap();
+    let named = sim.name_op_in(&op_in).unwrap();
+    let json  = serde_json::to_string(&named).unwrap();
+    assert_eq!(json,
+        r#"{"op_in":{"Constant":-1.0},"regs":["osc.out","osc.out"],"expr":"((r0 > 0.5) ? min(r1, 2) : (-1))"}"#);
+
+    let back : NamedOpIn = serde_json::from_str(&json).unwrap();
+    let again = sim.resolve_op_in(&back).unwrap();
+    match (op_in, again) {
+        (OpIn::Expr(a), OpIn::Expr(b)) =>
+            assert_eq!(sim.expr(a).unwrap(), sim.expr(b).unwrap()),
+        _ => panic!("not an expression: {:?}", again),
+    }
+
+    let bad = NamedOpIn::from_expr("r0 +", &["osc.out"]);
+    assert!(matches!(sim.resolve_op_in(&bad), Err(SimulatorError::BadExpr(_))));
+}
+
+#[test]
+fn simulator_resolves_register_names() {
+    let registry = OpRegistry::new();
+    let mut sim = Simulator::new();
+    sim.add_group("main");
+    sim.add_op(registry.create("sin", &[]).unwrap(), "osc".to_string(), 0);
+    let out = sim.resolve_reg("osc.out").unwrap();
+
+    let op_in = sim.parse_expr("osc.out * 2").unwrap();
+    let id    = match op_in { OpIn::Expr(id) => id, _ => panic!("{:?}", op_in) };
+    assert_eq!(sim.expr(id).unwrap(), &Expr::parse(&format!("r{} * 2", out)).unwrap());
+
+    assert!(sim.parse_expr("osc.nope").is_err());
+    assert_eq!(sim.parse_expr("r99"), Err(SimulatorError::RegOutOfRange(99)));
+    assert!(matches!(sim.parse_expr("osc.out +"),
+                     Err(SimulatorError::BadExpr(ExprError::Syntax(_, _)))));
+}
+
+#[test]
+fn simulator_evaluates_expressions_when_executing() {
+    let registry = OpRegistry::new();
+    let mut sim = Simulator::new();
+    sim.add_group("main");
+    // With amp 1.0 and freq 0.0 the sin outputs its `vert` input.
+    sim.add_op(registry.create("sin", &[]).unwrap(), "s".to_string(), 0);
+    for sm in sim.input_smoothing[0].iter_mut() { *sm = Smoothing::None; }
+    for (name, v) in [("amp", 1.0), ("phase", 0.0), ("freq", 0.0)].iter() {
+        sim.set_op_input(0, name, OpIn::Constant(*v), false).unwrap();
+    }
+    let proxy  = OutProxy::new(1);
+    let values = proxy.values.clone();
+    sim.add_op(Box::new(proxy), "src".to_string(), 0);
+    let out = sim.resolve_reg("s.out").unwrap();
+    values.borrow_mut()[0] = 2.0;
+
+    let op_in = sim.parse_expr("src.out0 * 0.5").unwrap();
+    sim.set_op_input(0, "vert", op_in, false).unwrap();
+    sim.exec();
+    assert_eq!(sim.get_reg(out), 1.0);
+    // The input keeps the expression, it is only evaluated for the op.
+    assert_eq!(sim.ops[0].input_value(VERT), Some(op_in));
+    assert_eq!(sim.calc_input(&op_in), 1.0);
+
+    values.borrow_mut()[0] = 4.0;
+    sim.exec();
+    assert_eq!(sim.get_reg(out), 2.0);
+
+    // Without its register the expression turns into its last value.
+    sim.remove_op(1);
+    assert_eq!(sim.ops[0].input_value(VERT), Some(OpIn::Constant(2.0)));
+    assert!(expr_id(op_in).and_then(|id| sim.expr(id)).is_none());
+}
+
+#[test]
+fn simulator_frees_unused_expressions() {
+    let registry = OpRegistry::new();
+    let mut sim = Simulator::new();
+    sim.add_group("main");
+    sim.add_op(registry.create("sin", &[]).unwrap(), "s".to_string(), 0);
+
+    // Not set to an input, so the next exec frees it.
+    let unused = expr_id(sim.parse_expr("1 + 2").unwrap()).unwrap();
+    assert!(sim.expr(unused).is_some());
+    sim.exec();
+    assert!(sim.expr(unused).is_none());
+
+    // Shared by two inputs, freed when the last one is set to another value.
+    let op_in = sim.parse_expr("s.out + 1").unwrap();
+    let id    = expr_id(op_in).unwrap();
+    sim.set_op_input(0, "freq", op_in, false).unwrap();
+    sim.set_op_input(0, "freq", op_in, true).unwrap();
+    sim.exec();
+    sim.set_op_input(0, "freq", OpIn::Constant(1.0), false).unwrap();
+    assert!(sim.expr(id).is_some());
+    sim.set_op_input(0, "freq", OpIn::Constant(1.0), true).unwrap();
+    assert!(sim.expr(id).is_none());
+
+    // An outdated id is rejected, even once its slot is reused.
+    let again = sim.parse_expr("2 * 3").unwrap();
+    assert_ne!(expr_id(again), Some(id));
+    assert_eq!(sim.set_op_input(0, "freq", OpIn::Expr(id), false),
+               Err(SimulatorError::UnknownExpr(id)));
+}
@@ -0,0 +1,294 @@
+//! Golden output regression tests for the shipped ops.
+//!
+//! Each case builds a `Simulator` from a small `Patch`, runs `exec` for a
+//! number of ticks and captures the registers of each tick via the scope
+//! `SampleRow`. Cases with audio also render a block per tick and capture
+//! the group buffers. The result is compared with `tests/golden/<case>.txt`
+//! within `TOLERANCE`.
+//!
+//! To regenerate the golden files after an intended change of the output:
+//!
+//!     GOLDEN_REGEN=1 cargo test --test golden
+
+use wctr_signal_ops::*;
+use wctr_signal_ops::ops::OutProxy;
+use wctr_signal_ops::signals::OpGroup;
+use std::path::PathBuf;
+
+const TOLERANCE     : f32 = 1e-5;
+const CONTROL_RATE  : f32 = 1000.0;
+/// Samples rendered after each `exec` in cases with audio.
+const BLOCK_SIZE    : usize = 4;
+
+/// Called before each tick with the tick number, to change inputs
+/// while running.
+type Step = Box<dyn FnMut(&mut Simulator, usize)>;
+
+struct Case {
+    name:   &'static str,
+    ticks:  usize,
+    /// Render a block after each tick and capture the group buffers.
+    audio:  bool,
+    build:  fn() -> (Simulator, Step),
+}
+
+fn op(op_type: &str, name: &str, group: &str, inputs: &[(&str, NamedOpIn)]) -> PatchOp {
+    PatchOp {
+        op_type:    op_type.to_string(),
+        type_args:  vec![],
+        name:       name.to_string(),
+        group:      group.to_string(),
+        inputs:     inputs.iter().map(|(n, v)| (n.to_string(), v.clone())).collect(),
+        defaults:   vec![],
+        modulations: vec![],
+    }
+}
+
+fn c(v: f32) -> NamedOpIn { NamedOpIn::new(OpIn::Constant(v), &[]) }
+
+fn sim_from_patch(patch: &Patch) -> Simulator {
+    let mut registry = OpRegistry::new();
+    registry.register("test_tone", "Saw test tone", |_| Box::new(TestTone { phase: 0 }));
+
+    let mut sim = Simulator::new();
+    sim.load_patch(patch, &registry).expect("patch loads");
+    sim.set_rates(CONTROL_RATE, CONTROL_RATE * BLOCK_SIZE as f32);
+    sim
+}
+
+/// Writes a saw with a period of 8 samples into its group.
+struct TestTone {
+    phase: usize,
+}
+
+impl Op for TestTone {
+    fn type_name(&self) -> &'static str { "test_tone" }
+
+    fn io_spec(&self, index: usize) -> OpIOSpec {
+        OpIOSpec {
+            index,
+            inputs:           vec![],
+            input_values:     vec![],
+            input_defaults:   vec![],
+            audio_out_groups: vec![],
+            outputs:          vec![],
+            output_regs:      vec![],
+        }
+    }
+
+    fn init_regs(&mut self, _start_reg: usize, _regs: &mut [f32]) { }
+    fn output_reg(&self, _idx: usize) -> Option<usize> { None }
+    fn set_input_by_index(&mut self, _idx: usize, _to: OpIn, _as_default: bool) -> bool { false }
+    fn exec(&mut self, _ctx: &ExecContext, _regs: &mut [f32]) { }
+    fn does_render(&self) -> bool { true }
+
+    fn render(&mut self, num_samples: usize, offs: usize, input_idx: usize, bufs: &mut [Vec<f32>]) {
+        for i in 0..num_samples {
+            let s = (self.phase % 8) as f32 / 4.0 - 1.0;
+            bufs[input_idx][offs + i * 2]     += s;
+            bufs[input_idx][offs + i * 2 + 1] += s;
+            self.phase += 1;
+        }
+    }
+}
+
+fn no_step() -> Step { Box::new(|_, _| ()) }
+
+fn cases() -> Vec<Case> {
+    vec![
+        Case {
+            name:  "sin_basic",
+            ticks: 64,
+            audio: false,
+            build: || (sim_from_patch(&Patch {
+                groups: vec!["main".to_string()],
+                ops: vec![
+                    op("sin", "s", "main", &[
+                        ("freq", c(50.0)),
+                        ("amp",  c(0.5)),
+                        ("vert", c(0.25)),
+                    ]),
+                ],
+                delay_edges: vec![],
+            }), no_step()),
+        },
+        Case {
+            name:  "sin_modulated",
+            ticks: 64,
+            audio: false,
+            build: || (sim_from_patch(&Patch {
+                groups: vec!["main".to_string()],
+                ops: vec![
+                    op("sin", "car", "main", &[
+                        ("freq", NamedOpIn::new(OpIn::RegMulAdd(0, 20.0, 40.0), &["mod.out"])),
+                        ("phase", NamedOpIn::new(OpIn::Reg(0), &["mod.out"])),
+                    ]),
+                    op("sin", "mod", "main", &[("freq", c(10.0))]),
+                ],
+                delay_edges: vec![],
+            }), no_step()),
+        },
+        Case {
+            name:  "out_proxy",
+            ticks: 16,
+            audio: false,
+            build: || {
+                let sim = sim_from_patch(&Patch {
+                    groups: vec!["main".to_string()],
+                    ops: vec![
+                        PatchOp { type_args: vec![3], ..op("out_proxy", "p", "main", &[]) },
+                        op("sin", "s", "main", &[
+                            ("amp",  NamedOpIn::new(OpIn::Reg(0), &["p.out0"])),
+                            ("freq", NamedOpIn::new(OpIn::RegMul(0, 100.0), &["p.out2"])),
+                        ]),
+                    ],
+                    delay_edges: vec![],
+                });
+                let values = sim.get_op_as::<OutProxy>(0).expect("proxy").values.clone();
+
+                (sim, Box::new(move |_, tick| {
+                    let steps = [[0.0, 0.5, 1.0], [1.0, -0.5, 0.5], [0.25, 2.0, 0.0]];
+                    values.borrow_mut().copy_from_slice(&steps[(tick / 6) % steps.len()]);
+                }))
+            },
+        },
+        Case {
+            name:  "audio_send",
+            ticks: 16,
+            audio: true,
+            build: || {
+                let mut sim = sim_from_patch(&Patch {
+                    groups: vec!["src".to_string(), "master".to_string()],
+                    ops: vec![
+                        op("test_tone", "tone", "src", &[]),
+                        PatchOp { type_args: vec![1], ..op("audio_send", "send", "src", &[]) },
+                    ],
+                    delay_edges: vec![],
+                });
+                // Smoothed from the initial volume, like a change by the user.
+                sim.set_op_input(1, "vol_r", OpIn::Constant(0.5), false).unwrap();
+
+                (sim, Box::new(|sim, tick| {
+                    if tick == 8 {
+                        // Smoothed by the default smoothing of the port.
+                        sim.set_op_input(1, "vol_l", OpIn::Constant(0.0), false).unwrap();
+                    }
+                }))
+            },
+        },
+    ]
+}
+
+/// Runs a case and returns the column names and the rows of values.
+fn run_case(case: &Case) -> (Vec<String>, Vec<Vec<f32>>) {
+    let (mut sim, mut step) = (case.build)();
+    let mut scope = sim.new_scope_reader();
+
+    let mut columns : Vec<String> =
+        (0..sim.regs.len())
+            .map(|i| sim.reg_name(i).unwrap_or_else(|_| format!("reg{}", i)))
+            .collect();
+    let groups : Vec<OpGroup> = sim.op_groups.clone();
+    if case.audio {
+        for grp in groups.iter() {
+            for i in 0..BLOCK_SIZE {
+                columns.push(format!("{}.l{}", grp.name, i));
+                columns.push(format!("{}.r{}", grp.name, i));
+            }
+        }
+    }
+
+    let mut bufs = sim.new_group_sample_buffers(BLOCK_SIZE);
+    let mut rows = Vec::with_capacity(case.ticks);
+    for tick in 0..case.ticks {
+        step(&mut sim, tick);
+        sim.exec();
+
+        let mut row = scope.fetch().expect("exec publishes the registers").sample_row.clone();
+        if case.audio {
+            sim.render(BLOCK_SIZE, 0, &mut bufs);
+            for buf in bufs.iter() {
+                row.extend_from_slice(&buf[0..(BLOCK_SIZE * 2)]);
+            }
+        }
+        rows.push(row);
+    }
+
+    (columns, rows)
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests").join("golden").join(format!("{}.txt", name))
+}
+
+fn format_golden(columns: &[String], rows: &[Vec<f32>]) -> String {
+    let mut s = format!("# columns: {}\n", columns.join(" "));
+    for row in rows.iter() {
+        let vals : Vec<String> = row.iter().map(|v| format!("{}", v)).collect();
+        s += &vals.join(" ");
+        s += "\n";
+    }
+    s
+}
+
+fn parse_golden(text: &str) -> (Vec<String>, Vec<Vec<f32>>) {
+    let mut columns = Vec::new();
+    let mut rows    = Vec::new();
+    for line in text.lines() {
+        if let Some(cols) = line.strip_prefix("# columns:") {
+            columns = cols.split_whitespace().map(|c| c.to_string()).collect();
+        } else if !line.starts_with('#') && !line.trim().is_empty() {
+            rows.push(
+                line.split_whitespace()
+                    .map(|v| v.parse::<f32>().expect("number in golden file"))
+                    .collect());
+        }
+    }
+    (columns, rows)
+}
+
+fn check_case(case: &Case) {
+    let (columns, rows) = run_case(case);
+    let path = golden_path(case.name);
+
+    if std::env::var_os("GOLDEN_REGEN").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, format_golden(&columns, &rows)).unwrap();
+        return;
+    }
+
+    let text = std::fs::read_to_string(&path).unwrap_or_else(|e| panic!(
+        "Can't read golden file {:?} ({}), run with GOLDEN_REGEN=1 to create it",
+        path, e));
+    let (g_columns, g_rows) = parse_golden(&text);
+
+    assert_eq!(columns, g_columns, "columns of case '{}'", case.name);
+    assert_eq!(rows.len(), g_rows.len(), "ticks of case '{}'", case.name);
+    for (tick, (row, g_row)) in rows.iter().zip(g_rows.iter()).enumerate() {
+        assert_eq!(row.len(), g_row.len(), "values of case '{}' in tick {}", case.name, tick);
+        for ((v, g), col) in row.iter().zip(g_row.iter()).zip(columns.iter()) {
+            assert!((v - g).abs() <= TOLERANCE,
+                "case '{}', tick {}, column '{}': got {}, expected {} \
+                 (run with GOLDEN_REGEN=1 to accept the new output)",
+                case.name, tick, col, v, g);
+        }
+    }
+}
+
+fn check_named(name: &str) {
+    let case = cases().into_iter().find(|c| c.name == name).expect("known case");
+    check_case(&case);
+}
+
+#[test]
+fn golden_sin_basic() { check_named("sin_basic"); }
+
+#[test]
+fn golden_sin_modulated() { check_named("sin_modulated"); }
+
+#[test]
+fn golden_out_proxy() { check_named("out_proxy"); }
+
+#[test]
+fn golden_audio_send() { check_named("audio_send"); }
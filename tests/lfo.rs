@@ -0,0 +1,69 @@
+//! Tests of the LFO waveforms.
+
+use wctr_signal_ops::*;
+
+fn close(a: f32, b: f32) -> bool { (a - b).abs() <= 1e-5 * b.abs().max(1.0) }
+
+/// Executes an LFO of type `typ` at its default frequency of 1 Hz,
+/// four times per cycle, and returns its first `n` outputs. The first
+/// one is at phase 0.0.
+fn outputs(typ: &str, args: &[usize], inputs: &[(&str, f32)], n: usize) -> Vec<f32> {
+    let mut sim = Simulator::new();
+    sim.add_group("main");
+    sim.add_op(OpRegistry::new().create(typ, args).unwrap(), "lfo".to_string(), 0);
+    sim.set_rates(4.0, 44100.0);
+    for sm in sim.input_smoothing[0].iter_mut() { *sm = Smoothing::None; }
+    for (name, v) in inputs.iter() {
+        sim.set_op_input(0, name, OpIn::Constant(*v), false).unwrap();
+    }
+    let out = sim.resolve_reg("lfo.out").unwrap();
+
+    (0..n).map(|_| { sim.exec(); sim.get_reg(out) }).collect()
+}
+
+fn assert_outputs(typ: &str, inputs: &[(&str, f32)], expected: &[f32]) {
+    let out = outputs(typ, &[], inputs, expected.len());
+    assert!(out.iter().zip(expected.iter()).all(|(a, b)| close(*a, *b)),
+            "{}: {:?} != {:?}", typ, out, expected);
+}
+
+#[test]
+fn waves_start_at_phase_zero() {
+    assert_outputs("tri",      &[], &[0.0,  1.0,  0.0, -1.0, 0.0]);
+    assert_outputs("saw_up",   &[], &[0.0,  0.5, -1.0, -0.5, 0.0]);
+    assert_outputs("saw_down", &[], &[0.0, -0.5,  1.0,  0.5, 0.0]);
+    assert_outputs("square",   &[], &[1.0,  1.0, -1.0, -1.0, 1.0]);
+}
+
+#[test]
+fn rising_waves_follow_sin() {
+    let sin = outputs("sin", &[], &[("freq", 1.0)], 3);
+    assert!(close(sin[0], 0.0) && sin[1] > 0.99, "{:?}", sin);
+    assert_outputs("tri",    &[], &[0.0, 1.0]);
+    assert_outputs("saw_up", &[], &[0.0, 0.5]);
+}
+
+#[test]
+fn inputs_shape_the_wave() {
+    assert_outputs("square", &[("pw", 0.25)], &[1.0, -1.0, -1.0, -1.0, 1.0]);
+    assert_outputs("tri", &[("phase", std::f32::consts::FRAC_PI_2)],
+                   &[1.0, 0.0, -1.0, 0.0]);
+    // vert is added before amp.
+    assert_outputs("tri", &[("amp", 2.0), ("vert", 0.5)], &[1.0, 3.0, 1.0, -1.0]);
+}
+
+#[test]
+fn random_holds_a_value_per_cycle() {
+    let out = outputs("random", &[], &[], 9);
+    assert!(out.iter().all(|v| (-1.0..=1.0).contains(v)), "{:?}", out);
+    assert!(out[0..4].iter().all(|v| *v == out[0]), "{:?}", out);
+    assert!(out[4..8].iter().all(|v| *v == out[4]), "{:?}", out);
+    assert_ne!(out[0], out[4]);
+    assert_ne!(out[4], out[8]);
+
+    // The values are reproduced by the seed.
+    assert_eq!(outputs("random", &[42], &[], 9), outputs("random", &[42], &[], 9));
+    assert_ne!(outputs("random", &[42], &[], 9), out);
+    let op = OpRegistry::new().create("random", &[42]).unwrap();
+    assert_eq!(op.type_args(), vec![42]);
+}
@@ -0,0 +1,138 @@
+//! Tests of the register mappings of `OpIn`.
+
+use wctr_signal_ops::*;
+
+fn close(a: f32, b: f32) -> bool { (a - b).abs() <= 1e-5 * b.abs().max(1.0) }
+
+fn calc(op_in: OpIn, v: f32) -> f32 { op_in.calc(&[v]) }
+
+#[test]
+fn reg_lin_maps_a_to_a_and_b_to_b() {
+    let m = |v| calc(OpIn::RegLin(0, 0.0, 1.0, 100.0, 200.0, false), v);
+    assert_eq!(m(0.0), 100.0);
+    assert_eq!(m(1.0), 200.0);
+    assert_eq!(m(0.25), 125.0);
+    // Unclamped values extrapolate.
+    assert_eq!(m(2.0), 300.0);
+
+    // Reversed ranges on either side.
+    let r = |v| calc(OpIn::RegLin(0, 1.0, -1.0, 0.0, 10.0, false), v);
+    assert_eq!(r(1.0), 0.0);
+    assert_eq!(r(-1.0), 10.0);
+    let r = |v| calc(OpIn::RegLin(0, 0.0, 1.0, 10.0, 0.0, false), v);
+    assert_eq!(r(0.25), 7.5);
+}
+
+#[test]
+fn reg_lin_clamps_to_the_target_range() {
+    let m = |v| calc(OpIn::RegLin(0, 0.0, 1.0, 10.0, -10.0, true), v);
+    assert_eq!(m(-5.0), 10.0);
+    assert_eq!(m(5.0), -10.0);
+    assert_eq!(m(0.5), 0.0);
+
+    // An empty source range maps everything to `a_to`.
+    assert_eq!(calc(OpIn::RegLin(0, 1.0, 1.0, 3.0, 4.0, true), 7.0), 3.0);
+}
+
+#[test]
+fn reg_exp_covers_the_range_exponentially() {
+    let m = |v| calc(OpIn::RegExp(0, 0.0, 1.0, 20.0, 20000.0, false), v);
+    assert!(close(m(0.0), 20.0));
+    assert!(close(m(1.0), 20000.0));
+    // Equal steps give equal ratios.
+    assert!(close(m(1.0 / 3.0), 200.0));
+    assert!(close(m(2.0 / 3.0), 2000.0));
+
+    // Decreasing and negative target ranges.
+    let d = |v| calc(OpIn::RegExp(0, 0.0, 1.0, 1000.0, 10.0, false), v);
+    assert!(close(d(0.5), 100.0));
+    let n = |v| calc(OpIn::RegExp(0, 0.0, 1.0, -1.0, -100.0, false), v);
+    assert!(close(n(0.5), -10.0));
+}
+
+#[test]
+fn reg_exp_clamps() {
+    let m = |v| calc(OpIn::RegExp(0, -1.0, 1.0, 1.0, 16.0, true), v);
+    assert!(close(m(-3.0), 1.0));
+    assert!(close(m(3.0), 16.0));
+    assert!(close(m(0.0), 4.0));
+
+    // Without clamping the curve continues.
+    let u = |v| calc(OpIn::RegExp(0, 0.0, 1.0, 1.0, 16.0, false), v);
+    assert!(close(u(1.5), 64.0));
+}
+
+#[test]
+fn reg_log_is_the_inverse_of_reg_exp() {
+    let exp = OpIn::RegExp(0, 0.0, 1.0, 20.0, 20000.0, false);
+    let log = OpIn::RegLog(0, 20.0, 20000.0, 0.0, 1.0, false);
+    for x in [0.0, 0.1, 0.5, 0.9, 1.0].iter() {
+        assert!(close(calc(log, calc(exp, *x)), *x), "{}", x);
+    }
+    assert!(close(calc(log, 2000.0), 2.0 / 3.0));
+}
+
+#[test]
+fn reg_log_clamps() {
+    let m = |v| calc(OpIn::RegLog(0, 10.0, 1000.0, 0.0, 1.0, true), v);
+    assert_eq!(m(1.0), 0.0);
+    assert_eq!(m(1e6), 1.0);
+    assert!(close(m(100.0), 0.5));
+    // Values on the other side of zero are far below the range.
+    assert_eq!(m(-50.0), 0.0);
+    assert_eq!(m(0.0), 0.0);
+
+    let u = |v| calc(OpIn::RegLog(0, 10.0, 1000.0, 0.0, 1.0, false), v);
+    assert!(close(u(1.0), -0.5));
+}
+
+#[test]
+fn ranges_crossing_zero_map_linearly() {
+    // `a_to` and `b_to` of different signs or zero.
+    for (a, b) in [(-1.0, 1.0), (0.0, 10.0), (10.0, 0.0)].iter() {
+        let exp = OpIn::RegExp(0, 0.0, 1.0, *a, *b, false);
+        let lin = OpIn::RegLin(0, 0.0, 1.0, *a, *b, false);
+        for x in [0.0, 0.25, 0.5, 1.0].iter() {
+            assert!(close(calc(exp, *x), calc(lin, *x)), "{} {} {}", a, b, x);
+        }
+    }
+
+    for (a, b) in [(-1.0, 1.0), (0.0, 10.0)].iter() {
+        let log = OpIn::RegLog(0, *a, *b, 0.0, 1.0, true);
+        let lin = OpIn::RegLin(0, *a, *b, 0.0, 1.0, true);
+        for v in [-2.0, *a, 0.5, *b, 20.0].iter() {
+            assert!(close(calc(log, *v), calc(lin, *v)), "{} {} {}", a, b, v);
+        }
+    }
+}
+
+#[test]
+#[allow(deprecated)]
+fn reversed_legacy_mappings() {
+    assert_eq!(calc(OpIn::RegLerpRev(0, 10.0, 20.0), 0.0), 20.0);
+    assert_eq!(calc(OpIn::RegLerpRev(0, 10.0, 20.0), 1.0), 10.0);
+    assert_eq!(calc(OpIn::RegMapRev(0, 0.0, 1.0, 100.0, 200.0), 0.0), 200.0);
+    assert_eq!(calc(OpIn::RegMapRev(0, 0.0, 1.0, 100.0, 200.0), 1.0), 100.0);
+}
+
+#[test]
+#[allow(deprecated)]
+fn saved_legacy_mappings_load_as_the_reversed_variants() {
+    let lerp : OpIn = serde_json::from_str(r#"{"RegLerp":[3,10.0,20.0]}"#).unwrap();
+    assert_eq!(lerp, OpIn::RegLerpRev(3, 10.0, 20.0));
+    let map : OpIn = serde_json::from_str(r#"{"RegMap":[1,0.0,1.0,100.0,200.0]}"#).unwrap();
+    assert_eq!(map, OpIn::RegMapRev(1, 0.0, 1.0, 100.0, 200.0));
+
+    // They keep their names when saved again.
+    assert_eq!(serde_json::to_string(&lerp).unwrap(), r#"{"RegLerp":[3,10.0,20.0]}"#);
+    assert_eq!(serde_json::to_string(&map).unwrap(), r#"{"RegMap":[1,0.0,1.0,100.0,200.0]}"#);
+
+    // Also within a saved patch input.
+    let named : NamedOpIn =
+        serde_json::from_str(r#"{"op_in":{"RegMap":[0,0.0,1.0,5.0,6.0]},"regs":["lfo.out"]}"#)
+            .unwrap();
+    assert_eq!(named, NamedOpIn::new(OpIn::RegMapRev(0, 0.0, 1.0, 5.0, 6.0), &["lfo.out"]));
+
+    let lin : OpIn = serde_json::from_str(r#"{"RegLin":[0,0.0,1.0,5.0,6.0,true]}"#).unwrap();
+    assert_eq!(lin, OpIn::RegLin(0, 0.0, 1.0, 5.0, 6.0, true));
+}
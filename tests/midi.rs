@@ -0,0 +1,219 @@
+//! Tests of the MIDI byte stream and Standard MIDI File parsing.
+
+use wctr_signal_ops::*;
+use wctr_signal_ops::midi::parse_smf;
+
+fn parse_stream(bytes: &[u8]) -> Vec<EventKind> {
+    let mut kinds = Vec::new();
+    MidiParser::new().parse(bytes, |ev| kinds.push(ev.kind));
+    kinds
+}
+
+fn smf(format: u16, division: u16, tracks: &[&[u8]]) -> Vec<u8> {
+    let mut data = b"MThd".to_vec();
+    data.extend_from_slice(&6u32.to_be_bytes());
+    data.extend_from_slice(&format.to_be_bytes());
+    data.extend_from_slice(&(tracks.len() as u16).to_be_bytes());
+    data.extend_from_slice(&division.to_be_bytes());
+    for track in tracks.iter() {
+        data.extend_from_slice(b"MTrk");
+        data.extend_from_slice(&(track.len() as u32).to_be_bytes());
+        data.extend_from_slice(track);
+    }
+    data
+}
+
+fn assert_times(events: &[TimedEvent], expected: &[f64]) {
+    assert_eq!(events.len(), expected.len(), "{:?}", events);
+    for (ev, t) in events.iter().zip(expected.iter()) {
+        assert!((ev.time - t).abs() < 1e-9, "{:?}", events);
+    }
+}
+
+#[test]
+fn stream_channel_messages() {
+    let kinds = parse_stream(&[
+        0x91, 60, 127,
+        0x81, 60, 0,
+        0xB2, 7, 127,
+        0xD3, 127,
+        0xE0, 0x00, 0x40,
+        0xE0, 0x00, 0x00,
+    ]);
+    assert_eq!(kinds, vec![
+        EventKind::NoteOn { channel: 1, note: 60, velocity: 1.0 },
+        EventKind::NoteOff { channel: 1, note: 60, velocity: 0.0 },
+        EventKind::ControlChange { channel: 2, controller: 7, value: 1.0 },
+        EventKind::Aftertouch { channel: 3, value: 1.0 },
+        EventKind::PitchBend { channel: 0, value: 0.0 },
+        EventKind::PitchBend { channel: 0, value: -1.0 },
+    ]);
+}
+
+#[test]
+fn stream_running_status_and_zero_velocity() {
+    let kinds = parse_stream(&[0x90, 60, 127, 64, 127, 60, 0]);
+    assert_eq!(kinds, vec![
+        EventKind::NoteOn { channel: 0, note: 60, velocity: 1.0 },
+        EventKind::NoteOn { channel: 0, note: 64, velocity: 1.0 },
+        EventKind::NoteOff { channel: 0, note: 60, velocity: 0.0 },
+    ]);
+}
+
+#[test]
+fn stream_real_time_between_data_bytes() {
+    let kinds = parse_stream(&[0x90, 60, 0xF8, 127, 0xFA]);
+    assert_eq!(kinds, vec![
+        EventKind::Clock,
+        EventKind::NoteOn { channel: 0, note: 60, velocity: 1.0 },
+        EventKind::Start,
+    ]);
+}
+
+#[test]
+fn stream_skips_sysex_and_system_common() {
+    let kinds = parse_stream(&[
+        0x90, 60, 127,
+        0xF0, 0x7E, 0x01, 0x02, 0xF7,
+        // Data bytes after the sysex have no running status.
+        62, 127,
+        0xF2, 0x10, 0x20,
+        0x80, 60, 64,
+    ]);
+    assert_eq!(kinds.len(), 2);
+    assert_eq!(kinds[0], EventKind::NoteOn { channel: 0, note: 60, velocity: 1.0 });
+    match kinds[1] {
+        EventKind::NoteOff { channel: 0, note: 60, .. } => (),
+        k => panic!("unexpected {:?}", k),
+    }
+}
+
+#[test]
+fn stream_split_across_buffers() {
+    let mut parser = MidiParser::new();
+    let mut kinds  = Vec::new();
+    parser.parse(&[0xB0, 1], |ev| kinds.push(ev.kind));
+    assert!(kinds.is_empty());
+    parser.parse(&[0, 2, 127], |ev| kinds.push(ev.kind));
+    assert_eq!(kinds, vec![
+        EventKind::ControlChange { channel: 0, controller: 1, value: 0.0 },
+        EventKind::ControlChange { channel: 0, controller: 2, value: 1.0 },
+    ]);
+}
+
+#[test]
+fn smf_format_0_with_running_status_and_tempo() {
+    let track : &[u8] = &[
+        0x00, 0x90, 60, 100,
+        // Running status.
+        0x60, 60, 0,
+        // 250000 us per quarter note.
+        0x00, 0xFF, 0x51, 0x03, 0x03, 0xD0, 0x90,
+        0x60, 0x90, 64, 100,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let events = parse_smf(&smf(0, 96, &[track])).unwrap();
+    // One quarter note at 120 BPM, then one at 240 BPM.
+    assert_times(&events, &[0.0, 0.5, 0.75]);
+    assert_eq!(events[1].event.kind,
+               EventKind::NoteOff { channel: 0, note: 60, velocity: 0.0 });
+    match events[2].event.kind {
+        EventKind::NoteOn { channel: 0, note: 64, .. } => (),
+        k => panic!("unexpected {:?}", k),
+    }
+}
+
+#[test]
+fn smf_format_1_merges_tracks() {
+    let tempo : &[u8] = &[
+        0x00, 0xFF, 0x51, 0x03, 0x0F, 0x42, 0x40,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let notes : &[u8] = &[
+        0x00, 0x92, 48, 127,
+        0x81, 0x40, 0x82, 48, 0,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+    let events = parse_smf(&smf(1, 96, &[tempo, notes])).unwrap();
+    // 192 ticks at 96 ticks per quarter note and 60 BPM.
+    assert_times(&events, &[0.0, 2.0]);
+    assert!(events.iter().all(|e| e.track == 1));
+}
+
+#[test]
+fn smf_meta_and_sysex_cancel_running_status() {
+    let after_meta : &[u8] = &[
+        0x00, 0x90, 60, 100,
+        0x00, 0xFF, 0x01, 0x01, b'x',
+        0x00, 60, 0,
+    ];
+    assert_eq!(parse_smf(&smf(0, 96, &[after_meta])),
+               Err(MidiError::MissingStatus(32)));
+
+    let after_sysex : &[u8] = &[
+        0x00, 0x90, 60, 100,
+        0x00, 0xF0, 0x02, 0x7E, 0xF7,
+        0x00, 60, 0,
+    ];
+    assert_eq!(parse_smf(&smf(0, 96, &[after_sysex])),
+               Err(MidiError::MissingStatus(32)));
+}
+
+#[test]
+fn smf_smpte_division() {
+    // 25 frames per second with 40 ticks per frame.
+    let division = (((-25i8) as u8 as u16) << 8) | 40;
+    let track : &[u8] = &[
+        0x00, 0xFC,
+        0x87, 0x68, 0xFA,
+    ];
+    let events = parse_smf(&smf(0, division, &[track])).unwrap();
+    assert_times(&events, &[0.0, 1.0]);
+    assert_eq!(events[0].event.kind, EventKind::Stop);
+    assert_eq!(events[1].event.kind, EventKind::Start);
+}
+
+#[test]
+fn smf_errors() {
+    assert_eq!(parse_smf(b"RIFF0000"), Err(MidiError::NotAMidiFile));
+    assert_eq!(parse_smf(&smf(2, 96, &[])), Err(MidiError::UnsupportedFormat(2)));
+
+    // A header too short for format, track count and division.
+    let mut short_header = smf(0, 96, &[]);
+    short_header[7] = 4;
+    assert_eq!(parse_smf(&short_header), Err(MidiError::NotAMidiFile));
+    assert_eq!(parse_smf(&smf(0, 96, &[])[..10]), Err(MidiError::UnexpectedEnd));
+
+    let mut truncated = smf(0, 96, &[&[0x00, 0x90, 60, 100]]);
+    truncated.pop();
+    assert_eq!(parse_smf(&truncated), Err(MidiError::UnexpectedEnd));
+
+    assert_eq!(parse_smf(&smf(0, 96, &[&[0x00, 60, 100]])),
+               Err(MidiError::MissingStatus(23)));
+}
+
+#[test]
+fn sequence_schedules_block_by_block() {
+    let track : &[u8] = &[
+        0x00, 0x90, 60, 100,
+        0x60, 0xFC,
+    ];
+    let mut seq = MidiSequence::from_smf(&smf(0, 96, &[track])).unwrap();
+    assert!((seq.duration() - 0.5).abs() < 1e-9);
+
+    let mut sim = Simulator::new();
+    sim.add_group("main");
+
+    seq.schedule_block(&mut sim, 0, 100, 1000.0).unwrap();
+    assert_eq!(sim.scheduled_event_count(), 1);
+    assert!(!seq.is_finished());
+
+    seq.schedule_block(&mut sim, 0, 400, 1000.0).unwrap();
+    assert_eq!(sim.scheduled_event_count(), 1);
+    seq.schedule_block(&mut sim, 0, 100, 1000.0).unwrap();
+    assert_eq!(sim.scheduled_event_count(), 2);
+    assert!(seq.is_finished());
+
+    seq.rewind();
+    assert!(!seq.is_finished());
+}
@@ -0,0 +1,155 @@
+//! Tests of the modulation of op inputs by registers.
+
+use wctr_signal_ops::*;
+use wctr_signal_ops::ops::OutProxy;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const VERT : usize = 2;
+
+/// A `sin` at index 0 that outputs its `vert` input, and an `OutProxy`
+/// at index 1 with two outputs as modulation sources.
+fn setup() -> (Simulator, Rc<RefCell<Vec<f32>>>, usize, usize, usize) {
+    let registry = OpRegistry::new();
+    let mut sim = Simulator::new();
+    sim.add_group("main");
+    sim.add_op(registry.create("sin", &[]).unwrap(), "s".to_string(), 0);
+    // Inputs take their values at once.
+    for sm in sim.input_smoothing[0].iter_mut() { *sm = Smoothing::None; }
+    for (name, v) in [("amp", 1.0), ("phase", 0.0), ("freq", 0.0), ("vert", 0.25)].iter() {
+        sim.set_op_input(0, name, OpIn::Constant(*v), false).unwrap();
+    }
+
+    let proxy  = OutProxy::new(2);
+    let values = proxy.values.clone();
+    sim.add_op(Box::new(proxy), "src".to_string(), 0);
+
+    let out = sim.resolve_reg("s.out").unwrap();
+    let m0  = sim.resolve_reg("src.out0").unwrap();
+    let m1  = sim.resolve_reg("src.out1").unwrap();
+    (sim, values, out, m0, m1)
+}
+
+#[test]
+fn depth_scales_the_source() {
+    let (mut sim, values, out, m0, _) = setup();
+    values.borrow_mut()[0] = 2.0;
+
+    sim.set_op_modulation(0, "vert", m0, 0.5).unwrap();
+    sim.exec();
+    assert_eq!(sim.get_reg(out), 0.25 + 0.5 * 2.0);
+
+    // Setting the same source again changes its depth.
+    sim.set_op_modulation(0, "vert", m0, -1.0).unwrap();
+    sim.exec();
+    assert_eq!(sim.get_reg(out), 0.25 - 2.0);
+    assert_eq!(sim.get_modulations(0),
+               vec![Modulation { input: VERT, source: m0, depth: -1.0 }]);
+
+    sim.set_op_modulation(0, "vert", m0, 0.0).unwrap();
+    sim.exec();
+    assert_eq!(sim.get_reg(out), 0.25);
+}
+
+#[test]
+fn sources_add_up_and_the_base_value_is_kept() {
+    let (mut sim, values, out, m0, m1) = setup();
+    values.borrow_mut().copy_from_slice(&[1.0, 10.0]);
+
+    sim.set_op_modulation(0, "vert", m0, 0.5).unwrap();
+    sim.set_op_modulation_by_index(0, VERT, m1, 0.25).unwrap();
+    sim.exec();
+    assert_eq!(sim.get_reg(out), 0.25 + 0.5 + 2.5);
+    assert_eq!(sim.ops[0].input_value(VERT), Some(OpIn::Constant(0.25)));
+
+    // A new base value is still modulated.
+    sim.set_op_input(0, "vert", OpIn::Constant(-1.0), false).unwrap();
+    sim.exec();
+    assert_eq!(sim.get_reg(out), -1.0 + 0.5 + 2.5);
+
+    assert!(sim.remove_op_modulation(0, VERT, m0));
+    assert!(!sim.remove_op_modulation(0, VERT, m0));
+    sim.exec();
+    assert_eq!(sim.get_reg(out), -1.0 + 2.5);
+    assert_eq!(sim.get_modulations(0),
+               vec![Modulation { input: VERT, source: m1, depth: 0.25 }]);
+}
+
+#[test]
+fn modulation_sources_are_executed_first() {
+    let (mut sim, values, out, m0, _) = setup();
+    sim.exec();
+    assert_eq!(sim.exec_order, vec![0, 1]);
+
+    sim.set_op_modulation(0, "vert", m0, 1.0).unwrap();
+    values.borrow_mut()[0] = 3.0;
+    sim.exec();
+    assert_eq!(sim.exec_order, vec![1, 0]);
+    // Seen in the same tick, not one tick late.
+    assert_eq!(sim.get_reg(out), 3.25);
+
+    sim.remove_op_modulation(0, VERT, m0);
+    sim.exec();
+    assert_eq!(sim.exec_order, vec![0, 1]);
+}
+
+#[test]
+fn self_modulation_is_a_feedback_cycle_that_still_runs() {
+    let (mut sim, _, out, _, _) = setup();
+    sim.set_op_modulation(0, "vert", out, 1.0).unwrap();
+    sim.exec();
+    sim.exec();
+    // Each tick adds the previous output onto the base value.
+    assert_eq!(sim.get_reg(out), 0.5);
+}
+
+#[test]
+fn errors() {
+    let (mut sim, _, _, m0, _) = setup();
+    assert_eq!(sim.set_op_modulation(5, "vert", m0, 1.0),
+               Err(SimulatorError::OpIndexOutOfRange(5)));
+    assert_eq!(sim.set_op_modulation(0, "nope", m0, 1.0),
+               Err(SimulatorError::UnknownInput("s".to_string(), "nope".to_string())));
+    assert_eq!(sim.set_op_modulation_by_index(0, 9, m0, 1.0),
+               Err(SimulatorError::InputIndexOutOfRange("s".to_string(), 9)));
+    assert_eq!(sim.set_op_modulation(0, "vert", 99, 1.0),
+               Err(SimulatorError::RegOutOfRange(99)));
+    assert!(sim.get_modulations(0).is_empty());
+    assert!(!sim.remove_op_modulation(7, 0, m0));
+}
+
+#[test]
+fn listed_with_the_specs_and_saved_in_patches() {
+    let (mut sim, _, _, m0, m1) = setup();
+    sim.set_op_modulation(0, "vert", m1, 0.5).unwrap();
+    sim.set_op_modulation(0, "amp", m0, 0.25).unwrap();
+
+    let specs = sim.get_specs();
+    assert_eq!(specs[0].2, vec![
+        Modulation { input: 0,    source: m0, depth: 0.25 },
+        Modulation { input: VERT, source: m1, depth: 0.5 },
+    ]);
+    assert!(specs[1].2.is_empty());
+
+    let patch = sim.save_patch();
+    assert_eq!(patch.ops[0].modulations, vec![
+        ("amp".to_string(),  "src.out0".to_string(), 0.25),
+        ("vert".to_string(), "src.out1".to_string(), 0.5),
+    ]);
+
+    let mut registry = OpRegistry::new();
+    registry.register("out_proxy", "Proxy", |_| Box::new(OutProxy::new(2)));
+    let mut loaded = Simulator::new();
+    loaded.load_patch(&patch, &registry).unwrap();
+    assert_eq!(loaded.get_modulations(0), sim.get_modulations(0));
+}
+
+#[test]
+fn removing_the_source_op_removes_the_modulation() {
+    let (mut sim, _, _, m0, _) = setup();
+    sim.set_op_modulation(0, "vert", m0, 1.0).unwrap();
+    sim.remove_op(1);
+    assert!(sim.get_modulations(0).is_empty());
+    sim.exec();
+    assert_eq!(sim.exec_order, vec![0]);
+}
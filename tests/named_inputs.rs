@@ -0,0 +1,106 @@
+//! Tests of saving inputs with `Simulator::serialize_inputs` and loading
+//! them with `Simulator::deserialize_inputs`, which refer to registers
+//! by name, see `NamedOpIn`.
+
+use wctr_signal_ops::*;
+use wctr_signal_ops::ops::OutProxy;
+
+fn input(sim: &Simulator, op: &str, name: &str) -> Option<OpIn> {
+    let idx = sim.get_op_index(op).unwrap();
+    sim.ops[idx].input_value(sim.ops[idx].input_index(name).unwrap())
+}
+
+fn add_sin(sim: &mut Simulator, name: &str) {
+    sim.add_op(OpRegistry::new().create("sin", &[]).unwrap(), name.to_string(), 0);
+}
+
+/// The ops in the given order, where `b` reads from `a` and `x`.
+fn sim_with(order: &[&str]) -> Simulator {
+    let mut sim = Simulator::new();
+    sim.add_group("main");
+    for name in order.iter() {
+        match *name {
+            "a" => { sim.add_op(Box::new(OutProxy::new(2)), "a".to_string(), 0); },
+            _   => add_sin(&mut sim, name),
+        }
+    }
+    sim
+}
+
+fn connect(sim: &mut Simulator) {
+    let a1 = sim.resolve_reg("a.out1").unwrap();
+    let x  = sim.resolve_reg("x.out").unwrap();
+    let b  = sim.get_op_index("b").unwrap();
+    sim.set_op_input(b, "phase", OpIn::RegMix2(a1, x, 0.25), false).unwrap();
+    let op_in = sim.parse_expr("a.out0 * 2 + x.out").unwrap();
+    sim.set_op_input(b, "freq", op_in, false).unwrap();
+}
+
+#[test]
+fn inputs_survive_reordering_and_removing_ops() {
+    let mut sim = sim_with(&["x", "a", "b"]);
+    connect(&mut sim);
+    let saved = sim.serialize_inputs();
+
+    let json  = serde_json::to_string(&saved).unwrap();
+    let saved : SerializedInputs = serde_json::from_str(&json).unwrap();
+
+    // Loaded into the ops in another order, with another op in between.
+    let mut other = sim_with(&["b", "y", "a", "x"]);
+    assert_eq!(other.deserialize_inputs(&saved), Ok(()));
+    let (a0, a1, x) =
+        (other.resolve_reg("a.out0").unwrap(), other.resolve_reg("a.out1").unwrap(),
+         other.resolve_reg("x.out").unwrap());
+    assert_eq!(input(&other, "b", "phase"), Some(OpIn::RegMix2(a1, x, 0.25)));
+    match input(&other, "b", "freq") {
+        Some(OpIn::Expr(id)) =>
+            assert_eq!(other.expr(id).unwrap().as_str(),
+                       format!("((r{} * 2) + r{})", a0, x)),
+        v => panic!("not an expression: {:?}", v),
+    }
+
+    // Removing an op in front moves the registers, the loaded inputs
+    // follow them.
+    other.remove_op(other.get_op_index("y").unwrap());
+    assert_eq!(other.deserialize_inputs(&saved), Ok(()));
+    let (a1, x) = (other.resolve_reg("a.out1").unwrap(), other.resolve_reg("x.out").unwrap());
+    assert_eq!(input(&other, "b", "phase"), Some(OpIn::RegMix2(a1, x, 0.25)));
+    // Saved again, only the order of the ops differs.
+    let mut again = other.serialize_inputs();
+    let mut saved = saved;
+    again.sort_by(|a, b| a.0.cmp(&b.0));
+    saved.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(again, saved);
+}
+
+#[test]
+fn unresolvable_names_are_reported() {
+    let mut sim = sim_with(&["x", "a", "b"]);
+    connect(&mut sim);
+    let mut saved = sim.serialize_inputs();
+
+    // Without `x` the inputs of `b` that read it can't be loaded,
+    // the others still are.
+    let mut other = sim_with(&["a", "b"]);
+    saved.push(("b".to_string(), vec![
+        ("amp".to_string(),  NamedOpIn::new(OpIn::RegAdd(0, 1.0), &["a.out0"])),
+        ("vert".to_string(), NamedOpIn::new(OpIn::Reg(0), &["a.out7"])),
+        ("nope".to_string(), NamedOpIn::new(OpIn::Constant(1.0), &[])),
+        ("freq".to_string(), NamedOpIn::new(OpIn::Reg(1), &["a.out0"])),
+    ]));
+    saved.push(("gone".to_string(), vec![]));
+
+    assert_eq!(other.deserialize_inputs(&saved), Err(vec![
+        SimulatorError::UnknownOp("x".to_string()),
+        SimulatorError::UnknownOp("x".to_string()),
+        SimulatorError::UnknownOp("x".to_string()),
+        SimulatorError::UnknownOutput("a".to_string(), "out7".to_string()),
+        SimulatorError::UnknownInput("b".to_string(), "nope".to_string()),
+        SimulatorError::RegOutOfRange(1),
+        SimulatorError::UnknownOp("gone".to_string()),
+    ]));
+    let a0 = other.resolve_reg("a.out0").unwrap();
+    assert_eq!(input(&other, "b", "amp"), Some(OpIn::RegAdd(a0, 1.0)));
+    assert_eq!(sim.reg_name(99), Err(SimulatorError::RegOutOfRange(99)));
+    assert_eq!(sim.resolve_reg("a"), Err(SimulatorError::BadRegName("a".to_string())));
+}
@@ -0,0 +1,189 @@
+//! Checks that the audio thread side of the `Simulator` does not
+//! allocate, with an allocator that counts the allocations and
+//! deallocations of the current thread.
+
+use wctr_signal_ops::*;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCS: Cell<usize> = const { Cell::new(0) };
+    static FREES:  Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCS.try_with(|a| a.set(a.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let _ = FREES.try_with(|a| a.set(a.get() + 1));
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCS.try_with(|a| a.set(a.get() + 1));
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn allocs_during<F: FnOnce()>(f: F) -> usize {
+    let before = ALLOCS.with(|a| a.get());
+    f();
+    ALLOCS.with(|a| a.get()) - before
+}
+
+fn frees_during<F: FnOnce()>(f: F) -> usize {
+    let before = FREES.with(|a| a.get());
+    f();
+    FREES.with(|a| a.get()) - before
+}
+
+fn sim_with_sins(n: usize) -> Simulator {
+    let registry = OpRegistry::new();
+    let mut sim = Simulator::new();
+    sim.add_group("main");
+    for i in 0..n {
+        sim.add_op(registry.create("sin", &[]).unwrap(), format!("s{}", i), 0);
+    }
+    sim.exec();
+    sim
+}
+
+#[test]
+fn exec_reorders_without_allocating() {
+    let mut sim = sim_with_sins(4);
+    let s3 = sim.resolve_reg("s3.out").unwrap();
+    let s0 = sim.resolve_reg("s0.out").unwrap();
+
+    let n = allocs_during(|| {
+        sim.set_op_input(0, "freq", OpIn::RegMul(s3, 10.0), false).unwrap();
+        sim.exec();
+        sim.set_op_input(1, "amp", OpIn::RegMix2(s0, s3, 0.5), false).unwrap();
+        sim.exec();
+    });
+    assert_eq!(n, 0);
+    assert_eq!(sim.exec_order, vec![2, 3, 0, 1]);
+}
+
+#[test]
+fn exec_with_modulation_does_not_allocate() {
+    let mut sim = sim_with_sins(3);
+    let s2 = sim.resolve_reg("s2.out").unwrap();
+    sim.set_op_modulation(0, "vert", s2, 0.5).unwrap();
+    sim.set_input_clamping(true);
+    sim.exec();
+
+    let n = allocs_during(|| {
+        for _ in 0..10 { sim.exec(); }
+    });
+    assert_eq!(n, 0);
+}
+
+#[test]
+fn ramps_on_many_ops_do_not_allocate() {
+    let mut sim  = sim_with_sins(8);
+    let mut comm = SimulatorCommunicator::new();
+    let mut ep   = comm.get_endpoint();
+
+    // Both inputs of every op start a ramp in the same block.
+    for i in 0..8 {
+        comm.set_op_input(i, "amp", OpIn::Constant(0.5), false).unwrap();
+        comm.set_op_input(i, "vert", OpIn::Constant(0.25), false).unwrap();
+    }
+    let n = allocs_during(|| {
+        ep.handle_ui_messages(&mut sim).unwrap();
+        sim.exec();
+    });
+    assert_eq!(n, 0);
+}
+
+#[test]
+fn replaced_expressions_are_freed_on_the_ui_thread() {
+    let mut sim  = sim_with_sins(2);
+    let mut comm = SimulatorCommunicator::new();
+    let mut ep   = comm.get_endpoint();
+    let expr     = |src: &str| Expr::parse(src).unwrap();
+
+    comm.set_op_input_expr(0, "freq", expr("r1 * 2 + 1"), false).unwrap();
+    ep.handle_ui_messages(&mut sim).unwrap();
+    sim.exec();
+
+    // The later expressions reuse the slots of the replaced ones, which
+    // are handed back to the UI thread.
+    comm.set_op_input_expr(0, "freq", expr("r1 * 3 + 1"), false).unwrap();
+    comm.set_op_input_expr(0, "freq", expr("r1 * 4 + 1"), false).unwrap();
+    comm.set_op_input_expr(1, "freq", expr("r0 + 1"), false).unwrap();
+    comm.set_op_input_expr(1, "freq", expr("r0 + 2"), false).unwrap();
+    let mut allocs = 0;
+    let frees = frees_during(|| allocs = allocs_during(|| {
+        ep.handle_ui_messages(&mut sim).unwrap();
+        sim.exec();
+    }));
+    assert_eq!((allocs, frees), (0, 0));
+
+    let n = allocs_during(|| {
+        comm.set_op_input(1, "freq", OpIn::Constant(2.0), false).unwrap();
+        ep.handle_ui_messages(&mut sim).unwrap();
+        sim.exec();
+    });
+    assert_eq!(n, 0);
+
+    match sim.ops[0].input_value(3) {
+        Some(OpIn::Expr(id)) => assert_eq!(sim.expr(id), Some(&expr("r1 * 4 + 1"))),
+        v => panic!("not an expression: {:?}", v),
+    }
+}
+
+#[test]
+fn loading_inputs_does_not_allocate() {
+    let mut sim  = sim_with_sins(2);
+    let mut comm = SimulatorCommunicator::new();
+    let mut ep   = comm.get_endpoint();
+    // One message per call, so the reply to the `Refresh` that
+    // `load_input_values` sends for the specs is counted apart.
+    ep.set_message_budget(1);
+
+    comm.set_op_input_expr(1, "freq", Expr::parse("r0 + 1").unwrap(), false).unwrap();
+    ep.handle_ui_messages(&mut sim).unwrap();
+
+    let inputs = vec![
+        ("s1".to_string(), vec![
+            ("freq".to_string(), NamedOpIn::from_expr("r0 * 2", &["s0.out"])),
+            ("amp".to_string(),  NamedOpIn::new(OpIn::Constant(0.5), &[])),
+        ]),
+        ("s0".to_string(), vec![
+            ("phase".to_string(), NamedOpIn::new(OpIn::Reg(0), &["s1.out"])),
+        ]),
+    ];
+    let ui = std::thread::spawn(move || {
+        comm.load_input_values(&inputs).unwrap();
+        comm
+    });
+
+    let mut allocating_calls = 0;
+    while sim.ops[0].input_value(1) == Some(OpIn::Constant(0.0)) {
+        let mut allocs = 0;
+        let frees = frees_during(|| allocs = allocs_during(|| {
+            ep.handle_ui_messages(&mut sim).unwrap();
+            sim.exec();
+        }));
+        if allocs + frees > 0 { allocating_calls += 1; }
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+    let _comm = ui.join().unwrap();
+
+    // Only the reply with the specs allocated.
+    assert_eq!(allocating_calls, 1);
+    assert_eq!(sim.ops[1].input_value(0), Some(OpIn::Constant(0.5)));
+    match sim.ops[1].input_value(3) {
+        Some(OpIn::Expr(id)) => assert_eq!(sim.expr(id).unwrap().as_str(), "(r0 * 2)"),
+        v => panic!("not an expression: {:?}", v),
+    }
+}
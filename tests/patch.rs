@@ -0,0 +1,57 @@
+//! Tests of loading a `Patch` with `Simulator::load_patch`.
+
+use wctr_signal_ops::*;
+use wctr_signal_ops::ops::{ENV_MAX_SEGMENTS, OUT_PROXY_MAX_OUTPUTS};
+
+fn op(op_type: &str, name: &str, type_args: &[usize]) -> PatchOp {
+    PatchOp {
+        op_type:     op_type.to_string(),
+        type_args:   type_args.to_vec(),
+        name:        name.to_string(),
+        group:       "main".to_string(),
+        inputs:      vec![],
+        defaults:    vec![],
+        modulations: vec![],
+    }
+}
+
+fn type_args(sim: &Simulator, name: &str) -> Vec<usize> {
+    sim.ops[sim.get_op_index(name).unwrap()].type_args()
+}
+
+#[test]
+fn type_args_are_capped_and_reported() {
+    let patch = Patch {
+        groups: vec!["main".to_string()],
+        ops: vec![
+            op("env", "huge_env", &[1_000_000_000]),
+            op("out_proxy", "huge_proxy", &[usize::MAX]),
+            op("env", "empty_env", &[0]),
+            op("sin", "sin", &[3]),
+            op("env", "env", &[]),
+            op("random", "random", &[7]),
+        ],
+        delay_edges: vec![],
+    };
+
+    let mut sim = Simulator::new();
+    assert_eq!(sim.load_patch(&patch, &OpRegistry::new()), Err(vec![
+        SimulatorError::BadTypeArgs("huge_env".to_string(), vec![1_000_000_000]),
+        SimulatorError::BadTypeArgs("huge_proxy".to_string(), vec![usize::MAX]),
+        SimulatorError::BadTypeArgs("empty_env".to_string(), vec![0]),
+        SimulatorError::BadTypeArgs("sin".to_string(), vec![3]),
+    ]));
+
+    // The ops are still created, with the arguments they support.
+    assert_eq!(type_args(&sim, "huge_env"), vec![ENV_MAX_SEGMENTS]);
+    assert_eq!(type_args(&sim, "huge_proxy"), vec![OUT_PROXY_MAX_OUTPUTS]);
+    assert_eq!(type_args(&sim, "empty_env"), vec![1]);
+    assert_eq!(type_args(&sim, "env"), vec![4]);
+    assert_eq!(type_args(&sim, "random"), vec![7]);
+
+    // Saved again, the patch loads without errors.
+    let saved = sim.save_patch();
+    let mut again = Simulator::new();
+    assert_eq!(again.load_patch(&saved, &OpRegistry::new()), Ok(()));
+    assert_eq!(again.save_patch(), saved);
+}
@@ -0,0 +1,175 @@
+//! Tests of the value scaling of `OpPort` and of the input clamping
+//! of the `Simulator`.
+
+use wctr_signal_ops::*;
+use wctr_signal_ops::ops::OutProxy;
+
+fn close(a: f32, b: f32) -> bool { (a - b).abs() <= 1e-4 * b.abs().max(1.0) }
+
+#[test]
+fn lin_scale() {
+    let p = OpPort::new("x", -10.0, 30.0);
+    assert_eq!(p.normalize(-10.0), 0.0);
+    assert_eq!(p.normalize(30.0), 1.0);
+    assert_eq!(p.normalize(0.0), 0.25);
+    assert_eq!(p.normalize(-100.0), 0.0);
+    assert_eq!(p.normalize(100.0), 1.0);
+
+    assert_eq!(p.denormalize(0.0), -10.0);
+    assert_eq!(p.denormalize(1.0), 30.0);
+    assert_eq!(p.denormalize(0.25), 0.0);
+    assert_eq!(p.denormalize(-1.0), -10.0);
+    assert_eq!(p.denormalize(2.0), 30.0);
+
+    for v in [-10.0, -3.5, 12.0, 30.0].iter() {
+        assert!(close(p.denormalize(p.normalize(*v)), *v), "{}", v);
+    }
+}
+
+#[test]
+fn log_scale() {
+    let p = OpPort::new("freq", 20.0, 20000.0).with_scale(PortScale::Log);
+    assert_eq!(p.normalize(20.0), 0.0);
+    assert_eq!(p.normalize(20000.0), 1.0);
+    assert!(close(p.normalize(200.0), 1.0 / 3.0));
+    assert!(close(p.normalize(2000.0), 2.0 / 3.0));
+    assert_eq!(p.normalize(1.0), 0.0);
+    assert_eq!(p.normalize(-5.0), 0.0);
+    assert_eq!(p.normalize(1e6), 1.0);
+
+    assert!(close(p.denormalize(0.0), 20.0));
+    assert!(close(p.denormalize(1.0), 20000.0));
+    assert!(close(p.denormalize(0.5), 632.4555));
+    for v in [20.0, 55.0, 440.0, 12345.0].iter() {
+        assert!(close(p.denormalize(p.normalize(*v)), *v), "{}", v);
+    }
+}
+
+#[test]
+fn log_scale_crossing_zero_is_linear() {
+    let log = OpPort::new("x", 0.0, 10.0).with_scale(PortScale::Log);
+    let lin = OpPort::new("x", 0.0, 10.0);
+    for v in [0.0, 2.5, 10.0, 20.0].iter() {
+        assert_eq!(log.normalize(*v), lin.normalize(*v));
+    }
+    for x in [0.0, 0.3, 1.0].iter() {
+        assert!(close(log.denormalize(*x), lin.denormalize(*x)));
+    }
+}
+
+#[test]
+fn denormalize_quantizes() {
+    let p = OpPort::new("n", 1.0, 9.0).with_step(2.0);
+    assert_eq!(p.denormalize(0.0), 1.0);
+    assert_eq!(p.denormalize(0.3), 3.0);
+    assert_eq!(p.denormalize(0.6), 5.0);
+    assert_eq!(p.denormalize(1.0), 9.0);
+
+    let c = OpPort::new("wave", 5.0, 5.0).with_choices(&["a", "b", "c"]);
+    assert_eq!(c.denormalize(0.5), 1.0);
+    assert_eq!(c.denormalize(0.8), 2.0);
+    assert_eq!(c.format_value(1.0), "b");
+}
+
+#[test]
+fn db_scale() {
+    let p = OpPort::new("vol", 0.0, 1.0).with_scale(PortScale::Db);
+    assert_eq!(p.normalize(0.0), 0.0);
+    assert_eq!(p.normalize(1.0), 1.0);
+    // The floor is 60 dB below the maximum.
+    assert!(close(p.normalize(0.001), 0.0));
+    assert!(close(p.normalize(0.01), 1.0 / 3.0));
+    assert!(close(p.denormalize(0.5), 0.031622775));
+    assert_eq!(p.denormalize(0.0), 0.0);
+    assert_eq!(p.format_value(0.0), "-inf dB");
+    assert_eq!(p.format_value(0.1), "-20.0 dB");
+}
+
+#[test]
+fn normalized_reg_matches_denormalize() {
+    let ports = [
+        OpPort::new("lin", -1.0, 3.0),
+        OpPort::new("log", 20.0, 20000.0).with_scale(PortScale::Log),
+        OpPort::new("db",  0.0, 2.0).with_scale(PortScale::Db),
+    ];
+    for p in ports.iter() {
+        let op_in = p.normalized_reg(1);
+        for x in [0.1, 0.5, 0.75, 1.0].iter() {
+            assert!(close(op_in.calc(&[0.0, *x]), p.denormalize(*x)), "{} {}", p.name, x);
+        }
+        // Clamped to the range of the port.
+        assert!(close(op_in.calc(&[0.0, 2.0]), p.max), "{}", p.name);
+    }
+
+    // Db goes down to the floor instead of `min`.
+    let db = &ports[2];
+    assert!(close(db.normalized_reg(0).calc(&[0.0]), 0.002));
+    assert!(close(ports[0].normalized_reg(0).calc(&[-1.0]), -1.0));
+}
+
+/// A `sin` with `amp` 1.0 and `vert` 0.0, so it outputs `sin(phase)`,
+/// and an `OutProxy` as modulation source.
+fn sin_sim() -> (Simulator, std::rc::Rc<std::cell::RefCell<Vec<f32>>>, usize, usize) {
+    let registry = OpRegistry::new();
+    let mut sim = Simulator::new();
+    sim.add_group("main");
+    sim.add_op(registry.create("sin", &[]).unwrap(), "s".to_string(), 0);
+    for sm in sim.input_smoothing[0].iter_mut() { *sm = Smoothing::None; }
+    for (name, v) in [("amp", 1.0), ("phase", 0.0), ("freq", 0.0), ("vert", 0.0)].iter() {
+        sim.set_op_input(0, name, OpIn::Constant(*v), false).unwrap();
+    }
+
+    let proxy  = OutProxy::new(1);
+    let values = proxy.values.clone();
+    sim.add_op(Box::new(proxy), "src".to_string(), 0);
+    let out = sim.resolve_reg("s.out").unwrap();
+    let src = sim.resolve_reg("src.out0").unwrap();
+    (sim, values, out, src)
+}
+
+#[test]
+fn clamping_applies_after_the_modulation() {
+    let (mut sim, values, out, src) = sin_sim();
+    let two_pi = 2.0 * std::f32::consts::PI;
+    sim.set_op_input(0, "phase", OpIn::Constant(5.0), false).unwrap();
+    sim.set_op_modulation(0, "phase", src, 1.0).unwrap();
+    values.borrow_mut()[0] = 2.0;
+
+    sim.exec();
+    assert!(close(sim.get_reg(out), 7.0_f32.sin()));
+
+    sim.set_input_clamping(true);
+    sim.exec();
+    assert!(close(sim.get_reg(out), two_pi.sin()));
+    // The input keeps its base value.
+    assert_eq!(sim.ops[0].input_value(1), Some(OpIn::Constant(5.0)));
+
+    // Within the range nothing is clamped.
+    values.borrow_mut()[0] = -1.0;
+    sim.exec();
+    assert!(close(sim.get_reg(out), 4.0_f32.sin()));
+
+    // Below the range.
+    sim.set_op_modulation(0, "phase", src, 20.0).unwrap();
+    sim.exec();
+    assert!(close(sim.get_reg(out), (-two_pi).sin()));
+}
+
+#[test]
+fn clamping_applies_to_unmodulated_inputs() {
+    let (mut sim, _, out, _) = sin_sim();
+    sim.set_op_input(0, "phase", OpIn::Constant(std::f32::consts::FRAC_PI_2), false).unwrap();
+    sim.set_op_input(0, "amp", OpIn::Constant(-3.0), false).unwrap();
+
+    sim.exec();
+    assert!(close(sim.get_reg(out), -3.0));
+
+    sim.set_input_clamping(true);
+    sim.exec();
+    assert_eq!(sim.get_reg(out), 0.0);
+    assert_eq!(sim.ops[0].input_value(0), Some(OpIn::Constant(-3.0)));
+
+    sim.set_input_clamping(false);
+    sim.exec();
+    assert!(close(sim.get_reg(out), -3.0));
+}
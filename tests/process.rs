@@ -0,0 +1,140 @@
+//! Tests of the sample accurate event handling of `Simulator::process`.
+
+use wctr_signal_ops::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+type ExecLog = Rc<RefCell<Vec<(ExecContext, bool)>>>;
+
+/// Records the `ExecContext` of each `exec` and whether an event
+/// arrived since the previous one.
+struct Recorder {
+    log:   ExecLog,
+    event: bool,
+}
+
+impl Op for Recorder {
+    fn type_name(&self) -> &'static str { "recorder" }
+
+    fn io_spec(&self, index: usize) -> OpIOSpec {
+        OpIOSpec {
+            index,
+            inputs:           vec![],
+            input_values:     vec![],
+            input_defaults:   vec![],
+            audio_out_groups: vec![],
+            outputs:          vec![],
+            output_regs:      vec![],
+        }
+    }
+
+    fn init_regs(&mut self, _start_reg: usize, _regs: &mut [f32]) { }
+    fn output_reg(&self, _idx: usize) -> Option<usize> { None }
+    fn set_input_by_index(&mut self, _idx: usize, _to: OpIn, _as_default: bool) -> bool { false }
+    fn event(&mut self, _ev: &Event) { self.event = true; }
+
+    fn exec(&mut self, ctx: &ExecContext, _regs: &mut [f32]) {
+        self.log.borrow_mut().push((*ctx, self.event));
+        self.event = false;
+    }
+}
+
+fn recording_sim() -> (Simulator, ExecLog) {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let mut sim = Simulator::new();
+    sim.add_group("main");
+    sim.add_op(Box::new(Recorder { log: log.clone(), event: false }), "rec".to_string(), 0);
+    sim.set_rates(1000.0, 16000.0);
+    (sim, log)
+}
+
+#[test]
+fn events_restart_the_tick_grid() {
+    let (mut sim, log) = recording_sim();
+    let mut bufs = sim.new_group_sample_buffers(64);
+
+    sim.schedule_event(0, Event::at(10, EventKind::Start)).unwrap();
+    sim.process(64, 0, &mut bufs);
+
+    let log = log.borrow();
+    let samples : Vec<f32> =
+        log.iter().map(|(ctx, _)| (ctx.dt * ctx.sample_rate).round()).collect();
+    let events : Vec<bool> = log.iter().map(|(_, ev)| *ev).collect();
+    // The first tick has the regular length, there is no previous one.
+    assert_eq!(samples, vec![16.0, 10.0, 16.0, 16.0, 16.0]);
+    assert_eq!(events, vec![false, true, false, false, false]);
+
+    let times : Vec<f64> = log.iter().map(|(ctx, _)| ctx.time).collect();
+    for (t, expected) in times.iter().zip([0.0, 10.0, 26.0, 42.0, 58.0].iter()) {
+        assert!((t * 16000.0 - expected).abs() < 1e-3, "{:?}", times);
+    }
+}
+
+#[test]
+fn events_later_in_the_queue_move_to_the_next_block() {
+    let (mut sim, log) = recording_sim();
+    let mut bufs = sim.new_group_sample_buffers(32);
+
+    sim.schedule_event(0, Event::at(40, EventKind::Stop)).unwrap();
+    sim.process(32, 0, &mut bufs);
+    assert_eq!(sim.scheduled_event_count(), 1);
+    assert!(log.borrow().iter().all(|(_, ev)| !ev));
+
+    sim.process(32, 0, &mut bufs);
+    assert_eq!(sim.scheduled_event_count(), 0);
+    // Ticks at 0, 16, 32, then the event at 40 and the grid after it.
+    let samples : Vec<f32> =
+        log.borrow().iter().map(|(ctx, _)| (ctx.dt * ctx.sample_rate).round()).collect();
+    assert_eq!(samples, vec![16.0, 16.0, 16.0, 8.0, 16.0]);
+}
+
+#[test]
+fn scheduling_for_a_missing_group_fails() {
+    let (mut sim, _log) = recording_sim();
+
+    assert_eq!(sim.schedule_event(1, Event::at(0, EventKind::Start)),
+               Err(SimulatorError::GroupIndexOutOfRange(1)));
+    assert_eq!(sim.scheduled_event_count(), 0);
+}
+
+#[test]
+fn envelope_starts_at_the_event_sample() {
+    let registry = OpRegistry::new();
+    let mut sim = Simulator::new();
+    sim.add_group("main");
+    sim.add_op(registry.create("adsr", &[]).unwrap(), "env".to_string(), 0);
+    sim.set_op_input(0, "attack", OpIn::Constant(0.0), false).unwrap();
+    sim.set_op_input(0, "sustain", OpIn::Constant(1.0), false).unwrap();
+    sim.set_rates(1000.0, 16000.0);
+    let out = sim.resolve_reg("env.out").unwrap();
+    let mut bufs = sim.new_group_sample_buffers(16);
+
+    sim.process(8, 0, &mut bufs);
+    assert_eq!(sim.get_reg(out), 0.0);
+    sim.schedule_event(0, Event::at(3, EventKind::NoteOn { channel: 0, note: 60, velocity: 1.0 })).unwrap();
+    sim.process(4, 0, &mut bufs);
+    // Without the early exec the envelope would wait for the tick at 16.
+    assert_eq!(sim.get_reg(out), 1.0);
+}
+
+#[test]
+fn control_interp_ramps_across_short_render_calls() {
+    let mut ci = ControlInterp::new(1.0);
+    ci.set(0.0, 8);
+
+    let mut values : Vec<f32> = ci.ramp(1).collect();
+    values.extend(ci.ramp(3));
+    values.extend(ci.ramp(6));
+    let expected = [0.875, 0.75, 0.625, 0.5, 0.375, 0.25, 0.125, 0.0, 0.0, 0.0];
+    for (v, e) in values.iter().zip(expected.iter()) {
+        assert!((v - e).abs() < 1e-6, "{:?}", values);
+    }
+
+    // A new target continues from the current value.
+    ci.set(1.0, 4);
+    let mid : Vec<f32> = ci.ramp(2).collect();
+    ci.set(0.5, 2);
+    let rest : Vec<f32> = ci.ramp(3).collect();
+    assert_eq!(mid, vec![0.25, 0.5]);
+    assert_eq!(rest, vec![0.5, 0.5, 0.5]);
+}
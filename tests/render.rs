@@ -0,0 +1,95 @@
+//! Tests of the group buffer handling of `Simulator::render`.
+
+use wctr_signal_ops::*;
+use wctr_signal_ops::ops::AudioSend;
+
+/// Writes a constant 0.5 into its group.
+struct Dc;
+
+impl Op for Dc {
+    fn type_name(&self) -> &'static str { "dc" }
+
+    fn io_spec(&self, index: usize) -> OpIOSpec {
+        OpIOSpec {
+            index,
+            inputs:           vec![],
+            input_values:     vec![],
+            input_defaults:   vec![],
+            audio_out_groups: vec![],
+            outputs:          vec![],
+            output_regs:      vec![],
+        }
+    }
+
+    fn init_regs(&mut self, _start_reg: usize, _regs: &mut [f32]) { }
+    fn output_reg(&self, _idx: usize) -> Option<usize> { None }
+    fn set_input_by_index(&mut self, _idx: usize, _to: OpIn, _as_default: bool) -> bool { false }
+    fn exec(&mut self, _ctx: &ExecContext, _regs: &mut [f32]) { }
+    fn does_render(&self) -> bool { true }
+
+    fn render(&mut self, num_samples: usize, offs: usize, input_idx: usize, bufs: &mut [Vec<f32>]) {
+        for s in bufs[input_idx][offs..(offs + num_samples * 2)].iter_mut() {
+            *s += 0.5;
+        }
+    }
+}
+
+/// Renders a `Dc` in the group `src` that is sent into the group `dst`.
+fn render_send(src: usize, dst: usize) -> Vec<Vec<f32>> {
+    let mut sim = Simulator::new();
+    sim.add_group("a");
+    sim.add_group("b");
+    sim.add_op(Box::new(Dc), "dc".to_string(), src);
+    sim.add_op(Box::new(AudioSend::to_group(dst)), "send".to_string(), src);
+    sim.set_rates(1000.0, 4000.0);
+
+    let mut bufs = sim.new_group_sample_buffers(4);
+    for b in bufs.iter_mut() {
+        for s in b.iter_mut() { *s = 9.0; }
+    }
+    sim.exec();
+    sim.render(2, 2, &mut bufs);
+    bufs
+}
+
+#[test]
+fn send_into_a_group_rendered_later() {
+    let bufs = render_send(0, 1);
+    assert_eq!(bufs[0], vec![9.0, 9.0, 0.5, 0.5, 0.5, 0.5, 9.0, 9.0]);
+    // The send must not be cleared when its target group is rendered.
+    assert_eq!(bufs[1], vec![9.0, 9.0, 0.5, 0.5, 0.5, 0.5, 9.0, 9.0]);
+}
+
+#[test]
+fn send_into_a_group_rendered_earlier() {
+    let bufs = render_send(1, 0);
+    assert_eq!(bufs[0], vec![9.0, 9.0, 0.5, 0.5, 0.5, 0.5, 9.0, 9.0]);
+    assert_eq!(bufs[1], vec![9.0, 9.0, 0.5, 0.5, 0.5, 0.5, 9.0, 9.0]);
+}
+
+#[test]
+fn send_gain_is_the_square_of_the_volume() {
+    let mut sim = Simulator::new();
+    sim.add_group("a");
+    sim.add_group("b");
+    sim.add_op(Box::new(Dc), "dc".to_string(), 0);
+    sim.add_op(Box::new(AudioSend::to_group(1)), "send".to_string(), 0);
+    sim.set_rates(1000.0, 4000.0);
+    for sm in sim.input_smoothing[1].iter_mut() { *sm = Smoothing::None; }
+    sim.set_op_input(1, "vol_l", OpIn::Constant(0.5), false).unwrap();
+    sim.set_op_input(1, "vol_r", OpIn::Constant(0.1), false).unwrap();
+
+    let mut bufs = sim.new_group_sample_buffers(4);
+    // The first tick ramps from the initial volume.
+    for _ in 0..2 {
+        sim.exec();
+        sim.render(4, 0, &mut bufs);
+    }
+    for s in bufs[1].chunks(2) {
+        assert!((s[0] - 0.5 * 0.25).abs() < 1e-6, "{:?}", bufs[1]);
+        assert!((s[1] - 0.5 * 0.01).abs() < 1e-6, "{:?}", bufs[1]);
+    }
+
+    let spec = sim.ops[1].io_spec(1);
+    assert!(spec.inputs.iter().all(|p| p.scale != PortScale::Db));
+}
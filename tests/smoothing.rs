@@ -0,0 +1,104 @@
+//! Tests of the smoothing of constant input changes.
+
+use wctr_signal_ops::*;
+
+const VERT : usize = 2;
+
+fn close(a: f32, b: f32) -> bool { (a - b).abs() <= 1e-5 * b.abs().max(1.0) }
+
+/// A `sin` that outputs its `vert` input, executed 100 times per second,
+/// and the register of its output.
+fn setup() -> (Simulator, usize) {
+    let mut sim = Simulator::new();
+    sim.add_group("main");
+    sim.add_op(OpRegistry::new().create("sin", &[]).unwrap(), "s".to_string(), 0);
+    sim.set_rates(100.0, 44100.0);
+    for sm in sim.input_smoothing[0].iter_mut() { *sm = Smoothing::None; }
+    for (name, v) in [("amp", 1.0), ("phase", 0.0), ("freq", 0.0), ("vert", 0.0)].iter() {
+        sim.set_op_input(0, name, OpIn::Constant(*v), false).unwrap();
+    }
+    let out = sim.resolve_reg("s.out").unwrap();
+    (sim, out)
+}
+
+/// Executes `sim` once per expected value and checks the output.
+fn assert_outputs(sim: &mut Simulator, out: usize, expected: &[f32]) {
+    for (i, e) in expected.iter().enumerate() {
+        sim.exec();
+        assert!(close(sim.get_reg(out), *e), "tick {}: {} != {}", i, sim.get_reg(out), e);
+    }
+}
+
+#[test]
+fn ports_declare_a_default_smoothing() {
+    let mut sim = Simulator::new();
+    sim.add_group("main");
+    sim.add_op(OpRegistry::new().create("sin", &[]).unwrap(), "s".to_string(), 0);
+    assert_eq!(sim.input_smoothing[0][VERT], Smoothing::Linear(0.01));
+    assert_eq!(sim.input_smoothing[0][1],    Smoothing::None);
+}
+
+#[test]
+fn linear_smoothing_ramps_over_the_given_time() {
+    let (mut sim, out) = setup();
+    sim.set_input_smoothing(0, VERT, Smoothing::Linear(0.04)).unwrap();
+
+    sim.set_op_input(0, "vert", OpIn::Constant(1.0), false).unwrap();
+    // The ramp sets the input in the following exec calls.
+    assert_eq!(sim.ops[0].input_value(VERT), Some(OpIn::Constant(0.0)));
+    assert_outputs(&mut sim, out, &[0.25, 0.5, 0.75, 1.0, 1.0]);
+    assert_eq!(sim.ops[0].input_value(VERT), Some(OpIn::Constant(1.0)));
+}
+
+#[test]
+fn exponential_smoothing_ends_at_the_target() {
+    let (mut sim, out) = setup();
+    sim.set_input_smoothing(0, VERT, Smoothing::Exponential(0.03)).unwrap();
+
+    sim.set_op_input(0, "vert", OpIn::Constant(1.0), false).unwrap();
+    assert_outputs(&mut sim, out, &[0.9, 0.99, 1.0, 1.0]);
+}
+
+#[test]
+fn ramps_continue_from_their_current_value() {
+    let (mut sim, out) = setup();
+    sim.set_input_smoothing(0, VERT, Smoothing::Linear(0.04)).unwrap();
+
+    sim.set_op_input(0, "vert", OpIn::Constant(1.0), false).unwrap();
+    assert_outputs(&mut sim, out, &[0.25, 0.5]);
+    sim.set_op_input(0, "vert", OpIn::Constant(0.0), false).unwrap();
+    assert_outputs(&mut sim, out, &[0.375, 0.25, 0.125, 0.0, 0.0]);
+}
+
+#[test]
+fn unsmoothed_and_non_constant_inputs_are_set_at_once() {
+    let (mut sim, out) = setup();
+    sim.set_op_input(0, "vert", OpIn::Constant(1.0), false).unwrap();
+    assert_outputs(&mut sim, out, &[1.0]);
+
+    // A register replaces a running ramp.
+    sim.set_input_smoothing(0, VERT, Smoothing::Linear(0.04)).unwrap();
+    sim.set_op_input(0, "vert", OpIn::Constant(0.0), false).unwrap();
+    assert_outputs(&mut sim, out, &[0.75]);
+    sim.set_op_input(0, "vert", OpIn::Reg(out), false).unwrap();
+    assert_outputs(&mut sim, out, &[0.75, 0.75]);
+    assert_eq!(sim.ops[0].input_value(VERT), Some(OpIn::Reg(out)));
+
+    // There is nothing to ramp from after a register, and switching
+    // the smoothing off replaces a running ramp too.
+    sim.set_op_input(0, "vert", OpIn::Constant(0.5), false).unwrap();
+    sim.set_op_input(0, "vert", OpIn::Constant(1.0), false).unwrap();
+    assert_outputs(&mut sim, out, &[0.625]);
+    sim.set_input_smoothing(0, VERT, Smoothing::None).unwrap();
+    sim.set_op_input(0, "vert", OpIn::Constant(0.0), false).unwrap();
+    assert_outputs(&mut sim, out, &[0.0, 0.0]);
+}
+
+#[test]
+fn set_input_smoothing_reports_unknown_inputs() {
+    let (mut sim, _) = setup();
+    assert_eq!(sim.set_input_smoothing(1, VERT, Smoothing::None),
+               Err(SimulatorError::OpIndexOutOfRange(1)));
+    assert_eq!(sim.set_input_smoothing(0, 99, Smoothing::None),
+               Err(SimulatorError::InputIndexOutOfRange("s".to_string(), 99)));
+}
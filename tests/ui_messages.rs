@@ -0,0 +1,257 @@
+//! Tests of the messages between `SimulatorCommunicator` and
+//! `SimulatorCommunicatorEndpoint`.
+
+use wctr_signal_ops::*;
+
+fn setup() -> (Simulator, SimulatorCommunicator, SimulatorCommunicatorEndpoint) {
+    let mut sim = Simulator::new();
+    sim.add_group("main");
+    sim.add_op(OpRegistry::new().create("sin", &[]).unwrap(), "s".to_string(), 0);
+    let mut comm = SimulatorCommunicator::new();
+    let ep = comm.get_endpoint();
+    (sim, comm, ep)
+}
+
+fn phase(sim: &Simulator) -> Option<OpIn> {
+    sim.ops[0].input_value(sim.ops[0].input_index("phase").unwrap())
+}
+
+fn input(sim: &Simulator, op: usize, name: &str) -> Option<OpIn> {
+    sim.ops[op].input_value(sim.ops[op].input_index(name).unwrap())
+}
+
+#[test]
+fn coalescing_keeps_order_of_name_and_index_addressing() {
+    let (mut sim, mut comm, mut ep) = setup();
+    let phase_idx = sim.ops[0].input_index("phase").unwrap();
+
+    comm.set_op_input(0, "phase", OpIn::Constant(1.0), false).unwrap();
+    comm.set_op_input_idx(0, phase_idx, OpIn::Constant(2.0), false).unwrap();
+    comm.set_op_input(0, "phase", OpIn::Constant(3.0), false).unwrap();
+    ep.handle_ui_messages(&mut sim).unwrap();
+    assert_eq!(phase(&sim), Some(OpIn::Constant(3.0)));
+
+    comm.set_op_input_idx(0, phase_idx, OpIn::Constant(4.0), false).unwrap();
+    comm.set_op_input(0, "phase", OpIn::Constant(5.0), false).unwrap();
+    comm.set_op_input_idx(0, phase_idx, OpIn::Constant(6.0), false).unwrap();
+    ep.handle_ui_messages(&mut sim).unwrap();
+    assert_eq!(phase(&sim), Some(OpIn::Constant(6.0)));
+}
+
+#[test]
+fn coalescing_applies_the_last_value() {
+    let (mut sim, mut comm, mut ep) = setup();
+
+    for i in 0..10 {
+        comm.set_op_input(0, "phase", OpIn::Constant(i as f32), false).unwrap();
+    }
+    ep.handle_ui_messages(&mut sim).unwrap();
+    assert_eq!(phase(&sim), Some(OpIn::Constant(9.0)));
+}
+
+/// Runs `f` with `comm` on a UI thread, while this thread handles the
+/// messages like the audio thread.
+fn on_ui_thread<T, F>(sim: &mut Simulator, ep: &mut SimulatorCommunicatorEndpoint,
+                      mut comm: SimulatorCommunicator, f: F) -> (SimulatorCommunicator, T)
+    where T: Send + 'static,
+          F: FnOnce(&mut SimulatorCommunicator) -> T + Send + 'static {
+
+    let ui = std::thread::spawn(move || {
+        let ret = f(&mut comm);
+        (comm, ret)
+    });
+    while !ui.is_finished() {
+        ep.handle_ui_messages(sim).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+    let ret = ui.join().unwrap();
+    ep.handle_ui_messages(sim).unwrap();
+    ret
+}
+
+#[test]
+fn input_values_are_loaded_by_name() {
+    let (mut sim, comm, mut ep) = setup();
+    sim.add_op(OpRegistry::new().create("sin", &[]).unwrap(), "t".to_string(), 0);
+    let s_out = sim.resolve_reg("s.out").unwrap();
+
+    let inputs = vec![
+        ("t".to_string(), vec![
+            ("freq".to_string(),  NamedOpIn::from_expr("r0 * 2", &["s.out"])),
+            ("phase".to_string(), NamedOpIn::new(OpIn::RegAdd(0, 1.0), &["s.out"])),
+            ("nope".to_string(),  NamedOpIn::new(OpIn::Constant(1.0), &[])),
+        ]),
+        ("s".to_string(), vec![
+            ("phase".to_string(), NamedOpIn::new(OpIn::Reg(0), &["x.out"])),
+            ("freq".to_string(),  NamedOpIn::from_expr("r0 +", &["t.out"])),
+        ]),
+        ("x".to_string(), vec![]),
+    ];
+    let (comm, ()) =
+        on_ui_thread(&mut sim, &mut ep, comm,
+                     move |comm| comm.load_input_values(&inputs).unwrap());
+
+    let t = sim.get_op_index("t").unwrap();
+    match sim.ops[t].input_value(sim.ops[t].input_index("freq").unwrap()) {
+        Some(OpIn::Expr(id)) =>
+            assert_eq!(sim.expr(id).unwrap().as_str(), format!("(r{} * 2)", s_out)),
+        v => panic!("not an expression: {:?}", v),
+    }
+    assert_eq!(sim.ops[t].input_value(sim.ops[t].input_index("phase").unwrap()),
+               Some(OpIn::RegAdd(s_out, 1.0)));
+    assert_eq!(phase(&sim), Some(OpIn::Constant(0.0)));
+
+    // The inputs that could not be resolved are reported.
+    let (_, errors) =
+        on_ui_thread(&mut sim, &mut ep, comm, |comm| {
+            let mut errors = Vec::new();
+            comm.update(|ev| {
+                if let SimulatorUIEvent::Error(e) = ev { errors.push(e); }
+            }).unwrap();
+            errors
+        });
+    assert_eq!(errors, vec![
+        SimulatorError::UnknownInput("t".to_string(), "nope".to_string()),
+        SimulatorError::UnknownOp("x".to_string()),
+        SimulatorError::BadExpr(ExprError::Syntax(4, "unexpected end".to_string())),
+        SimulatorError::UnknownOp("x".to_string()),
+    ]);
+}
+
+#[test]
+fn events_round_trip_through_serde() {
+    let registry = OpRegistry::new();
+    let mut sim = Simulator::new();
+    sim.add_group("main");
+    sim.add_group("fx");
+    for (i, name) in registry.type_names().iter().enumerate() {
+        let op = registry.create(name, &[]).unwrap();
+        sim.add_op(op, format!("{}{}", name, i), i % 2);
+    }
+    let src = sim.resolve_reg("sin0.out").unwrap();
+    sim.set_op_modulation(0, "freq", src, 0.5).unwrap();
+    let expr_id = match sim.parse_expr("sin0.out * 2").unwrap() {
+        OpIn::Expr(id) => id,
+        op_in => panic!("{:?}", op_in),
+    };
+
+    let events = [
+        SimulatorUIEvent::OpSpecUpdate(sim.get_specs()),
+        SimulatorUIEvent::SerializedInputValues(sim.serialize_inputs()),
+        SimulatorUIEvent::Error(SimulatorError::UnknownInput("s".to_string(), "x".to_string())),
+        SimulatorUIEvent::Error(SimulatorError::BadExpr(ExprError::Syntax(3, "unexpected end".to_string()))),
+        SimulatorUIEvent::Error(sim.parse_expr("nope.out").unwrap_err()),
+        SimulatorUIEvent::Error(SimulatorError::UnknownExpr(expr_id)),
+    ];
+    for ev in events.iter() {
+        let json = serde_json::to_string(ev).unwrap();
+        let back : SimulatorUIEvent = serde_json::from_str(&json).unwrap();
+        assert_eq!(&back, ev, "{}", json);
+    }
+
+    match &events[0] {
+        SimulatorUIEvent::OpSpecUpdate(specs) => {
+            assert_eq!(specs.len(), registry.type_names().len());
+            assert_eq!(specs[1].1.group.name, "fx");
+            assert_eq!(specs[0].2.len(), 1);
+        },
+        ev => panic!("{:?}", ev),
+    }
+}
+
+#[test]
+fn errors_are_sent_back_to_the_ui() {
+    let (mut sim, mut comm, mut ep) = setup();
+
+    comm.set_op_input(0, "nope", OpIn::Constant(1.0), false).unwrap();
+    comm.set_op_input_idx(0, 99, OpIn::Constant(1.0), false).unwrap();
+    comm.set_op_input(5, "phase", OpIn::Constant(1.0), false).unwrap();
+    comm.set_op_input(0, "freq", OpIn::Reg(99), false).unwrap();
+    comm.set_op_input(0, "phase", OpIn::Constant(2.0), false).unwrap();
+    ep.handle_ui_messages(&mut sim).unwrap();
+    assert_eq!(phase(&sim), Some(OpIn::Constant(2.0)));
+
+    let (comm, errors) =
+        on_ui_thread(&mut sim, &mut ep, comm, |comm| {
+            let mut errors = Vec::new();
+            comm.update(|ev| {
+                if let SimulatorUIEvent::Error(e) = ev { errors.push(e); }
+            }).unwrap();
+            errors
+        });
+    assert_eq!(errors, vec![
+        SimulatorError::UnknownInput("s".to_string(), "nope".to_string()),
+        SimulatorError::InputIndexOutOfRange("s".to_string(), 99),
+        SimulatorError::OpIndexOutOfRange(5),
+        SimulatorError::RegOutOfRange(99),
+    ]);
+
+    drop(comm);
+    assert_eq!(ep.handle_ui_messages(&mut sim), Err(SimulatorError::Disconnected));
+}
+
+#[test]
+fn unfetched_events_fill_the_queue() {
+    let mut sim = Simulator::new();
+    sim.add_group("main");
+    sim.add_op(OpRegistry::new().create("sin", &[]).unwrap(), "s".to_string(), 0);
+    let mut comm = SimulatorCommunicator::with_queue_size(2);
+    let mut ep = comm.get_endpoint();
+
+    comm.set_op_input(0, "nope", OpIn::Constant(1.0), false).unwrap();
+    comm.set_op_input(0, "nada", OpIn::Constant(1.0), false).unwrap();
+    ep.handle_ui_messages(&mut sim).unwrap();
+
+    comm.set_op_input(0, "none", OpIn::Constant(1.0), false).unwrap();
+    assert_eq!(ep.handle_ui_messages(&mut sim), Err(SimulatorError::QueueFull));
+}
+
+#[test]
+fn message_budget_limits_the_messages_per_call() {
+    let (mut sim, mut comm, mut ep) = setup();
+    sim.add_op(OpRegistry::new().create("sin", &[]).unwrap(), "t".to_string(), 0);
+    let t = sim.get_op_index("t").unwrap();
+    ep.set_message_budget(2);
+
+    comm.set_op_input(0, "phase", OpIn::Constant(1.0), false).unwrap();
+    comm.set_op_input(0, "freq",  OpIn::Constant(2.0), false).unwrap();
+    comm.set_op_input(t, "phase", OpIn::Constant(3.0), false).unwrap();
+    comm.set_op_input(t, "freq",  OpIn::Constant(4.0), false).unwrap();
+    comm.set_op_input(0, "phase", OpIn::Constant(5.0), false).unwrap();
+
+    ep.handle_ui_messages(&mut sim).unwrap();
+    assert_eq!(input(&sim, 0, "phase"), Some(OpIn::Constant(1.0)));
+    assert_eq!(input(&sim, 0, "freq"),  Some(OpIn::Constant(2.0)));
+    assert_eq!(input(&sim, t, "phase"), Some(OpIn::Constant(0.0)));
+
+    ep.handle_ui_messages(&mut sim).unwrap();
+    assert_eq!(input(&sim, t, "phase"), Some(OpIn::Constant(3.0)));
+    assert_eq!(input(&sim, t, "freq"),  Some(OpIn::Constant(4.0)));
+    assert_eq!(input(&sim, 0, "phase"), Some(OpIn::Constant(1.0)));
+
+    ep.handle_ui_messages(&mut sim).unwrap();
+    assert_eq!(input(&sim, 0, "phase"), Some(OpIn::Constant(5.0)));
+}
+
+#[test]
+fn coalescing_is_limited_to_the_message_budget() {
+    let (mut sim, mut comm, mut ep) = setup();
+    ep.set_message_budget(2);
+
+    for i in 1..=3 {
+        comm.set_op_input(0, "phase", OpIn::Constant(i as f32), false).unwrap();
+    }
+    ep.handle_ui_messages(&mut sim).unwrap();
+    assert_eq!(phase(&sim), Some(OpIn::Constant(2.0)));
+    ep.handle_ui_messages(&mut sim).unwrap();
+    assert_eq!(phase(&sim), Some(OpIn::Constant(3.0)));
+
+    // A budget of 0 still handles one message per call.
+    ep.set_message_budget(0);
+    comm.set_op_input(0, "phase", OpIn::Constant(4.0), false).unwrap();
+    comm.set_op_input(0, "phase", OpIn::Constant(5.0), false).unwrap();
+    ep.handle_ui_messages(&mut sim).unwrap();
+    assert_eq!(phase(&sim), Some(OpIn::Constant(4.0)));
+    ep.handle_ui_messages(&mut sim).unwrap();
+    assert_eq!(phase(&sim), Some(OpIn::Constant(5.0)));
+}
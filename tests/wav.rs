@@ -0,0 +1,84 @@
+//! Tests of the WAV file writer.
+
+use wctr_signal_ops::*;
+
+fn u16_at(d: &[u8], pos: usize) -> u16 { u16::from_le_bytes([d[pos], d[pos + 1]]) }
+fn u32_at(d: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([d[pos], d[pos + 1], d[pos + 2], d[pos + 3]])
+}
+
+fn write(samples: &[f32], channels: u16, format: WavFormat) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_wav(&mut out, samples, channels, 48000, format).unwrap();
+    out
+}
+
+#[test]
+fn int16_header_and_clipping() {
+    let d = write(&[0.0, 1.0, -1.0, 2.0], 2, WavFormat::Int16);
+    assert_eq!(d.len(), 44 + 8);
+    assert_eq!(&d[0..4], b"RIFF");
+    assert_eq!(u32_at(&d, 4) as usize, d.len() - 8);
+    assert_eq!(&d[8..16], b"WAVEfmt ");
+    assert_eq!(u32_at(&d, 16), 16);
+    assert_eq!(u16_at(&d, 20), 1);
+    assert_eq!(u16_at(&d, 22), 2);
+    assert_eq!(u32_at(&d, 24), 48000);
+    assert_eq!(u32_at(&d, 28), 48000 * 4);
+    assert_eq!(u16_at(&d, 32), 4);
+    assert_eq!(u16_at(&d, 34), 16);
+    assert_eq!(&d[36..40], b"data");
+    assert_eq!(u32_at(&d, 40), 8);
+
+    let samples : Vec<i16> =
+        d[44..].chunks(2).map(|b| i16::from_le_bytes([b[0], b[1]])).collect();
+    assert_eq!(samples, vec![0, 32767, -32767, 32767]);
+}
+
+#[test]
+fn int24_odd_data_is_padded() {
+    let d = write(&[0.5], 1, WavFormat::Int24);
+    assert_eq!(u32_at(&d, 40), 3);
+    assert_eq!(d.len(), 44 + 3 + 1);
+    assert_eq!(u32_at(&d, 4) as usize, d.len() - 8);
+    assert_eq!(&d[44..47], &4_194_304i32.to_le_bytes()[0..3]);
+    assert_eq!(d[47], 0);
+}
+
+#[test]
+fn float32_has_fact_chunk() {
+    let samples = [0.25, -2.0, 0.5, 1.5, 0.0, 0.0];
+    let d = write(&samples, 2, WavFormat::Float32);
+    assert_eq!(u32_at(&d, 4) as usize, d.len() - 8);
+    assert_eq!(u32_at(&d, 16), 18);
+    assert_eq!(u16_at(&d, 20), 3);
+    assert_eq!(u16_at(&d, 34), 32);
+    assert_eq!(u16_at(&d, 36), 0);
+    assert_eq!(&d[38..42], b"fact");
+    assert_eq!(u32_at(&d, 42), 4);
+    assert_eq!(u32_at(&d, 46), 3);
+    assert_eq!(&d[50..54], b"data");
+    assert_eq!(u32_at(&d, 54), 24);
+
+    // Float samples are not clipped.
+    let written : Vec<f32> =
+        d[58..].chunks(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+    assert_eq!(written, samples.to_vec());
+}
+
+#[test]
+fn long_input_is_written_completely() {
+    let samples : Vec<f32> = (0..5000).map(|i| (i % 100) as f32 / 100.0).collect();
+    let d = write(&samples, 1, WavFormat::Int16);
+    assert_eq!(d.len(), 44 + 10000);
+    assert_eq!(i16::from_le_bytes([d[44 + 2 * 4999], d[45 + 2 * 4999]]),
+               (0.99f32 * 32767.0).round() as i16);
+}
+
+#[test]
+fn zero_channels_are_rejected() {
+    let mut out = Vec::new();
+    let err = write_wav(&mut out, &[0.0], 0, 48000, WavFormat::Int16).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    assert!(out.is_empty());
+}